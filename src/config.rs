@@ -1,6 +1,24 @@
+use crate::constants::{
+    DEFAULT_DATA_TIMEOUT, DEFAULT_IDLE_TIMEOUT, DEFAULT_PASV_ACCEPT_TIMEOUT,
+    DEFAULT_IDENT_TIMEOUT, DEFAULT_MIN_PASSWORD_LENGTH, DEFAULT_OWNER_FILE,
+    DEFAULT_PASSWORD_STATE_FILE, DEFAULT_SHUTDOWN_GRACE_PERIOD,
+};
+use crate::constants::{
+    DEFAULT_ANONYMOUS_FULL_MESSAGE, DEFAULT_BANNER_PATH, DEFAULT_BAN_DURATION, DEFAULT_BAN_FILE,
+    DEFAULT_DIR_QUOTA_FILE, DEFAULT_FAILURE_WINDOW, DEFAULT_GRACE_PERIOD, DEFAULT_GROUPS_FILE,
+    DEFAULT_IP_LIMIT_MESSAGE, DEFAULT_MAX_LOGIN_FAILURES, DEFAULT_SITE_FULL_MESSAGE,
+    DEFAULT_TARPIT_DELAY, DEFAULT_TARPIT_MAX_DELAY,
+};
+use crate::core_auth::acl::{AclRule, PathPermission};
+use crate::core_auth::hash::HashAlgorithm;
+use crate::core_quota::dir_quota::DirQuota;
+use crate::core_quota::ratio::UserRatio;
 use crate::core_tls::tls_config::TlsConfig;
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerConfig {
@@ -12,6 +30,11 @@ pub struct ServerConfig {
     pub upload_buffer_size: Option<usize>, // Optional to allow default value
     pub download_buffer_size: Option<usize>, // Optional to allow default value
     pub passwd_file: String,               // Path to the shadow (passwd) file
+    pub idle_timeout: Option<u64>,         // Control connection idle timeout, in seconds
+    pub pasv_accept_timeout: Option<u64>,  // Time allowed to open a PASV data connection, in seconds
+    pub data_timeout: Option<u64>,         // Time a data transfer may stall, in seconds
+    pub shutdown_grace_period: Option<u64>, // Time running transfers get to finish on shutdown, in seconds
+    pub banner_file: Option<String>,       // Banner sent to new connections
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -36,6 +59,39 @@ pub struct QuotaConfig {
 
     /// Activation du système de ratio
     pub enable_ratio: Option<bool>,
+
+    /// Garder la partie reçue d'un upload interrompu par le quota (supprimée
+    /// par défaut)
+    pub keep_partial_uploads: Option<bool>,
+
+    /// Quota souple par défaut (en octets, 0 = aucun)
+    pub default_soft_quota: Option<u64>,
+
+    /// Délai de grâce après un dépassement du quota souple (en secondes)
+    pub grace_period: Option<u64>,
+
+    /// Quota de chaque membre par groupe principal (en octets, 0 = illimité),
+    /// pour les groupes sans quota dans groups.json
+    #[serde(default)]
+    pub group_quotas: HashMap<String, u64>,
+
+    /// Ratio de chaque membre par groupe principal (format "upload:download"),
+    /// pour les groupes absents de groups.json
+    #[serde(default)]
+    pub group_ratios: HashMap<String, String>,
+
+    /// Espace total utilisable par les membres d'un groupe principal
+    /// (en octets, 0 = illimité)
+    #[serde(default)]
+    pub group_total_quotas: HashMap<String, u64>,
+
+    /// Fichier des quotas de répertoire posés par SITE DIRQUOTA
+    pub dir_quota_storage_file: Option<PathBuf>,
+
+    /// Quotas de répertoire : taille et nombre de fichiers maximum d'une
+    /// arborescence du site, quel que soit l'uploader
+    #[serde(default)]
+    pub dir_quotas: Vec<DirQuota>,
 }
 
 impl Default for QuotaConfig {
@@ -48,8 +104,387 @@ impl Default for QuotaConfig {
             stats_storage_file: Some(PathBuf::from("data/transfer_stats.json")),
             enable_quota: Some(true),
             enable_ratio: Some(true),
+            keep_partial_uploads: Some(false),
+            default_soft_quota: Some(0),
+            grace_period: Some(DEFAULT_GRACE_PERIOD),
+            group_quotas: HashMap::new(),
+            group_ratios: HashMap::new(),
+            group_total_quotas: HashMap::new(),
+            dir_quota_storage_file: Some(PathBuf::from(DEFAULT_DIR_QUOTA_FILE)),
+            dir_quotas: Vec::new(),
+        }
+    }
+}
+
+/// Connection limits, all optional: a missing limit means unlimited.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct LimitsConfig {
+    /// Maximum number of logged in users (EXEMPT users may still log in)
+    pub max_users: Option<usize>,
+
+    /// Maximum number of connections from a single IP address
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Maximum number of logged in anonymous users
+    pub max_anonymous_users: Option<usize>,
+
+    /// Message sent with the 421 reply when max_users is reached
+    pub site_full_message: Option<String>,
+
+    /// Message sent with the 421 reply when max_connections_per_ip is reached
+    pub ip_limit_message: Option<String>,
+
+    /// Message sent with the 421 reply when max_anonymous_users is reached
+    pub anonymous_full_message: Option<String>,
+}
+
+impl LimitsConfig {
+    pub fn site_full_message(&self) -> &str {
+        self.site_full_message
+            .as_deref()
+            .unwrap_or(DEFAULT_SITE_FULL_MESSAGE)
+    }
+
+    pub fn ip_limit_message(&self) -> &str {
+        self.ip_limit_message
+            .as_deref()
+            .unwrap_or(DEFAULT_IP_LIMIT_MESSAGE)
+    }
+
+    pub fn anonymous_full_message(&self) -> &str {
+        self.anonymous_full_message
+            .as_deref()
+            .unwrap_or(DEFAULT_ANONYMOUS_FULL_MESSAGE)
+    }
+}
+
+/// A pair of transfer speed limits, in KB/s. 0 or a missing value means unlimited.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SpeedLimit {
+    pub max_download_speed: Option<u64>,
+    pub max_upload_speed: Option<u64>,
+}
+
+impl SpeedLimit {
+    /// Returns the limit for one direction, `None` when unlimited.
+    pub fn for_direction(&self, upload: bool) -> Option<u64> {
+        let limit = if upload {
+            self.max_upload_speed
+        } else {
+            self.max_download_speed
+        };
+        limit.filter(|speed| *speed > 0)
+    }
+}
+
+/// Speed limit for the transfers inside a section of the site.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SectionSpeedLimit {
+    /// Section path, relative to the site root (e.g. "/mp3")
+    pub path: PathBuf,
+
+    #[serde(flatten)]
+    pub limit: SpeedLimit,
+}
+
+/// Bandwidth throttling.
+///
+/// The top-level limits cap the aggregate bandwidth of the whole server, the
+/// group and section limits apply to each transfer.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ThrottleConfig {
+    #[serde(flatten)]
+    pub global: SpeedLimit,
+
+    #[serde(default)]
+    pub groups: HashMap<String, SpeedLimit>,
+
+    #[serde(default)]
+    pub sections: Vec<SectionSpeedLimit>,
+}
+
+/// Failed login tarpitting and temporary IP bans.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BanConfig {
+    /// Failed logins from one IP within the window before it is banned, 0 = never ban
+    pub max_failures: Option<u32>,
+
+    /// Window in which failed logins are counted, in seconds
+    pub failure_window: Option<u64>,
+
+    /// Duration of a ban, in seconds
+    pub ban_duration: Option<u64>,
+
+    /// Reply delay after the first failed login, in milliseconds; doubled for each further failure
+    pub tarpit_delay: Option<u64>,
+
+    /// Upper bound of the reply delay, in milliseconds
+    pub tarpit_max_delay: Option<u64>,
+
+    /// File the bans are kept in across restarts
+    pub ban_file: Option<PathBuf>,
+}
+
+impl BanConfig {
+    pub fn max_failures(&self) -> u32 {
+        self.max_failures.unwrap_or(DEFAULT_MAX_LOGIN_FAILURES)
+    }
+
+    pub fn failure_window(&self) -> Duration {
+        Duration::from_secs(self.failure_window.unwrap_or(DEFAULT_FAILURE_WINDOW))
+    }
+
+    pub fn ban_duration(&self) -> Duration {
+        Duration::from_secs(self.ban_duration.unwrap_or(DEFAULT_BAN_DURATION))
+    }
+
+    pub fn ban_file(&self) -> PathBuf {
+        self.ban_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_BAN_FILE))
+    }
+
+    /// Returns the reply delay after `failures` failed logins.
+    pub fn tarpit_delay(&self, failures: u32) -> Duration {
+        let base = self.tarpit_delay.unwrap_or(DEFAULT_TARPIT_DELAY);
+        let max = self.tarpit_max_delay.unwrap_or(DEFAULT_TARPIT_MAX_DELAY);
+        let exponent = failures.saturating_sub(1).min(31);
+        Duration::from_millis(base.saturating_mul(1 << exponent).min(max))
+    }
+}
+
+/// An authentication backend of the `[auth]` chain.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthBackendConfig {
+    /// A passwd file read on every login, `server.passwd_file` by default
+    Passwd { path: Option<String> },
+
+    /// A passwd file kept in memory and re-read when it changes
+    Cached { path: Option<String> },
+
+    /// A program answering on stdin/stdout, see `ExternalBackend`
+    External {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        timeout: Option<u64>, // seconds
+    },
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AuthConfig {
+    /// Backends asked in order, the cached `server.passwd_file` if empty
+    #[serde(default)]
+    pub backends: Vec<AuthBackendConfig>,
+
+    /// Let users without any IP line in their userfile log in from anywhere
+    pub allow_users_without_ip: Option<bool>,
+
+    /// Query the identd of clients (RFC 1413) when they connect
+    pub ident_lookup: Option<bool>,
+
+    /// Time allowed for the ident reply, in seconds
+    pub ident_timeout: Option<u64>,
+
+    /// Hash algorithm for new passwords and rehashed ones
+    pub preferred_hash: Option<HashAlgorithm>,
+
+    /// Rehash passwords stored with another algorithm than `preferred_hash` on login
+    pub rehash_on_login: Option<bool>,
+}
+
+impl AuthConfig {
+    pub fn backends(&self) -> Vec<AuthBackendConfig> {
+        if self.backends.is_empty() {
+            vec![AuthBackendConfig::Cached { path: None }]
+        } else {
+            self.backends.clone()
+        }
+    }
+
+    pub fn allow_users_without_ip(&self) -> bool {
+        self.allow_users_without_ip.unwrap_or(false)
+    }
+
+    pub fn ident_lookup(&self) -> bool {
+        self.ident_lookup.unwrap_or(true)
+    }
+
+    pub fn ident_timeout(&self) -> Duration {
+        Duration::from_secs(self.ident_timeout.unwrap_or(DEFAULT_IDENT_TIMEOUT))
+    }
+
+    pub fn preferred_hash(&self) -> HashAlgorithm {
+        self.preferred_hash.unwrap_or(HashAlgorithm::Bcrypt)
+    }
+
+    pub fn rehash_on_login(&self) -> bool {
+        self.rehash_on_login.unwrap_or(false)
+    }
+}
+
+/// Rules for passwords set with SITE PASSWD and SITE CHPASS.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PasswordPolicyConfig {
+    pub min_length: Option<usize>,
+    pub require_lowercase: Option<bool>,
+    pub require_uppercase: Option<bool>,
+    pub require_digit: Option<bool>,
+    pub require_special: Option<bool>,
+
+    /// Number of previous passwords that may not be reused, 0 = no history
+    pub history: Option<usize>,
+
+    /// Make users change a password set by a siteop on their next login
+    pub force_change_on_chpass: Option<bool>,
+
+    /// File keeping the password history and the pending forced changes
+    pub state_file: Option<PathBuf>,
+}
+
+impl PasswordPolicyConfig {
+    pub fn min_length(&self) -> usize {
+        self.min_length.unwrap_or(DEFAULT_MIN_PASSWORD_LENGTH)
+    }
+
+    pub fn history(&self) -> usize {
+        self.history.unwrap_or(0)
+    }
+
+    pub fn force_change_on_chpass(&self) -> bool {
+        self.force_change_on_chpass.unwrap_or(false)
+    }
+
+    pub fn state_file(&self) -> PathBuf {
+        self.state_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_PASSWORD_STATE_FILE))
+    }
+}
+
+/// Anonymous access ("anonymous" or "ftp" with an e-mail address as password).
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AnonymousConfig {
+    pub enabled: Option<bool>,
+
+    /// Root of the anonymous users, relative to chroot_dir (default min_homedir)
+    pub root: Option<String>,
+
+    /// Refuse DELE, RMD, MKD, RNFR, RNTO and uploads outside incoming_dir
+    pub read_only: Option<bool>,
+
+    /// Upload-only directory, relative to root: files may be sent but not
+    /// listed, downloaded or overwritten
+    pub incoming_dir: Option<String>,
+
+    /// Only accept an e-mail address as password
+    pub require_email_password: Option<bool>,
+
+    /// Maximum number of logged in anonymous users, overrides
+    /// limits.max_anonymous_users
+    pub max_users: Option<usize>,
+}
+
+impl AnonymousConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.unwrap_or(true)
+    }
+
+    pub fn require_email_password(&self) -> bool {
+        self.require_email_password.unwrap_or(true)
+    }
+
+    /// The incoming directory as a site path, e.g. `/incoming`.
+    pub fn incoming_dir(&self) -> Option<PathBuf> {
+        self.incoming_dir
+            .as_deref()
+            .map(|dir| PathBuf::from("/").join(dir.trim_matches('/')))
+    }
+}
+
+/// glftpd style path permissions.
+///
+/// Each rule is a line `"<path> <acl>"`: a glob on the site path (`*` and `?`)
+/// followed by an ACL of users (`-name`), groups (`=name`), flags (`1A`) and
+/// `*`, any of them negated with `!`. The first rule matching the path decides,
+/// paths without a matching rule are open to everyone.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AclConfig {
+    #[serde(default)]
+    pub upload: Vec<String>,
+    #[serde(default)]
+    pub download: Vec<String>,
+    #[serde(default)]
+    pub makedir: Vec<String>,
+    #[serde(default)]
+    pub delete: Vec<String>,
+
+    /// Lets the uploader delete a file the delete rules refuse
+    #[serde(default)]
+    pub deleteown: Vec<String>,
+    #[serde(default)]
+    pub rename: Vec<String>,
+
+    /// Lets the uploader rename a file the rename rules refuse
+    #[serde(default)]
+    pub renameown: Vec<String>,
+    #[serde(default)]
+    pub nuke: Vec<String>,
+
+    /// Hides the sessions inside the path from SITE WHO for the users listed
+    #[serde(default)]
+    pub hideinwho: Vec<String>,
+
+    /// Only the users listed may enter, list or see the path and what is below
+    #[serde(default)]
+    pub privpath: Vec<String>,
+
+    /// Who uploaded or created each file, for deleteown and renameown
+    pub owner_file: Option<PathBuf>,
+}
+
+impl AclConfig {
+    /// Returns the rule lines of one permission.
+    pub fn rules(&self, permission: PathPermission) -> &[String] {
+        match permission {
+            PathPermission::Upload => &self.upload,
+            PathPermission::Download => &self.download,
+            PathPermission::MakeDir => &self.makedir,
+            PathPermission::Delete => &self.delete,
+            PathPermission::DeleteOwn => &self.deleteown,
+            PathPermission::Rename => &self.rename,
+            PathPermission::RenameOwn => &self.renameown,
+            PathPermission::Nuke => &self.nuke,
+            PathPermission::HideInWho => &self.hideinwho,
+            PathPermission::PrivPath => &self.privpath,
         }
     }
+
+    pub fn owner_file(&self) -> PathBuf {
+        self.owner_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_OWNER_FILE))
+    }
+}
+
+/// The groups of the site and what their members may do.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GroupsConfig {
+    /// Groups and their permissions, created with the default groups when missing
+    pub storage_file: Option<PathBuf>,
+}
+
+impl GroupsConfig {
+    pub fn storage_file(&self) -> PathBuf {
+        self.storage_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_GROUPS_FILE))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -57,6 +492,21 @@ pub struct Config {
     pub server: ServerConfig,
     pub quota: Option<QuotaConfig>,
     pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    pub throttle: Option<ThrottleConfig>,
+    #[serde(default)]
+    pub bans: BanConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub password_policy: PasswordPolicyConfig,
+    #[serde(default)]
+    pub anonymous: AnonymousConfig,
+    #[serde(default)]
+    pub acl: AclConfig,
+    #[serde(default)]
+    pub groups: GroupsConfig,
 }
 
 impl Default for ServerConfig {
@@ -71,11 +521,105 @@ impl Default for ServerConfig {
             download_buffer_size: Some(128 * 1024), // Default 128 KB
 
             passwd_file: String::from("/etc/passwd"),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            pasv_accept_timeout: Some(DEFAULT_PASV_ACCEPT_TIMEOUT),
+            data_timeout: Some(DEFAULT_DATA_TIMEOUT),
+            shutdown_grace_period: Some(DEFAULT_SHUTDOWN_GRACE_PERIOD),
+            banner_file: Some(String::from(DEFAULT_BANNER_PATH)),
         }
     }
 }
 
+impl ServerConfig {
+    /// Returns the control connection idle timeout, or `None` when it is disabled (0).
+    pub fn idle_timeout_duration(&self) -> Option<Duration> {
+        match self.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Returns how long the server waits for the client to open a PASV data connection.
+    pub fn pasv_accept_timeout_duration(&self) -> Duration {
+        Duration::from_secs(
+            self.pasv_accept_timeout
+                .unwrap_or(DEFAULT_PASV_ACCEPT_TIMEOUT),
+        )
+    }
+
+    /// Returns how long a data transfer may stall before it is aborted.
+    pub fn data_timeout_duration(&self) -> Duration {
+        Duration::from_secs(self.data_timeout.unwrap_or(DEFAULT_DATA_TIMEOUT))
+    }
+
+    /// Returns the path of the banner sent to new connections.
+    pub fn banner_path(&self) -> &str {
+        self.banner_file.as_deref().unwrap_or(DEFAULT_BANNER_PATH)
+    }
+
+    /// Returns how long running transfers may continue once a shutdown is requested.
+    pub fn shutdown_grace_period_duration(&self) -> Duration {
+        Duration::from_secs(
+            self.shutdown_grace_period
+                .unwrap_or(DEFAULT_SHUTDOWN_GRACE_PERIOD),
+        )
+    }
+}
+
 impl Config {
+    /// Returns the server-wide speed limit for one direction, in bytes per second.
+    pub fn global_speed_limit(&self, upload: bool) -> Option<u64> {
+        self.throttle
+            .as_ref()
+            .and_then(|throttle| throttle.global.for_direction(upload))
+            .map(|speed| speed * 1024)
+    }
+
+    /// Returns `true` if the part of an upload stopped by the quota is kept.
+    pub fn keep_partial_uploads(&self) -> bool {
+        self.quota
+            .as_ref()
+            .and_then(|quota| quota.keep_partial_uploads)
+            .unwrap_or(false)
+    }
+
+    /// Returns the file the directory quotas set with SITE DIRQUOTA are saved to.
+    pub fn dir_quota_file(&self) -> PathBuf {
+        self.quota
+            .as_ref()
+            .and_then(|quota| quota.dir_quota_storage_file.clone())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DIR_QUOTA_FILE))
+    }
+
+    /// Returns the directory quotas of the `[quota]` section.
+    pub fn dir_quotas(&self) -> &[DirQuota] {
+        self.quota
+            .as_ref()
+            .map_or(&[], |quota| quota.dir_quotas.as_slice())
+    }
+
+    /// Returns the directory the site paths start from.
+    pub fn site_root(&self) -> PathBuf {
+        PathBuf::from(&self.server.chroot_dir).join(self.server.min_homedir.trim_start_matches('/'))
+    }
+
+    /// Returns the directory the anonymous users are jailed in.
+    pub fn anonymous_root(&self) -> PathBuf {
+        let root = self
+            .anonymous
+            .root
+            .as_deref()
+            .unwrap_or(&self.server.min_homedir);
+        PathBuf::from(&self.server.chroot_dir).join(root.trim_start_matches('/'))
+    }
+
+    /// Returns the maximum number of logged in anonymous users, if any.
+    pub fn max_anonymous_users(&self) -> Option<usize> {
+        self.anonymous
+            .max_users
+            .or(self.limits.max_anonymous_users)
+    }
+
     pub fn load_from_file(path: &str) -> Self {
         let config_str = std::fs::read_to_string(path).expect("Failed to read config file");
         let mut config: Config = toml::from_str(&config_str).expect("Failed to parse config file");
@@ -87,6 +631,21 @@ impl Config {
         if config.server.download_buffer_size.is_none() {
             config.server.download_buffer_size = Some(128 * 1024);
         }
+        if config.server.idle_timeout.is_none() {
+            config.server.idle_timeout = Some(DEFAULT_IDLE_TIMEOUT);
+        }
+        if config.server.pasv_accept_timeout.is_none() {
+            config.server.pasv_accept_timeout = Some(DEFAULT_PASV_ACCEPT_TIMEOUT);
+        }
+        if config.server.data_timeout.is_none() {
+            config.server.data_timeout = Some(DEFAULT_DATA_TIMEOUT);
+        }
+        if config.server.shutdown_grace_period.is_none() {
+            config.server.shutdown_grace_period = Some(DEFAULT_SHUTDOWN_GRACE_PERIOD);
+        }
+        if config.server.banner_file.is_none() {
+            config.server.banner_file = Some(String::from(DEFAULT_BANNER_PATH));
+        }
 
         // Set quota defaults if not specified
         if config.quota.is_none() {
@@ -118,4 +677,100 @@ impl Config {
 
         config
     }
+
+    /// Checks that the configuration can be used by the server.
+    ///
+    /// Used before swapping in a reloaded configuration, so that a typo in the
+    /// file does not take the running server down.
+    ///
+    /// # Returns
+    ///
+    /// An error describing the first problem found.
+    pub fn validate(&self) -> Result<()> {
+        let server = &self.server;
+
+        server
+            .pasv_address
+            .parse::<std::net::Ipv4Addr>()
+            .with_context(|| format!("Invalid pasv_address: {}", server.pasv_address))?;
+
+        let home_dir = PathBuf::from(&server.chroot_dir)
+            .join(server.min_homedir.trim_start_matches('/'));
+        if !home_dir.is_dir() {
+            bail!("min_homedir is not a directory: {}", home_dir.display());
+        }
+
+        if server.upload_buffer_size == Some(0) || server.download_buffer_size == Some(0) {
+            bail!("Buffer sizes must be greater than 0");
+        }
+
+        if !PathBuf::from(&server.passwd_file).is_file() {
+            bail!("passwd_file not found: {}", server.passwd_file);
+        }
+
+        for backend in self.auth.backends() {
+            match backend {
+                AuthBackendConfig::Passwd { path: Some(path) }
+                | AuthBackendConfig::Cached { path: Some(path) }
+                    if !PathBuf::from(&path).is_file() =>
+                {
+                    bail!("Auth backend passwd file not found: {}", path);
+                }
+                AuthBackendConfig::External { program, .. }
+                    if program.contains('/') && !PathBuf::from(&program).is_file() =>
+                {
+                    bail!("Auth backend program not found: {}", program);
+                }
+                _ => {}
+            }
+        }
+
+        if self.anonymous.enabled() {
+            let root = self.anonymous_root();
+            if !root.is_dir() {
+                bail!("Anonymous root is not a directory: {}", root.display());
+            }
+            if let Some(incoming) = self.anonymous.incoming_dir() {
+                let incoming = root.join(incoming.strip_prefix("/").unwrap_or(&incoming));
+                if !incoming.is_dir() {
+                    bail!("Anonymous incoming_dir is not a directory: {}", incoming.display());
+                }
+            }
+        }
+
+        for permission in PathPermission::ALL {
+            for line in self.acl.rules(permission) {
+                if AclRule::parse(line).is_none() {
+                    bail!("Invalid {} rule: {}", permission.name(), line);
+                }
+            }
+        }
+
+        if !PathBuf::from(server.banner_path()).is_file() {
+            bail!("banner_file not found: {}", server.banner_path());
+        }
+
+        if let Some(quota) = &self.quota {
+            if let Some(ratio) = &quota.default_ratio {
+                UserRatio::new("default", ratio)
+                    .with_context(|| format!("Invalid default_ratio: {}", ratio))?;
+            }
+            for (group, ratio) in &quota.group_ratios {
+                UserRatio::new(group, ratio).with_context(|| {
+                    format!("Invalid group_ratios entry for {}: {}", group, ratio)
+                })?;
+            }
+            for dir_quota in &quota.dir_quotas {
+                if dir_quota.path.split('/').any(|part| part == "..") {
+                    bail!("Invalid dir_quotas path: {}", dir_quota.path);
+                }
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            tls.validate().context("Invalid TLS configuration")?;
+        }
+
+        Ok(())
+    }
 }
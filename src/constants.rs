@@ -1,6 +1,7 @@
 // src/constants.rs
 
 pub const USERNAME_REGEX: &str = r"^[a-zA-Z0-9]{1,32}$";
+pub const GROUPNAME_REGEX: &str = r"^[a-zA-Z0-9_-]{1,32}$";
 pub const IP_HOSTNAME_MAX_LENGTH: usize = 128;
 
 // Constants specific to the `site addip` command
@@ -12,12 +13,55 @@ pub const MIN_DELIP_ARGS: usize = 2;
 pub const MAX_DELIP_IPS: usize = 10;
 
 pub const DEFAULT_CONFIG_PATH: &str = "/etc/rouilleftpd.conf";
+pub const DEFAULT_BANNER_PATH: &str = "ftp-data/text/banner.txt";
 
 // Constants specific to the RETR and STOR commands
 pub const MESSAGE_LENGTH: usize = 58;
 
 pub const MIN_DELUSER_ARGS: usize = 1;
 
+// Timeouts, in seconds
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 900;
+pub const MIN_IDLE_TIMEOUT: u64 = 30;
+pub const DEFAULT_PASV_ACCEPT_TIMEOUT: u64 = 30;
+pub const DEFAULT_DATA_TIMEOUT: u64 = 120;
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: u64 = 30;
+
+// Connection limit replies, sent with a 421 code
+pub const DEFAULT_SITE_FULL_MESSAGE: &str = "Site full, try again later.";
+pub const DEFAULT_IP_LIMIT_MESSAGE: &str = "Too many connections from your IP address.";
+pub const DEFAULT_ANONYMOUS_FULL_MESSAGE: &str = "Too many anonymous users, try again later.";
+
+// Failed login tarpitting and IP bans
+pub const DEFAULT_MAX_LOGIN_FAILURES: u32 = 5;
+pub const DEFAULT_FAILURE_WINDOW: u64 = 600; // seconds
+pub const DEFAULT_BAN_DURATION: u64 = 3600; // seconds
+pub const DEFAULT_TARPIT_DELAY: u64 = 1000; // milliseconds
+pub const DEFAULT_TARPIT_MAX_DELAY: u64 = 30000; // milliseconds
+pub const DEFAULT_BAN_FILE: &str = "data/bans.json";
+
+// RFC 1413 ident lookups
+pub const IDENT_PORT: u16 = 113;
+pub const DEFAULT_IDENT_TIMEOUT: u64 = 5; // seconds
+
+// Authentication backends
+pub const DEFAULT_EXTERNAL_AUTH_TIMEOUT: u64 = 10; // seconds
+
+// Password policy
+pub const DEFAULT_MIN_PASSWORD_LENGTH: usize = 6;
+pub const DEFAULT_PASSWORD_STATE_FILE: &str = "data/passwords.json";
+
+// Path permissions
+pub const DEFAULT_OWNER_FILE: &str = "data/owners.json";
+pub const OWNER_FLUSH_INTERVAL: u64 = 5; // seconds
+
+// Groups
+pub const DEFAULT_GROUPS_FILE: &str = "data/groups.json";
+
+// Quotas
+pub const DEFAULT_GRACE_PERIOD: u64 = 7 * 24 * 3600; // seconds
+pub const DEFAULT_DIR_QUOTA_FILE: &str = "data/dir_quotas.json";
+
 /*
   Flagname       	Flag	Description
     ------------------------------------------------------------------------
@@ -99,3 +143,5 @@ pub const SITE_ADDIP_HELP_PATH: &str = "ftp-data/help/site.addip";
 pub const SITE_ADDUSER_HELP_PATH: &str = "ftp-data/help/site.adduser";
 pub const SITE_DELIP_HELP_PATH: &str = "ftp-data/help/site.delip";
 pub const SITE_DELUSER_HELP_PATH: &str = "ftp-data/help/site.deluser";
+pub const SITE_GADDUSER_HELP_PATH: &str = "ftp-data/help/site.gadduser";
+pub const SITE_CHANGE_HELP_PATH: &str = "ftp-data/help/site.change";
@@ -0,0 +1,311 @@
+use crate::config::AclConfig;
+use crate::core_auth::mask::wildcard_match;
+use crate::core_ftpcommand::site::helper::parse_flags;
+use crate::session::Session;
+use std::path::Path;
+
+/// The glftpd path permissions, one list of rules each in `[acl]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathPermission {
+    Upload,
+    Download,
+    MakeDir,
+    Delete,
+    DeleteOwn,
+    Rename,
+    RenameOwn,
+    Nuke,
+    HideInWho,
+    PrivPath,
+}
+
+impl PathPermission {
+    pub const ALL: [PathPermission; 10] = [
+        PathPermission::Upload,
+        PathPermission::Download,
+        PathPermission::MakeDir,
+        PathPermission::Delete,
+        PathPermission::DeleteOwn,
+        PathPermission::Rename,
+        PathPermission::RenameOwn,
+        PathPermission::Nuke,
+        PathPermission::HideInWho,
+        PathPermission::PrivPath,
+    ];
+
+    /// Returns the glftpd keyword of the permission.
+    pub fn name(self) -> &'static str {
+        match self {
+            PathPermission::Upload => "upload",
+            PathPermission::Download => "download",
+            PathPermission::MakeDir => "makedir",
+            PathPermission::Delete => "delete",
+            PathPermission::DeleteOwn => "deleteown",
+            PathPermission::Rename => "rename",
+            PathPermission::RenameOwn => "renameown",
+            PathPermission::Nuke => "nuke",
+            PathPermission::HideInWho => "hideinwho",
+            PathPermission::PrivPath => "privpath",
+        }
+    }
+}
+
+/// The user an ACL is checked against.
+#[derive(Debug, Clone, Copy)]
+pub struct AclUser<'a> {
+    pub username: &'a str,
+    pub groups: &'a [String],
+    pub flags: &'a [u8],
+}
+
+impl<'a> From<&'a Session> for AclUser<'a> {
+    fn from(session: &'a Session) -> Self {
+        Self {
+            username: session.username.as_deref().unwrap_or_default(),
+            groups: &session.groups,
+            flags: &session.flags,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AclEntry {
+    Anyone,
+    User(String),
+    Group(String),
+    /// Matches users having any of the flags
+    Flags(Vec<u8>),
+}
+
+impl AclEntry {
+    fn matches(&self, user: &AclUser) -> bool {
+        match self {
+            AclEntry::Anyone => true,
+            AclEntry::User(name) => name == user.username,
+            AclEntry::Group(name) => user.groups.iter().any(|group| group == name),
+            AclEntry::Flags(flags) => flags.iter().any(|flag| user.flags.contains(flag)),
+        }
+    }
+}
+
+/// A `<path> <acl>` rule of the `[acl]` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    path: String,
+    /// The entries of the ACL, with whether they are negated
+    entries: Vec<(bool, AclEntry)>,
+}
+
+impl AclRule {
+    /// Parses a rule line, returning `None` if it is malformed.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut tokens = line.split_whitespace();
+        let path = tokens.next()?.to_string();
+
+        let mut entries = Vec::new();
+        for token in tokens {
+            let (negated, token) = match token.strip_prefix('!') {
+                Some(token) => (true, token),
+                None => (false, token),
+            };
+            let entry = if token == "*" {
+                AclEntry::Anyone
+            } else if let Some(name) = token.strip_prefix('-') {
+                AclEntry::User(name.to_string())
+            } else if let Some(name) = token.strip_prefix('=') {
+                AclEntry::Group(name.to_string())
+            } else if !token.is_empty()
+                && token.chars().all(|c| c.is_ascii_alphanumeric() && c != '0')
+            {
+                AclEntry::Flags(parse_flags(token))
+            } else {
+                return None;
+            };
+            if matches!(&entry, AclEntry::User(name) | AclEntry::Group(name) if name.is_empty()) {
+                return None;
+            }
+            entries.push((negated, entry));
+        }
+
+        (!entries.is_empty()).then_some(Self { path, entries })
+    }
+
+    fn matches_path(&self, path: &Path) -> bool {
+        wildcard_match(&self.path, &path.to_string_lossy())
+    }
+
+    /// Returns whether the ACL grants `user`: the first entry matching the user
+    /// decides, a user no entry matches is refused.
+    pub fn allows(&self, user: &AclUser) -> bool {
+        self.entries
+            .iter()
+            .find(|(_, entry)| entry.matches(user))
+            .is_some_and(|(negated, _)| !negated)
+    }
+}
+
+/// Returns the first `permission` rule matching the site path `path`.
+fn find_rule(config: &AclConfig, permission: PathPermission, path: &Path) -> Option<AclRule> {
+    config
+        .rules(permission)
+        .iter()
+        .filter_map(|line| AclRule::parse(line))
+        .find(|rule| rule.matches_path(path))
+}
+
+/// Returns whether `path`, or a directory above it, is a privpath that `user`
+/// is not listed in.
+pub fn is_private(config: &AclConfig, user: &AclUser, path: &Path) -> bool {
+    path.ancestors().any(|dir| {
+        find_rule(config, PathPermission::PrivPath, dir).is_some_and(|rule| !rule.allows(user))
+    })
+}
+
+/// Returns whether `user` may do `permission` on the site path `path`.
+///
+/// Private paths are closed to the users their privpath does not list, other
+/// paths are decided by the first matching rule, or open when none matches.
+pub fn is_allowed(
+    config: &AclConfig,
+    permission: PathPermission,
+    user: &AclUser,
+    path: &Path,
+) -> bool {
+    !is_private(config, user, path)
+        && find_rule(config, permission, path).is_none_or(|rule| rule.allows(user))
+}
+
+/// Returns whether `user` may delete or rename `path`, owned by `owner`.
+///
+/// The deleteown and renameown rules only add to the delete and rename ones:
+/// when the plain rule refuses, the owner may still go on if a matching "own"
+/// rule allows it.
+pub fn may_modify(
+    config: &AclConfig,
+    permission: PathPermission,
+    user: &AclUser,
+    path: &Path,
+    owner: Option<&str>,
+) -> bool {
+    if is_allowed(config, permission, user, path) {
+        return true;
+    }
+    let own = match permission {
+        PathPermission::Delete => PathPermission::DeleteOwn,
+        PathPermission::Rename => PathPermission::RenameOwn,
+        _ => return false,
+    };
+    owner == Some(user.username)
+        && !is_private(config, user, path)
+        && find_rule(config, own, path).is_some_and(|rule| rule.allows(user))
+}
+
+/// Returns whether the sessions in the directory `path` are hidden from SITE
+/// WHO for `viewer`, that is whether a hideinwho rule matching the path lists
+/// the viewer.
+pub fn is_hidden_in_who(config: &AclConfig, viewer: &AclUser, path: &Path) -> bool {
+    find_rule(config, PathPermission::HideInWho, path).is_some_and(|rule| rule.allows(viewer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::SITEOP;
+
+    #[test]
+    fn test_acl_rules() {
+        let config = AclConfig {
+            upload: vec![
+                "/incoming/* !-guest =uploaders 1".to_string(),
+                "/requests/* *".to_string(),
+            ],
+            delete: vec!["/* 1".to_string()],
+            deleteown: vec!["/incoming/* *".to_string()],
+            hideinwho: vec!["/private/* !1 *".to_string()],
+            privpath: vec!["/private =friends".to_string()],
+            ..Default::default()
+        };
+        let groups = vec!["uploaders".to_string()];
+        let uploader = AclUser {
+            username: "alice",
+            groups: &groups,
+            flags: &[3],
+        };
+        let guest = AclUser {
+            username: "guest",
+            groups: &groups,
+            flags: &[],
+        };
+        let siteop = AclUser {
+            username: "root",
+            groups: &[],
+            flags: &[SITEOP],
+        };
+
+        let file = Path::new("/incoming/file.zip");
+        assert!(is_allowed(&config, PathPermission::Upload, &uploader, file));
+        assert!(is_allowed(&config, PathPermission::Upload, &siteop, file));
+        assert!(!is_allowed(&config, PathPermission::Upload, &guest, file));
+        assert!(is_allowed(
+            &config,
+            PathPermission::Upload,
+            &guest,
+            Path::new("/requests/a")
+        ));
+        assert!(is_allowed(&config, PathPermission::Download, &guest, file));
+
+        assert!(!may_modify(
+            &config,
+            PathPermission::Delete,
+            &uploader,
+            file,
+            Some("bob")
+        ));
+        assert!(may_modify(
+            &config,
+            PathPermission::Delete,
+            &uploader,
+            file,
+            Some("alice")
+        ));
+        assert!(may_modify(
+            &config,
+            PathPermission::Delete,
+            &siteop,
+            file,
+            None
+        ));
+
+        let private = Path::new("/private/secret/file");
+        assert!(is_private(&config, &uploader, private));
+        assert!(!is_allowed(
+            &config,
+            PathPermission::Download,
+            &uploader,
+            private
+        ));
+        let friends = vec!["friends".to_string()];
+        let friend = AclUser {
+            username: "carol",
+            groups: &friends,
+            flags: &[],
+        };
+        assert!(!is_private(&config, &friend, private));
+
+        assert!(is_hidden_in_who(
+            &config,
+            &friend,
+            Path::new("/private/secret")
+        ));
+        assert!(!is_hidden_in_who(
+            &config,
+            &siteop,
+            Path::new("/private/secret")
+        ));
+        assert!(!is_hidden_in_who(&config, &friend, Path::new("/incoming")));
+
+        assert!(AclRule::parse("/incoming/*").is_none());
+        assert!(AclRule::parse("/incoming/* -").is_none());
+        assert!(AclRule::parse("/incoming/* 1$").is_none());
+    }
+}
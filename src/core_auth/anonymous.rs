@@ -0,0 +1,98 @@
+use crate::config::AnonymousConfig;
+use crate::core_ftpcommand::ftpcommand::FtpCommand;
+use crate::session::Session;
+use std::path::Path;
+
+/// Returns whether `username` asks for an anonymous login.
+pub fn is_anonymous_username(username: &str) -> bool {
+    username.eq_ignore_ascii_case("anonymous") || username.eq_ignore_ascii_case("ftp")
+}
+
+/// Checks that the password of an anonymous login looks like an e-mail address.
+pub fn is_valid_email(password: &str) -> bool {
+    let Some((local, domain)) = password.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !password.chars().any(char::is_whitespace)
+        && domain.split('.').all(|label| !label.is_empty())
+        && domain.contains('.')
+}
+
+/// Checks a command of an anonymous session against the `[anonymous]` rules.
+///
+/// With `read_only`, nothing may be deleted, renamed or created, and files may
+/// only be uploaded to a new name in the incoming directory. The incoming
+/// directory is upload-only: it can neither be listed nor downloaded from.
+///
+/// # Returns
+///
+/// The reply to send when the command is refused.
+pub async fn check_anonymous_access(
+    anonymous: &AnonymousConfig,
+    session: &Session,
+    cmd: &FtpCommand,
+    args: &[String],
+) -> Result<(), &'static str> {
+    let incoming = anonymous.incoming_dir();
+    let in_incoming = |path: &Path| incoming.as_deref().is_some_and(|dir| path.starts_with(dir));
+    let arg = args.join(" ");
+
+    match cmd {
+        FtpCommand::DELE
+        | FtpCommand::RMD
+        | FtpCommand::RNFR
+        | FtpCommand::RNTO
+        | FtpCommand::MKD
+            if anonymous.read_only() =>
+        {
+            Err("550 Permission denied, anonymous access is read-only.\r\n")
+        }
+        FtpCommand::SITE
+            if anonymous.read_only()
+                && args.first().is_some_and(|subcommand| {
+                    ["CHMOD", "UTIME"]
+                        .iter()
+                        .any(|denied| subcommand.eq_ignore_ascii_case(denied))
+                }) =>
+        {
+            Err("550 Permission denied, anonymous access is read-only.\r\n")
+        }
+        FtpCommand::STOR if anonymous.read_only() => {
+            if !in_incoming(&session.site_path(&arg)) {
+                Err("550 Permission denied, anonymous uploads go to the incoming directory.\r\n")
+            } else if tokio::fs::metadata(session.real_path(&arg)).await.is_ok() {
+                Err("550 File exists, anonymous users cannot overwrite files.\r\n")
+            } else {
+                Ok(())
+            }
+        }
+        FtpCommand::RETR | FtpCommand::SIZE | FtpCommand::MDTM
+            if in_incoming(&session.site_path(&arg)) =>
+        {
+            Err("550 Permission denied, the incoming directory is upload-only.\r\n")
+        }
+        FtpCommand::LIST if in_incoming(&session.site_path(&arg)) => {
+            Err("550 Permission denied, the incoming directory is upload-only.\r\n")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_email() {
+        assert!(is_valid_email("user@example.com"));
+        assert!(is_valid_email("first.last@mail.example.org"));
+        assert!(!is_valid_email("guest"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("user@localhost"));
+        assert!(!is_valid_email("user@@example.com"));
+        assert!(!is_valid_email("user@example..com"));
+        assert!(!is_valid_email("us er@example.com"));
+    }
+}
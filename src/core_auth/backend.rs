@@ -0,0 +1,296 @@
+use crate::config::AuthBackendConfig;
+use crate::core_auth::error::AuthError;
+use crate::core_auth::external_backend::ExternalBackend;
+use crate::core_auth::hash::HashAlgorithm;
+use crate::core_auth::passwd_backend::{CachedPasswdBackend, PasswdFileBackend};
+use crate::Config;
+use async_trait::async_trait;
+use log::error;
+
+/// A user account found by a backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthUser {
+    pub username: String,
+    /// Name of the backend holding the account
+    pub backend: String,
+}
+
+/// Outcome of a password check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Accepted,
+    Rejected,
+    /// The backend does not know the user, the next backend of a chain is asked
+    UnknownUser,
+}
+
+/// A source of user accounts and passwords.
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &str;
+
+    /// Looks up an account, returning `None` if the backend does not know it.
+    async fn lookup(&self, username: &str) -> Result<Option<AuthUser>, AuthError>;
+
+    /// Checks the password of an account.
+    async fn verify(&self, username: &str, password: &str) -> Result<Verification, AuthError>;
+
+    /// Re-hashes the stored password of an account with `algorithm`, if it
+    /// uses another one. `password` must already have been verified.
+    ///
+    /// Returns whether the stored hash changed; backends that do not store
+    /// hashes keep the default, which does nothing.
+    async fn upgrade_hash(
+        &self,
+        _username: &str,
+        _password: &str,
+        _algorithm: HashAlgorithm,
+    ) -> Result<bool, AuthError> {
+        Ok(false)
+    }
+
+    /// Replaces the stored password hash of an account.
+    ///
+    /// Returns `false` if the account is unknown or the backend does not store
+    /// passwords.
+    async fn set_password_hash(&self, _username: &str, _hash: &str) -> Result<bool, AuthError> {
+        Ok(false)
+    }
+
+    /// Adds an account with an already hashed password.
+    ///
+    /// Returns `false` if the backend does not store accounts.
+    async fn add_user(&self, _username: &str, _hash: &str) -> Result<bool, AuthError> {
+        Ok(false)
+    }
+}
+
+/// Backends asked in order; the first one knowing a user decides for it.
+///
+/// A failing backend is logged and skipped, its error is only returned when no
+/// other backend knows the user, so an unavailable program does not turn into
+/// a wrong password.
+pub struct AuthChain {
+    backends: Vec<Box<dyn AuthBackend>>,
+}
+
+impl AuthChain {
+    pub fn new(backends: Vec<Box<dyn AuthBackend>>) -> Self {
+        Self { backends }
+    }
+
+    /// Builds the chain configured in the `[auth]` section.
+    pub fn from_config(config: &Config) -> Self {
+        let backends = config
+            .auth
+            .backends()
+            .into_iter()
+            .map(|backend| -> Box<dyn AuthBackend> {
+                match backend {
+                    AuthBackendConfig::Passwd { path } => Box::new(PasswdFileBackend::new(
+                        path.unwrap_or_else(|| config.server.passwd_file.clone()),
+                    )),
+                    AuthBackendConfig::Cached { path } => Box::new(CachedPasswdBackend::new(
+                        path.unwrap_or_else(|| config.server.passwd_file.clone()),
+                    )),
+                    AuthBackendConfig::External {
+                        program,
+                        args,
+                        timeout,
+                    } => Box::new(ExternalBackend::new(program, args, timeout)),
+                }
+            })
+            .collect();
+        Self::new(backends)
+    }
+}
+
+#[async_trait]
+impl AuthBackend for AuthChain {
+    fn name(&self) -> &str {
+        "chain"
+    }
+
+    async fn lookup(&self, username: &str) -> Result<Option<AuthUser>, AuthError> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.lookup(username).await {
+                Ok(Some(user)) => return Ok(Some(user)),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Auth backend {} failed: {}", backend.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        last_error.map_or(Ok(None), Err)
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verification, AuthError> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.verify(username, password).await {
+                Ok(Verification::UnknownUser) => {}
+                Ok(verification) => return Ok(verification),
+                Err(e) => {
+                    error!("Auth backend {} failed: {}", backend.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        last_error.map_or(Ok(Verification::UnknownUser), Err)
+    }
+
+    async fn upgrade_hash(
+        &self,
+        username: &str,
+        password: &str,
+        algorithm: HashAlgorithm,
+    ) -> Result<bool, AuthError> {
+        // Only the backend that authenticated the user holds its password
+        for backend in &self.backends {
+            if backend.lookup(username).await?.is_some() {
+                return backend.upgrade_hash(username, password, algorithm).await;
+            }
+        }
+        Ok(false)
+    }
+
+    async fn set_password_hash(&self, username: &str, hash: &str) -> Result<bool, AuthError> {
+        for backend in &self.backends {
+            if backend.lookup(username).await?.is_some() {
+                return backend.set_password_hash(username, hash).await;
+            }
+        }
+        Ok(false)
+    }
+
+    async fn add_user(&self, username: &str, hash: &str) -> Result<bool, AuthError> {
+        // New accounts go to the first backend able to store them
+        for backend in &self.backends {
+            if backend.add_user(username, hash).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backend knowing a fixed set of accounts, or failing every request.
+    struct FakeBackend {
+        name: &'static str,
+        accounts: Vec<(&'static str, &'static str)>,
+        failing: bool,
+    }
+
+    impl FakeBackend {
+        fn boxed(name: &'static str, accounts: &[(&'static str, &'static str)]) -> Box<Self> {
+            Box::new(Self {
+                name,
+                accounts: accounts.to_vec(),
+                failing: false,
+            })
+        }
+
+        fn failing(name: &'static str) -> Box<Self> {
+            Box::new(Self {
+                name,
+                accounts: Vec::new(),
+                failing: true,
+            })
+        }
+
+        fn check(&self) -> Result<(), AuthError> {
+            if self.failing {
+                return Err(AuthError::ProgramTimeout(self.name.to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl AuthBackend for FakeBackend {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn lookup(&self, username: &str) -> Result<Option<AuthUser>, AuthError> {
+            self.check()?;
+            Ok(self
+                .accounts
+                .iter()
+                .find(|(name, _)| *name == username)
+                .map(|_| AuthUser {
+                    username: username.to_string(),
+                    backend: self.name.to_string(),
+                }))
+        }
+
+        async fn verify(&self, username: &str, password: &str) -> Result<Verification, AuthError> {
+            self.check()?;
+            Ok(
+                match self.accounts.iter().find(|(name, _)| *name == username) {
+                    Some((_, stored)) if *stored == password => Verification::Accepted,
+                    Some(_) => Verification::Rejected,
+                    None => Verification::UnknownUser,
+                },
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_order() {
+        let chain = AuthChain::new(vec![
+            FakeBackend::boxed("first", &[("alice", "one")]),
+            FakeBackend::boxed("second", &[("alice", "two"), ("bob", "three")]),
+        ]);
+
+        // The first backend knowing a user decides, even against a later one
+        let alice = chain.lookup("alice").await.unwrap().unwrap();
+        assert_eq!(alice.backend, "first");
+        assert_eq!(
+            chain.verify("alice", "two").await.unwrap(),
+            Verification::Rejected
+        );
+
+        // Users unknown to the first backend are asked to the next one
+        let bob = chain.lookup("bob").await.unwrap().unwrap();
+        assert_eq!(bob.backend, "second");
+        assert_eq!(
+            chain.verify("bob", "three").await.unwrap(),
+            Verification::Accepted
+        );
+        assert_eq!(chain.lookup("carol").await.unwrap(), None);
+        assert_eq!(
+            chain.verify("carol", "four").await.unwrap(),
+            Verification::UnknownUser
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_skips_failing_backends() {
+        let chain = AuthChain::new(vec![
+            FakeBackend::failing("broken"),
+            FakeBackend::boxed("passwd", &[("alice", "one")]),
+        ]);
+
+        // A failing backend does not stop the others from deciding
+        assert_eq!(
+            chain.lookup("alice").await.unwrap().unwrap().backend,
+            "passwd"
+        );
+        assert_eq!(
+            chain.verify("alice", "one").await.unwrap(),
+            Verification::Accepted
+        );
+
+        // Its error comes out when nobody else knows the user
+        assert!(chain.lookup("bob").await.is_err());
+        assert!(chain.verify("bob", "two").await.is_err());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::config::BanConfig;
+use crate::helpers::write_file_atomic;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A temporary ban of an IP address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    /// Unix timestamp at which the ban expires
+    pub until: u64,
+    /// Why the IP was banned
+    pub reason: String,
+}
+
+impl Ban {
+    fn is_active(&self, now: u64) -> bool {
+        self.until > now
+    }
+}
+
+/// Failed logins counted within the failure window.
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    window_start: Instant,
+}
+
+/// Outcome of a failed login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailedLogin {
+    /// How long to wait before replying to the client
+    pub delay: Duration,
+    /// Whether the IP is banned, by this failure or an earlier one
+    pub banned: bool,
+}
+
+#[derive(Default)]
+struct BanState {
+    ip_failures: HashMap<IpAddr, Failures>,
+    account_failures: HashMap<String, Failures>,
+    bans: HashMap<IpAddr, Ban>,
+}
+
+/// Tracks failed logins per IP and per account, and the temporary IP bans
+/// they lead to. Bans are persisted so they survive a restart.
+pub struct BanManager {
+    state: Mutex<BanState>,
+    ban_file: PathBuf,
+    /// Serializes the writes of the ban file
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl BanManager {
+    pub fn new(ban_file: PathBuf) -> Self {
+        Self {
+            state: Mutex::new(BanState::default()),
+            ban_file,
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Loads the bans saved by a previous run, dropping the expired ones.
+    pub async fn load(&self) {
+        let content = match tokio::fs::read_to_string(&self.ban_file).await {
+            Ok(content) => content,
+            Err(e) => {
+                info!("No ban file loaded from {:?}: {}", self.ban_file, e);
+                return;
+            }
+        };
+
+        match serde_json::from_str::<HashMap<IpAddr, Ban>>(&content) {
+            Ok(bans) => {
+                let now = unix_now();
+                let mut state = self.state.lock().unwrap();
+                state.bans = bans.into_iter().filter(|(_, ban)| ban.is_active(now)).collect();
+                info!("Loaded {} active IP ban(s)", state.bans.len());
+            }
+            Err(e) => error!("Failed to parse ban file {:?}: {}", self.ban_file, e),
+        }
+    }
+
+    /// Writes the active bans to the ban file.
+    pub async fn save(&self) {
+        // Taken before the snapshot, so a later save never writes older bans
+        let _saving = self.save_lock.lock().await;
+        let content = {
+            let now = unix_now();
+            let mut state = self.state.lock().unwrap();
+            state.bans.retain(|_, ban| ban.is_active(now));
+            serde_json::to_string_pretty(&state.bans)
+        };
+
+        let result = match content {
+            Ok(content) => write_file_atomic(&self.ban_file, content.as_bytes()).await,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        if let Err(e) = result {
+            error!("Failed to save ban file {:?}: {}", self.ban_file, e);
+        }
+    }
+
+    /// Returns the active ban of `ip`, if any.
+    pub fn ban_of(&self, ip: IpAddr) -> Option<Ban> {
+        let state = self.state.lock().unwrap();
+        state
+            .bans
+            .get(&ip)
+            .filter(|ban| ban.is_active(unix_now()))
+            .cloned()
+    }
+
+    /// Returns the active bans, soonest to expire first.
+    pub fn list(&self) -> Vec<(IpAddr, Ban)> {
+        let now = unix_now();
+        let state = self.state.lock().unwrap();
+        let mut bans: Vec<(IpAddr, Ban)> = state
+            .bans
+            .iter()
+            .filter(|(_, ban)| ban.is_active(now))
+            .map(|(ip, ban)| (*ip, ban.clone()))
+            .collect();
+        bans.sort_by_key(|(_, ban)| ban.until);
+        bans
+    }
+
+    /// Lifts the ban of `ip`, returning `false` if it was not banned.
+    pub async fn unban(&self, ip: IpAddr) -> bool {
+        let removed = {
+            let mut state = self.state.lock().unwrap();
+            state.ip_failures.remove(&ip);
+            state.bans.remove(&ip).is_some()
+        };
+        if removed {
+            self.save().await;
+        }
+        removed
+    }
+
+    /// Records a failed login from `ip`, as `username` when the account exists.
+    ///
+    /// The reply delay doubles with each failure of the IP or the account within
+    /// the failure window. Once the IP reaches `max_failures` it gets banned.
+    /// Unknown accounts are not counted, so random user names cannot grow the
+    /// counters, and the counters whose window expired are dropped. While the IP
+    /// is banned, every failure reports the ban with the longest delay.
+    pub async fn record_failure(
+        &self,
+        ip: IpAddr,
+        username: Option<&str>,
+        config: &BanConfig,
+    ) -> FailedLogin {
+        let window = config.failure_window();
+        let (failures, banned) = {
+            let mut state = self.state.lock().unwrap();
+            if state
+                .bans
+                .get(&ip)
+                .is_some_and(|ban| ban.is_active(unix_now()))
+            {
+                return FailedLogin {
+                    delay: config.tarpit_delay(config.max_failures()),
+                    banned: true,
+                };
+            }
+
+            state
+                .ip_failures
+                .retain(|_, failures| failures.window_start.elapsed() <= window);
+            state
+                .account_failures
+                .retain(|_, failures| failures.window_start.elapsed() <= window);
+
+            let ip_failures = increment(state.ip_failures.entry(ip), window);
+            let account_failures = match username {
+                Some(username) => {
+                    increment(state.account_failures.entry(username.to_string()), window)
+                }
+                None => 0,
+            };
+
+            let banned = config.max_failures() > 0 && ip_failures >= config.max_failures();
+            if banned {
+                let ban = Ban {
+                    until: unix_now() + config.ban_duration().as_secs(),
+                    reason: format!(
+                        "{} failed logins (last as {})",
+                        ip_failures,
+                        username.unwrap_or("an unknown user")
+                    ),
+                };
+                warn!("Banning {} until {}: {}", ip, ban.until, ban.reason);
+                state.ip_failures.remove(&ip);
+                state.bans.insert(ip, ban);
+            }
+
+            (ip_failures.max(account_failures), banned)
+        };
+
+        if banned {
+            self.save().await;
+        }
+
+        FailedLogin {
+            delay: config.tarpit_delay(failures),
+            banned,
+        }
+    }
+
+    /// Clears the failure counters after a successful login.
+    pub fn record_success(&self, ip: IpAddr, username: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.ip_failures.remove(&ip);
+        state.account_failures.remove(username);
+    }
+}
+
+/// Counts one more failure, starting a new window if the previous one expired.
+fn increment<K>(entry: std::collections::hash_map::Entry<'_, K, Failures>, window: Duration) -> u32 {
+    let failures = entry.or_insert(Failures {
+        count: 0,
+        window_start: Instant::now(),
+    });
+    if failures.window_start.elapsed() > window {
+        failures.count = 0;
+        failures.window_start = Instant::now();
+    }
+    failures.count += 1;
+    failures.count
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban_config() -> BanConfig {
+        BanConfig {
+            max_failures: Some(3),
+            ban_duration: Some(60),
+            tarpit_delay: Some(100),
+            tarpit_max_delay: Some(1000),
+            ..BanConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failures_while_banned() {
+        let dir = tempfile::tempdir().unwrap();
+        let bans = BanManager::new(dir.path().join("bans.json"));
+        let config = ban_config();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        for _ in 0..2 {
+            assert!(!bans.record_failure(ip, None, &config).await.banned);
+        }
+        assert!(bans.record_failure(ip, None, &config).await.banned);
+
+        // The counter restarts with the ban, the ban still answers
+        let failure = bans.record_failure(ip, Some("bob"), &config).await;
+        assert!(failure.banned);
+        assert_eq!(failure.delay, config.tarpit_delay(3));
+    }
+
+    #[tokio::test]
+    async fn test_tarpit_growth() {
+        let dir = tempfile::tempdir().unwrap();
+        let bans = BanManager::new(dir.path().join("bans.json"));
+        let config = BanConfig {
+            max_failures: Some(0),
+            ..ban_config()
+        };
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // The delay doubles with each failure, up to the maximum
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            let failure = bans.record_failure(ip, None, &config).await;
+            assert!(!failure.banned);
+            delays.push(failure.delay.as_millis());
+        }
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+        // The account counts too, whatever the IP it fails from
+        bans.record_success(ip, "bob");
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        bans.record_failure(ip, Some("bob"), &config).await;
+        let failure = bans.record_failure(other, Some("bob"), &config).await;
+        assert_eq!(failure.delay, Duration::from_millis(200));
+
+        // A successful login starts over
+        bans.record_success(other, "bob");
+        let failure = bans.record_failure(other, Some("bob"), &config).await;
+        assert_eq!(failure.delay, Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_ban_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let ban_file = dir.path().join("bans.json");
+        let bans = BanManager::new(ban_file.clone());
+        let config = ban_config();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let expired = Ban {
+            until: unix_now() - 1,
+            reason: "expired".to_string(),
+        };
+        bans.state.lock().unwrap().bans.insert(ip, expired);
+
+        // An expired ban no longer refuses the IP nor shows in the list
+        assert!(bans.ban_of(ip).is_none());
+        assert!(bans.list().is_empty());
+        assert!(!bans.record_failure(ip, None, &config).await.banned);
+
+        // Nor is it saved
+        bans.save().await;
+        assert_eq!(std::fs::read_to_string(ban_file).unwrap(), "{}");
+    }
+
+    #[tokio::test]
+    async fn test_ban_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let ban_file = dir.path().join("bans.json");
+        let config = ban_config();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let expired_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let bans = BanManager::new(ban_file.clone());
+        for _ in 0..3 {
+            bans.record_failure(ip, Some("bob"), &config).await;
+        }
+        bans.state.lock().unwrap().bans.insert(
+            expired_ip,
+            Ban {
+                until: unix_now() - 1,
+                reason: "expired".to_string(),
+            },
+        );
+        bans.save().await;
+
+        // The active ban survives a restart, the expired one is dropped
+        let restarted = BanManager::new(ban_file.clone());
+        restarted.load().await;
+        let ban = restarted.ban_of(ip).unwrap();
+        assert!(ban.reason.contains("bob"));
+        assert_eq!(restarted.list().len(), 1);
+
+        // Lifting a ban is saved too
+        assert!(restarted.unban(ip).await);
+        let reloaded = BanManager::new(ban_file);
+        reloaded.load().await;
+        assert!(reloaded.list().is_empty());
+    }
+}
@@ -1,28 +1,60 @@
+use crate::core_auth::hash::verify_hash_blocking;
+
+/// A line of the passwd file.
+///
+/// Both the short `user:hash` form and the glftpd form
+/// `user:hash:uid:gid:date:homedir:shell` are accepted; the fields after the
+/// hash are kept as they are so the line can be written back.
 #[derive(Debug, Clone)]
 pub struct PasswdEntry {
     username: String,
     hashed_password: String,
+    extra_fields: Vec<String>,
 }
 
 impl PasswdEntry {
     pub fn from_line(line: &str) -> Option<Self> {
-        let parts: Vec<&str> = line.split(':').collect();
-        if parts.len() != 2 {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut parts = line.split(':');
+        let username = parts.next()?;
+        let hashed_password = parts.next()?;
+        if username.is_empty() {
             return None;
         }
         let entry = PasswdEntry {
-            username: parts[0].to_string(),
-            hashed_password: parts[1].to_string(),
+            username: username.to_string(),
+            hashed_password: hashed_password.to_string(),
+            extra_fields: parts.map(String::from).collect(),
         };
 
         Some(entry)
     }
 
+    /// Formats the entry back into a passwd line, without the line feed.
+    pub fn to_line(&self) -> String {
+        let mut fields = vec![self.username.as_str(), self.hashed_password.as_str()];
+        fields.extend(self.extra_fields.iter().map(String::as_str));
+        fields.join(":")
+    }
+
     pub fn get_hashed_password(&self) -> &str {
         &self.hashed_password
     }
 
+    pub fn set_hashed_password(&mut self, hashed_password: String) {
+        self.hashed_password = hashed_password;
+    }
+
     pub fn get_username(&self) -> &str {
         &self.username
     }
+
+    /// Checks `password` against the stored hash, whatever its format.
+    pub async fn verify_password(&self, password: &str) -> bool {
+        verify_hash_blocking(password, self.get_hashed_password()).await
+    }
 }
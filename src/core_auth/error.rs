@@ -0,0 +1,72 @@
+// Gestion des erreurs pour les backends d'authentification
+use crate::core_auth::hash::HashAlgorithm;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("Failed to read passwd file {0}: {1}")]
+    PasswdReadError(String, std::io::Error),
+
+    #[error("Failed to write passwd file {0}: {1}")]
+    PasswdWriteError(String, std::io::Error),
+
+    #[error("Failed to hash the password with {0:?}")]
+    HashError(HashAlgorithm),
+
+    #[error("Failed to run auth program {0}: {1}")]
+    ProgramError(String, std::io::Error),
+
+    #[error("Auth program {0} timed out")]
+    ProgramTimeout(String),
+
+    #[error("Unexpected reply from auth program {0}: {1:?}")]
+    ProgramReply(String, String),
+}
+
+#[derive(Error, Debug)]
+pub enum PasswordError {
+    #[error("Password must be at least {0} characters long")]
+    TooShort(usize),
+
+    #[error("Password must contain a lowercase letter")]
+    MissingLowercase,
+
+    #[error("Password must contain an uppercase letter")]
+    MissingUppercase,
+
+    #[error("Password must contain a digit")]
+    MissingDigit,
+
+    #[error("Password must contain a special character")]
+    MissingSpecial,
+
+    #[error("Password was used recently")]
+    Reused,
+
+    #[error("User not found: {0}")]
+    UserNotFound(String),
+
+    #[error("The authentication backend of {0} cannot change passwords")]
+    Unsupported(String),
+
+    #[error("Failed to write password state: {0}")]
+    StateWriteError(std::io::Error),
+
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+}
+
+impl PasswordError {
+    pub fn to_ftp_response(&self) -> String {
+        match self {
+            PasswordError::UserNotFound(_) => "550 User not found.".to_string(),
+            PasswordError::Unsupported(_) => {
+                "550 Password cannot be changed for this user.".to_string()
+            }
+            PasswordError::StateWriteError(_) | PasswordError::Auth(_) => {
+                "451 Requested action aborted. Local error in processing.".to_string()
+            }
+            policy => format!("501 {}.", policy),
+        }
+    }
+}
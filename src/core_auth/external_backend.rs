@@ -0,0 +1,186 @@
+use crate::constants::DEFAULT_EXTERNAL_AUTH_TIMEOUT;
+use crate::core_auth::backend::{AuthBackend, AuthUser, Verification};
+use crate::core_auth::error::AuthError;
+use async_trait::async_trait;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// Accounts checked by an external program, run once per request.
+///
+/// The server writes the request on the program's stdin, one field per line,
+/// then closes it:
+///
+/// ```text
+/// LOOKUP            VERIFY
+/// <username>        <username>
+///                   <password>
+/// ```
+///
+/// The first line of stdout is the answer: `OK` (user exists / password
+/// accepted), `FAIL` (wrong password) or `UNKNOWN` (no such user).
+pub struct ExternalBackend {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl ExternalBackend {
+    pub fn new(program: String, args: Vec<String>, timeout: Option<u64>) -> Self {
+        Self {
+            program,
+            args,
+            timeout: Duration::from_secs(timeout.unwrap_or(DEFAULT_EXTERNAL_AUTH_TIMEOUT)),
+        }
+    }
+
+    /// Runs the program with `request` on stdin and returns its answer.
+    async fn ask(&self, request: String) -> Result<String, AuthError> {
+        let program_error = |e| AuthError::ProgramError(self.program.clone(), e);
+
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(program_error)?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        let exchange = async {
+            stdin.write_all(request.as_bytes()).await?;
+            drop(stdin);
+
+            let mut answer = String::new();
+            BufReader::new(stdout).read_line(&mut answer).await?;
+            child.wait().await?;
+            Ok(answer.trim().to_string())
+        };
+
+        match timeout(self.timeout, exchange).await {
+            Ok(result) => result.map_err(program_error),
+            Err(_) => Err(AuthError::ProgramTimeout(self.program.clone())),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for ExternalBackend {
+    fn name(&self) -> &str {
+        "external"
+    }
+
+    async fn lookup(&self, username: &str) -> Result<Option<AuthUser>, AuthError> {
+        match self.ask(format!("LOOKUP\n{}\n", username)).await?.as_str() {
+            "OK" => Ok(Some(AuthUser {
+                username: username.to_string(),
+                backend: self.name().to_string(),
+            })),
+            "UNKNOWN" => Ok(None),
+            other => Err(AuthError::ProgramReply(
+                self.program.clone(),
+                other.to_string(),
+            )),
+        }
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verification, AuthError> {
+        match self
+            .ask(format!("VERIFY\n{}\n{}\n", username, password))
+            .await?
+            .as_str()
+        {
+            "OK" => Ok(Verification::Accepted),
+            "FAIL" => Ok(Verification::Rejected),
+            "UNKNOWN" => Ok(Verification::UnknownUser),
+            other => Err(AuthError::ProgramReply(
+                self.program.clone(),
+                other.to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// An external program knowing alice, with "secret" as password.
+    const PROGRAM: &str = r#"
+read request
+read username
+read password
+case "$request:$username:$password" in
+    LOOKUP:alice:) echo OK ;;
+    VERIFY:alice:secret) echo OK ;;
+    VERIFY:alice:*) echo FAIL ;;
+    *:mallory:*) echo MAYBE ;;
+    *) echo UNKNOWN ;;
+esac
+"#;
+
+    fn backend(dir: &Path, script: &str, timeout: Option<u64>) -> ExternalBackend {
+        let path = dir.join("auth.sh");
+        std::fs::write(&path, script).unwrap();
+        ExternalBackend::new(
+            "sh".to_string(),
+            vec![path.to_string_lossy().into_owned()],
+            timeout,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_external_protocol() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(dir.path(), PROGRAM, None);
+
+        let alice = backend.lookup("alice").await.unwrap().unwrap();
+        assert_eq!(alice.backend, "external");
+        assert_eq!(backend.lookup("bob").await.unwrap(), None);
+        assert_eq!(
+            backend.verify("alice", "secret").await.unwrap(),
+            Verification::Accepted
+        );
+        assert_eq!(
+            backend.verify("alice", "guess").await.unwrap(),
+            Verification::Rejected
+        );
+        assert_eq!(
+            backend.verify("bob", "secret").await.unwrap(),
+            Verification::UnknownUser
+        );
+
+        // Anything else is an error, not a wrong password
+        assert!(matches!(
+            backend.verify("mallory", "secret").await,
+            Err(AuthError::ProgramReply(_, reply)) if reply == "MAYBE"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_external_program_failures() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let slow = backend(dir.path(), "sleep 5\necho OK\n", Some(1));
+        assert!(matches!(
+            slow.lookup("alice").await,
+            Err(AuthError::ProgramTimeout(_))
+        ));
+
+        let missing = ExternalBackend::new(
+            dir.path().join("missing").to_string_lossy().into_owned(),
+            Vec::new(),
+            None,
+        );
+        assert!(matches!(
+            missing.lookup("alice").await,
+            Err(AuthError::ProgramError(_, _))
+        ));
+    }
+}
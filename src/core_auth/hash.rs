@@ -0,0 +1,154 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+/// Iterations of PBKDF2-HMAC-SHA1 used by glftpd.
+const GLFTPD_ITERATIONS: u32 = 100;
+const GLFTPD_SALT_LEN: usize = 4;
+const GLFTPD_HASH_LEN: usize = 20;
+
+/// The password hash formats found in passwd files, detected by their prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// glftpd's `$<salt>$<hash>`: PBKDF2-HMAC-SHA1, 100 iterations, 4 byte salt, hex encoded
+    Glftpd,
+    /// `$2a$`, `$2b$`, `$2y$`
+    Bcrypt,
+    /// `$argon2id$`
+    Argon2id,
+    /// `$6$`
+    #[serde(rename = "sha512-crypt")]
+    Sha512Crypt,
+}
+
+impl HashAlgorithm {
+    /// Detects the algorithm of `hash`, `None` for an unknown format.
+    pub fn detect(hash: &str) -> Option<Self> {
+        if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+            Some(Self::Bcrypt)
+        } else if hash.starts_with("$argon2id$") {
+            Some(Self::Argon2id)
+        } else if hash.starts_with("$6$") {
+            Some(Self::Sha512Crypt)
+        } else if parse_glftpd(hash).is_some() {
+            Some(Self::Glftpd)
+        } else {
+            None
+        }
+    }
+
+    /// Hashes `password` with a fresh random salt.
+    pub fn hash(self, password: &str) -> Option<String> {
+        match self {
+            Self::Glftpd => {
+                let mut salt = [0u8; GLFTPD_SALT_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+                Some(format!(
+                    "${}${}",
+                    hex::encode(salt),
+                    hex::encode(glftpd_digest(password, &salt))
+                ))
+            }
+            Self::Bcrypt => bcrypt::hash(password, bcrypt::DEFAULT_COST).ok(),
+            Self::Argon2id => {
+                let mut salt = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let salt = SaltString::encode_b64(&salt).ok()?;
+                Argon2::default()
+                    .hash_password(password.as_bytes(), &salt)
+                    .ok()
+                    .map(|hash| hash.to_string())
+            }
+            Self::Sha512Crypt => pwhash::sha512_crypt::hash(password).ok(),
+        }
+    }
+
+    /// Runs `hash` on the blocking thread pool, so that the deliberately slow
+    /// algorithms do not stall the other sessions of the worker.
+    pub async fn hash_blocking(self, password: &str) -> Option<String> {
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || self.hash(&password))
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+/// Checks `password` against `hash`, whatever its format.
+pub fn verify_hash(password: &str, hash: &str) -> bool {
+    match HashAlgorithm::detect(hash) {
+        Some(HashAlgorithm::Glftpd) => match parse_glftpd(hash) {
+            Some((salt, digest)) => glftpd_digest(password, &salt).as_slice() == digest,
+            None => false,
+        },
+        Some(HashAlgorithm::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(HashAlgorithm::Argon2id) => match PasswordHash::new(hash) {
+            Ok(parsed) => Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            Err(_) => false,
+        },
+        Some(HashAlgorithm::Sha512Crypt) => pwhash::sha512_crypt::verify(password, hash),
+        None => false,
+    }
+}
+
+/// Runs `verify_hash` on the blocking thread pool, like `HashAlgorithm::hash_blocking`.
+pub async fn verify_hash_blocking(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    tokio::task::spawn_blocking(move || verify_hash(&password, &hash))
+        .await
+        .unwrap_or(false)
+}
+
+/// Splits a glftpd hash into its salt and digest.
+fn parse_glftpd(hash: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (salt, digest) = hash.strip_prefix('$')?.split_once('$')?;
+    let salt = hex::decode(salt).ok()?;
+    let digest = hex::decode(digest).ok()?;
+    (salt.len() == GLFTPD_SALT_LEN && digest.len() == GLFTPD_HASH_LEN).then_some((salt, digest))
+}
+
+fn glftpd_digest(password: &str, salt: &[u8]) -> [u8; GLFTPD_HASH_LEN] {
+    let mut digest = [0u8; GLFTPD_HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), salt, GLFTPD_ITERATIONS, &mut digest);
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glftpd_hash() {
+        // PBKDF2-HMAC-SHA1("secret", 0xdeadbeef, 100 iterations, 20 bytes)
+        let hash = "$deadbeef$c5d1e91af2d48c7adf7f36ee1ba5f7796bab19a3";
+        assert_eq!(HashAlgorithm::detect(hash), Some(HashAlgorithm::Glftpd));
+        assert!(verify_hash("secret", hash));
+        assert!(!verify_hash("Secret", hash));
+    }
+
+    #[test]
+    fn test_detect_and_roundtrip() {
+        for algorithm in [
+            HashAlgorithm::Glftpd,
+            HashAlgorithm::Argon2id,
+            HashAlgorithm::Sha512Crypt,
+        ] {
+            let hash = algorithm.hash("hunter2").unwrap();
+            assert_eq!(HashAlgorithm::detect(&hash), Some(algorithm));
+            assert!(verify_hash("hunter2", &hash));
+            assert!(!verify_hash("hunter3", &hash));
+        }
+
+        assert_eq!(
+            HashAlgorithm::detect("$2b$04$abcdefghijklmnopqrstuu5qz0Zbx1pqKTmB2J7n6bdCHkOQwEXgu"),
+            Some(HashAlgorithm::Bcrypt)
+        );
+        assert_eq!(HashAlgorithm::detect("plaintext"), None);
+        assert!(!verify_hash("plaintext", "plaintext"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::net::IpAddr;
+
+/// An `ident@host` mask from the `IP` lines of a userfile.
+///
+/// The ident part is a wildcard pattern (`*` and `?`). The host part is either
+/// a CIDR block (`10.0.0.0/8`, `2001:db8::/32`) or a wildcard pattern matched
+/// against the textual address (`192.168.1.*`, `*`). Hostnames are not
+/// resolved, so a mask only matches on the address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentMask {
+    ident: String,
+    host: HostMask,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostMask {
+    Cidr(IpAddr, u8),
+    Pattern(String),
+}
+
+impl IdentMask {
+    /// Parses an `ident@host` mask, returning `None` if it is malformed.
+    pub fn parse(mask: &str) -> Option<Self> {
+        let (ident, host) = mask.split_once('@')?;
+        if ident.is_empty() || host.is_empty() || host.contains('@') {
+            return None;
+        }
+
+        let host = match host.split_once('/') {
+            Some((address, prefix)) => {
+                let address: IpAddr = address.parse().ok()?;
+                let prefix: u8 = prefix.parse().ok()?;
+                let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+                if prefix > max_prefix {
+                    return None;
+                }
+                HostMask::Cidr(address, prefix)
+            }
+            None => HostMask::Pattern(host.to_lowercase()),
+        };
+
+        Some(Self {
+            ident: ident.to_string(),
+            host,
+        })
+    }
+
+    /// Returns whether a client connecting from `ip` matches the mask.
+    ///
+    /// Without an ident reply only masks with a `*` ident can match.
+    pub fn matches(&self, ident: Option<&str>, ip: IpAddr) -> bool {
+        let ident_matches = match ident {
+            Some(ident) => wildcard_match(&self.ident, ident),
+            None => self.ident == "*",
+        };
+        ident_matches && self.host_matches(ip)
+    }
+
+    fn host_matches(&self, ip: IpAddr) -> bool {
+        // An IPv4 client on a dual-stack socket shows up as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+
+        match &self.host {
+            HostMask::Cidr(network, prefix) => in_network(ip, *network, *prefix),
+            HostMask::Pattern(pattern) => wildcard_match(pattern, &ip.to_string()),
+        }
+    }
+}
+
+/// Returns whether any of `masks` lets the client in. Malformed masks are ignored.
+pub fn any_mask_matches<S: AsRef<str>>(masks: &[S], ident: Option<&str>, ip: IpAddr) -> bool {
+    masks
+        .iter()
+        .filter_map(|mask| IdentMask::parse(mask.as_ref()))
+        .any(|mask| mask.matches(ident, ip))
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Case-insensitive match of `text` against a pattern where `*` matches any
+/// sequence and `?` any single character.
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_wildcard_masks() {
+        let mask = IdentMask::parse("*@192.168.1.*").unwrap();
+        assert!(mask.matches(None, ip("192.168.1.42")));
+        assert!(mask.matches(Some("bob"), ip("192.168.1.42")));
+        assert!(!mask.matches(None, ip("192.168.2.42")));
+
+        let mask = IdentMask::parse("bob@*").unwrap();
+        assert!(mask.matches(Some("BOB"), ip("10.0.0.1")));
+        assert!(!mask.matches(Some("alice"), ip("10.0.0.1")));
+        assert!(!mask.matches(None, ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn test_cidr_masks() {
+        let mask = IdentMask::parse("*@10.1.0.0/16").unwrap();
+        assert!(mask.matches(None, ip("10.1.200.3")));
+        assert!(mask.matches(None, ip("::ffff:10.1.0.1")));
+        assert!(!mask.matches(None, ip("10.2.0.1")));
+
+        let mask = IdentMask::parse("*@2001:db8::/32").unwrap();
+        assert!(mask.matches(None, ip("2001:db8:1::5")));
+        assert!(!mask.matches(None, ip("2001:db9::5")));
+
+        assert!(IdentMask::parse("*@10.0.0.0/33").is_none());
+        assert!(IdentMask::parse("*@host/8").is_none());
+        assert!(IdentMask::parse("192.168.1.1").is_none());
+    }
+}
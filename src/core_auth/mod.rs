@@ -1,2 +1,14 @@
+pub mod acl;
+pub mod anonymous;
+pub mod backend;
+pub mod ban;
 pub mod core_auth;
+pub mod error;
+pub mod external_backend;
+pub mod hash;
 pub mod helper;
+pub mod mask;
+pub mod passwd_backend;
+pub mod password;
+
+pub use backend::{AuthBackend, AuthChain, Verification};
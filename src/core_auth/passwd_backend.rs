@@ -0,0 +1,270 @@
+use crate::core_auth::backend::{AuthBackend, AuthUser, Verification};
+use crate::core_auth::core_auth::PasswdEntry;
+use crate::core_auth::error::AuthError;
+use crate::core_auth::hash::HashAlgorithm;
+use crate::helpers::write_file_atomic;
+use async_trait::async_trait;
+use log::info;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+type PasswdMap = HashMap<String, PasswdEntry>;
+
+/// Serializes the read-modify-write cycles of the passwd files.
+static PASSWD_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Parses the content of a passwd file, skipping malformed lines.
+pub fn parse_passwd(content: &str) -> PasswdMap {
+    content
+        .lines()
+        .filter_map(PasswdEntry::from_line)
+        .map(|entry| (entry.get_username().to_string(), entry))
+        .collect()
+}
+
+async fn read_passwd(path: &str) -> Result<PasswdMap, AuthError> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| AuthError::PasswdReadError(path.to_string(), e))?;
+    Ok(parse_passwd(&content))
+}
+
+/// Rewrites the line of `username` in the passwd file at `path`.
+///
+/// `update` changes the entry and returns whether it did; the file is only
+/// written, atomically, when it did. Every other line is kept untouched.
+async fn rewrite_passwd_entry<F>(path: &str, username: &str, update: F) -> Result<bool, AuthError>
+where
+    F: FnOnce(&mut PasswdEntry) -> Result<bool, AuthError>,
+{
+    let _lock = PASSWD_LOCK.lock().await;
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| AuthError::PasswdReadError(path.to_string(), e))?;
+
+    let mut update = Some(update);
+    let mut updated = false;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        match PasswdEntry::from_line(line) {
+            Some(mut entry) if entry.get_username() == username && update.is_some() => {
+                let update = update.take().expect("checked above");
+                if update(&mut entry)? {
+                    lines.push(entry.to_line());
+                    updated = true;
+                } else {
+                    lines.push(line.to_string());
+                }
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    if updated {
+        let mut content = lines.join("\n");
+        content.push('\n');
+        write_file_atomic(Path::new(path), content.as_bytes())
+            .await
+            .map_err(|e| AuthError::PasswdWriteError(path.to_string(), e))?;
+    }
+    Ok(updated)
+}
+
+/// Replaces the hash of `username` when it is not already an `algorithm` hash.
+///
+/// The password is verified and hashed before the file is locked, the entry is
+/// left alone if its hash changed in the meantime.
+async fn upgrade_passwd_hash(
+    path: &str,
+    username: &str,
+    password: &str,
+    algorithm: HashAlgorithm,
+) -> Result<bool, AuthError> {
+    let passwd = read_passwd(path).await?;
+    let Some(entry) = passwd.get(username) else {
+        return Ok(false);
+    };
+    let current = HashAlgorithm::detect(entry.get_hashed_password());
+    if current == Some(algorithm) || !entry.verify_password(password).await {
+        return Ok(false);
+    }
+    let hash = algorithm
+        .hash_blocking(password)
+        .await
+        .ok_or(AuthError::HashError(algorithm))?;
+
+    rewrite_passwd_entry(path, username, |latest| {
+        if latest.get_hashed_password() != entry.get_hashed_password() {
+            return Ok(false);
+        }
+        latest.set_hashed_password(hash);
+        info!(
+            "Rehashed the password of {} from {:?} to {:?}",
+            username, current, algorithm
+        );
+        Ok(true)
+    })
+    .await
+}
+
+async fn set_passwd_hash(path: &str, username: &str, hash: &str) -> Result<bool, AuthError> {
+    rewrite_passwd_entry(path, username, |entry| {
+        entry.set_hashed_password(hash.to_string());
+        Ok(true)
+    })
+    .await
+}
+
+/// Appends an account to the passwd file, glftpd style.
+async fn add_passwd_user(path: &str, username: &str, hash: &str) -> Result<bool, AuthError> {
+    let _lock = PASSWD_LOCK.lock().await;
+    let mut content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| AuthError::PasswdReadError(path.to_string(), e))?;
+    if parse_passwd(&content).contains_key(username) {
+        return Ok(false);
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!(
+        "{}:{}:0:0:{}:/:/bin/false\n",
+        username,
+        hash,
+        chrono::Utc::now().timestamp()
+    ));
+    write_file_atomic(Path::new(path), content.as_bytes())
+        .await
+        .map_err(|e| AuthError::PasswdWriteError(path.to_string(), e))?;
+    info!("Added {} to {}", username, path);
+    Ok(true)
+}
+
+async fn verify_entry(entry: Option<&PasswdEntry>, password: &str) -> Verification {
+    match entry {
+        Some(entry) if entry.verify_password(password).await => Verification::Accepted,
+        Some(_) => Verification::Rejected,
+        None => Verification::UnknownUser,
+    }
+}
+
+/// The passwd file, read again for every request.
+pub struct PasswdFileBackend {
+    path: String,
+}
+
+impl PasswdFileBackend {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for PasswdFileBackend {
+    fn name(&self) -> &str {
+        "passwd"
+    }
+
+    async fn lookup(&self, username: &str) -> Result<Option<AuthUser>, AuthError> {
+        let passwd = read_passwd(&self.path).await?;
+        Ok(passwd.contains_key(username).then(|| AuthUser {
+            username: username.to_string(),
+            backend: self.name().to_string(),
+        }))
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verification, AuthError> {
+        let passwd = read_passwd(&self.path).await?;
+        Ok(verify_entry(passwd.get(username), password).await)
+    }
+
+    async fn upgrade_hash(
+        &self,
+        username: &str,
+        password: &str,
+        algorithm: HashAlgorithm,
+    ) -> Result<bool, AuthError> {
+        upgrade_passwd_hash(&self.path, username, password, algorithm).await
+    }
+
+    async fn set_password_hash(&self, username: &str, hash: &str) -> Result<bool, AuthError> {
+        set_passwd_hash(&self.path, username, hash).await
+    }
+
+    async fn add_user(&self, username: &str, hash: &str) -> Result<bool, AuthError> {
+        add_passwd_user(&self.path, username, hash).await
+    }
+}
+
+/// The passwd file kept in memory, read again only when its modification time
+/// changes.
+pub struct CachedPasswdBackend {
+    path: String,
+    cache: Mutex<Option<(SystemTime, Arc<PasswdMap>)>>,
+}
+
+impl CachedPasswdBackend {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            cache: Mutex::new(None),
+        }
+    }
+
+    async fn entries(&self) -> Result<Arc<PasswdMap>, AuthError> {
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| AuthError::PasswdReadError(self.path.clone(), e))?;
+
+        if let Some((cached_at, passwd)) = self.cache.lock().unwrap().as_ref() {
+            if *cached_at == modified {
+                return Ok(Arc::clone(passwd));
+            }
+        }
+
+        let passwd = Arc::new(read_passwd(&self.path).await?);
+        info!("Loaded {} account(s) from {}", passwd.len(), self.path);
+        *self.cache.lock().unwrap() = Some((modified, Arc::clone(&passwd)));
+        Ok(passwd)
+    }
+}
+
+#[async_trait]
+impl AuthBackend for CachedPasswdBackend {
+    fn name(&self) -> &str {
+        "cached"
+    }
+
+    async fn lookup(&self, username: &str) -> Result<Option<AuthUser>, AuthError> {
+        let passwd = self.entries().await?;
+        Ok(passwd.contains_key(username).then(|| AuthUser {
+            username: username.to_string(),
+            backend: self.name().to_string(),
+        }))
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verification, AuthError> {
+        let passwd = self.entries().await?;
+        Ok(verify_entry(passwd.get(username), password).await)
+    }
+
+    async fn upgrade_hash(
+        &self,
+        username: &str,
+        password: &str,
+        algorithm: HashAlgorithm,
+    ) -> Result<bool, AuthError> {
+        upgrade_passwd_hash(&self.path, username, password, algorithm).await
+    }
+
+    async fn set_password_hash(&self, username: &str, hash: &str) -> Result<bool, AuthError> {
+        set_passwd_hash(&self.path, username, hash).await
+    }
+
+    async fn add_user(&self, username: &str, hash: &str) -> Result<bool, AuthError> {
+        add_passwd_user(&self.path, username, hash).await
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::PasswordPolicyConfig;
+use crate::core_auth::backend::{AuthBackend, Verification};
+use crate::core_auth::error::{AuthError, PasswordError};
+use crate::core_auth::hash::verify_hash_blocking;
+use crate::helpers::write_file_atomic;
+use crate::state::ServerState;
+use crate::Config;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::sync::Mutex;
+
+/// Serializes the read-modify-write cycles of the password state file.
+static STATE_FILE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// What the server remembers about the password of a user.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct PasswordState {
+    /// Hashes of the latest passwords, newest first
+    #[serde(default)]
+    history: Vec<String>,
+    /// The user must change the password before doing anything else
+    #[serde(default)]
+    must_change: bool,
+}
+
+async fn load_states(path: &Path) -> HashMap<String, PasswordState> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            error!("Failed to parse password state file {:?}: {}", path, e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+async fn save_states(
+    path: &Path,
+    states: &HashMap<String, PasswordState>,
+) -> Result<(), PasswordError> {
+    let content = serde_json::to_string_pretty(states)
+        .map_err(|e| PasswordError::StateWriteError(std::io::Error::other(e)))?;
+    write_file_atomic(path, content.as_bytes())
+        .await
+        .map_err(PasswordError::StateWriteError)
+}
+
+/// Checks `password` against the length and character class rules.
+pub fn check_policy(policy: &PasswordPolicyConfig, password: &str) -> Result<(), PasswordError> {
+    if password.chars().count() < policy.min_length() {
+        return Err(PasswordError::TooShort(policy.min_length()));
+    }
+    if policy.require_lowercase.unwrap_or(false) && !password.chars().any(char::is_lowercase) {
+        return Err(PasswordError::MissingLowercase);
+    }
+    if policy.require_uppercase.unwrap_or(false) && !password.chars().any(char::is_uppercase) {
+        return Err(PasswordError::MissingUppercase);
+    }
+    if policy.require_digit.unwrap_or(false) && !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err(PasswordError::MissingDigit);
+    }
+    if policy.require_special.unwrap_or(false) && password.chars().all(char::is_alphanumeric) {
+        return Err(PasswordError::MissingSpecial);
+    }
+    Ok(())
+}
+
+/// Returns whether `username` has to change the password before going on.
+pub async fn must_change_password(config: &Config, username: &str) -> bool {
+    let states = load_states(&config.password_policy.state_file()).await;
+    states
+        .get(username)
+        .map(|state| state.must_change)
+        .unwrap_or(false)
+}
+
+/// Changes the password of `username` through the authentication backends.
+///
+/// The new password is checked against the policy, the current password and the
+/// history, hashed with the preferred algorithm, then stored by the backend
+/// holding the account.
+///
+/// # Arguments
+///
+/// * `state` - The shared server state, holding the authentication backends.
+/// * `config` - The configuration with the policy and the hash algorithm.
+/// * `username` - The account to change.
+/// * `password` - The new password, in clear.
+/// * `must_change` - Whether the user has to change it again on next login.
+pub async fn change_password(
+    state: &ServerState,
+    config: &Config,
+    username: &str,
+    password: &str,
+    must_change: bool,
+) -> Result<(), PasswordError> {
+    let policy = &config.password_policy;
+    check_policy(policy, password)?;
+
+    let auth = state.auth();
+    if auth.lookup(username).await?.is_none() {
+        return Err(PasswordError::UserNotFound(username.to_string()));
+    }
+
+    let _lock = STATE_FILE_LOCK.lock().await;
+    let state_file = policy.state_file();
+    let mut states = load_states(&state_file).await;
+    let user_state = states.entry(username.to_string()).or_default();
+
+    // The current password is refused even without history, or a forced
+    // change could be cleared by setting the same password again
+    let is_current = auth.verify(username, password).await? == Verification::Accepted;
+    let mut in_history = false;
+    for hash in user_state.history.iter().take(policy.history()) {
+        if verify_hash_blocking(password, hash).await {
+            in_history = true;
+            break;
+        }
+    }
+    if is_current || in_history {
+        return Err(PasswordError::Reused);
+    }
+
+    let algorithm = config.auth.preferred_hash();
+    let hash = algorithm
+        .hash_blocking(password)
+        .await
+        .ok_or(AuthError::HashError(algorithm))?;
+    if !auth.set_password_hash(username, &hash).await? {
+        return Err(PasswordError::Unsupported(username.to_string()));
+    }
+    info!("Password of {} changed", username);
+
+    user_state.history.insert(0, hash);
+    user_state.history.truncate(policy.history());
+    user_state.must_change = must_change;
+    if user_state.history.is_empty() && !user_state.must_change {
+        states.remove(username);
+    }
+    save_states(&state_file, &states).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_policy() {
+        let policy = PasswordPolicyConfig {
+            min_length: Some(8),
+            require_uppercase: Some(true),
+            require_digit: Some(true),
+            require_special: Some(true),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            check_policy(&policy, "Sh0rt!"),
+            Err(PasswordError::TooShort(8))
+        ));
+        assert!(matches!(
+            check_policy(&policy, "lowercase1!"),
+            Err(PasswordError::MissingUppercase)
+        ));
+        assert!(matches!(
+            check_policy(&policy, "NoDigitsHere!"),
+            Err(PasswordError::MissingDigit)
+        ));
+        assert!(matches!(
+            check_policy(&policy, "NoSpecial123"),
+            Err(PasswordError::MissingSpecial)
+        ));
+        assert!(check_policy(&policy, "C0rrect-Horse").is_ok());
+    }
+}
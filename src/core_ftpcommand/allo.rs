@@ -40,34 +40,176 @@ pub async fn handle_allo_command(
     Ok(())
 }*/
 
+use crate::core_quota::manager::QuotaManager;
+use crate::helpers::send_response;
 use crate::session::Session;
 use crate::Config;
 use anyhow::Result;
-use log::{error, info};
+use log::{info, warn};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex as TokioMutex;
 
+/// Handles the ALLO (Allocate) FTP command.
+///
+/// `ALLO <size> [R <record size>]` announces the size of the next upload. With
+/// quotas enabled the size is reserved on the user's quota right away and kept
+/// in the session for the next STOR, so an upload that cannot fit is refused
+/// early and parallel uploads cannot take the space in between.
+///
+/// # Arguments
+///
+/// * `writer` - A shared, locked TCP stream for writing responses to the client.
+/// * `config` - A shared server configuration.
+/// * `session` - A shared, locked session, keeping the reservation for the next STOR.
+/// * `arg` - The number of bytes to allocate.
+/// * `quota_manager` - The quota manager, if quotas are enabled.
+///
+/// # Returns
+///
+/// Result<(), std::io::Error> indicating the success or failure of the operation.
 pub async fn handle_allo_command(
     writer: Arc<TokioMutex<TcpStream>>,
     _config: Arc<Config>,
-    _session: Arc<TokioMutex<Session>>,
-    _arg: String,
+    session: Arc<TokioMutex<Session>>,
+    arg: String,
+    quota_manager: Option<Arc<QuotaManager>>,
 ) -> Result<(), std::io::Error> {
     // Log the received ALLO command.
-    info!("Received ALLO command with argument: {}", _arg);
+    info!("Received ALLO command with argument: {}", arg);
 
-    // Define the success response message.
-    let response = "200 ALLO command successful.\r\n";
+    let Some(size) = arg
+        .split_whitespace()
+        .next()
+        .and_then(|size| size.parse::<u64>().ok())
+    else {
+        send_response(&writer, b"501 Syntax error in parameters or arguments.\r\n").await?;
+        return Ok(());
+    };
 
-    // Lock the writer to send the response.
-    let mut writer = writer.lock().await;
-    if let Err(e) = writer.write_all(response.as_bytes()).await {
-        error!("Failed to send ALLO response: {}", e);
-        return Err(e);
+    // A new ALLO replaces the previous one, give its reservation back first
+    let (username, base_dir) = {
+        let mut session = session.lock().await;
+        session.allocated = None;
+        session.allocation = None;
+        (session.username.clone(), session.base_path.clone())
+    };
+
+    let mut allocation = None;
+    if let (Some(quota_mgr), Some(username)) = (&quota_manager, username) {
+        let mut reservation = quota_mgr.start_upload(&username);
+        if let Err(e) = quota_mgr
+            .reserve_allocation(&mut reservation, base_dir, size)
+            .await
+        {
+            warn!("ALLO {} refused for user {}: {}", size, username, e);
+            send_response(&writer, format!("{}\r\n", e.to_ftp_response()).as_bytes()).await?;
+            return Ok(());
+        }
+        allocation = Some(reservation);
+    }
+
+    {
+        let mut session = session.lock().await;
+        session.allocated = Some(size);
+        session.allocation = allocation;
     }
+    send_response(&writer, b"200 ALLO command successful.\r\n").await?;
 
     info!("Sent ALLO success response.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_quota::config::{GroupQuotaConfig, QuotaConfig, UserQuotaConfig};
+    use std::path::PathBuf;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    const MB: u64 = 1024 * 1024;
+
+    /// Both ends of a control connection, the server one shared like the
+    /// handlers get it.
+    async fn control_connection() -> (Arc<TokioMutex<TcpStream>>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (Arc::new(TokioMutex::new(server)), client)
+    }
+
+    async fn reply(client: &mut TcpStream) -> String {
+        let mut buffer = [0; 512];
+        let n = client.read(&mut buffer).await.unwrap();
+        String::from_utf8_lossy(&buffer[..n]).into_owned()
+    }
+
+    fn session(username: &str) -> Arc<TokioMutex<Session>> {
+        let mut session = Session::new(PathBuf::from("/"));
+        session.username = Some(username.to_string());
+        Arc::new(TokioMutex::new(session))
+    }
+
+    #[tokio::test]
+    async fn test_allo_reserves_the_quota() {
+        let config: Arc<Config> = Arc::new(
+            toml::from_str(
+                r#"
+                [server]
+                listen_port = 2121
+                pasv_address = "127.0.0.1"
+                ipc_key = "0x0000BEEF"
+                chroot_dir = "/tmp"
+                min_homedir = "site"
+                passwd_file = "/tmp/passwd"
+                "#,
+            )
+            .unwrap(),
+        );
+        let quota_config = QuotaConfig {
+            default_quota: 3 * MB,
+            ..QuotaConfig::default()
+        };
+        let quota_mgr = Arc::new(QuotaManager::new(
+            quota_config,
+            GroupQuotaConfig::new(),
+            UserQuotaConfig::new(),
+        ));
+        let (writer, mut client) = control_connection().await;
+        let first = session("alice");
+        let second = session("alice");
+
+        // The announced size is reserved until the next STOR
+        let allo = |session: &Arc<TokioMutex<Session>>, size: u64| {
+            handle_allo_command(
+                writer.clone(),
+                config.clone(),
+                session.clone(),
+                size.to_string(),
+                Some(quota_mgr.clone()),
+            )
+        };
+        allo(&first, 2 * MB).await.unwrap();
+        assert!(reply(&mut client).await.starts_with("200"));
+        {
+            let first = first.lock().await;
+            assert_eq!(first.allocated, Some(2 * MB));
+            let allocation = first.allocation.as_ref().unwrap();
+            assert_eq!(allocation.granted(), Some(2 * MB));
+        }
+
+        // Another connection of the user cannot take it in the meantime
+        allo(&second, 2 * MB).await.unwrap();
+        assert!(reply(&mut client).await.starts_with("552"));
+        assert!(second.lock().await.allocation.is_none());
+
+        // A new ALLO gives the previous reservation back
+        allo(&first, MB).await.unwrap();
+        assert!(reply(&mut client).await.starts_with("200"));
+        allo(&second, 2 * MB).await.unwrap();
+        assert!(reply(&mut client).await.starts_with("200"));
+    }
+}
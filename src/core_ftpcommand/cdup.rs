@@ -1,4 +1,5 @@
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use log::{error, info, warn};
 use std::path::{Path, PathBuf};
@@ -12,6 +13,7 @@ pub async fn handle_cdup_command(
     _config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     _arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     let mut session = session.lock().await;
     let current_dir = &session.current_dir;
@@ -54,6 +56,9 @@ pub async fn handle_cdup_command(
         } else {
             session.current_dir = format!("/{}", session.current_dir);
         }
+        state
+            .sessions()
+            .set_current_dir(session.id, &session.current_dir);
         info!("Directory successfully changed to: {}", session.current_dir);
         let mut writer = writer.lock().await;
         writer
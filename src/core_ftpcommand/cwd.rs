@@ -1,5 +1,7 @@
+use crate::core_auth::acl::{self, AclUser};
 use crate::helpers::generate_and_send_statline;
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use log::{debug, error, info, warn};
 use std::path::{Component, PathBuf};
@@ -10,9 +12,10 @@ use tokio::sync::Mutex;
 
 pub async fn handle_cwd_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     let mut session = session.lock().await;
     let sanitized_arg = arg.replace("\"", "\\\"");
@@ -38,6 +41,16 @@ pub async fn handle_cwd_command(
         acc
     });
 
+    // Private paths look like missing ones to the users they are closed to
+    if acl::is_private(&config.acl, &AclUser::from(&*session), &new_dir_normalized) {
+        warn!("Refused to enter private path: {:?}", new_dir_normalized);
+        let mut writer = writer.lock().await;
+        writer
+            .write_all(b"550 Failed to change directory.\r\n")
+            .await?;
+        return Ok(());
+    }
+
     // Construct the full path within the chroot environment
     let full_path = session.base_path.join(
         new_dir_normalized
@@ -70,6 +83,9 @@ pub async fn handle_cwd_command(
                 .to_str()
                 .unwrap()
         );
+        state
+            .sessions()
+            .set_current_dir(session.id, &session.current_dir);
         info!("Directory successfully changed to: {}", session.current_dir);
         let mut writer = writer.lock().await;
         writer
@@ -1,5 +1,8 @@
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::core_quota::dir_quota::DirUsage;
 use crate::helpers::{sanitize_input, send_response};
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use log::{error, info, warn};
@@ -21,6 +24,7 @@ use tokio::sync::Mutex;
 /// * `config` - A shared server configuration.
 /// * `session` - A shared, locked session containing the user's current state.
 /// * `arg` - The file name to delete.
+/// * `state` - The shared server state, holding the owners of the files and the quotas.
 ///
 /// # Returns
 ///
@@ -30,27 +34,37 @@ pub async fn handle_dele_command(
     config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     // Sanitize the input argument to prevent directory traversal attacks.
     let sanitized_arg = sanitize_input(&arg);
     info!("Received DELE command with argument: {}", sanitized_arg);
 
     // Construct the file path within the user's current directory.
-    let file_path = {
+    let (file_path, owner, allowed) = {
         // Lock the session to get the current directory.
         let session = session.lock().await;
-        let mut current_dir = session.current_dir.clone();
+        let site_path = session.site_path(&sanitized_arg);
+        let file_path = session.real_path(&sanitized_arg);
 
-        // Remove leading slash from current_dir if present
-        if current_dir.starts_with('/') {
-            current_dir = current_dir.trim_start_matches('/').to_string();
-        }
-
-        // Join the base_path, current_dir, and sanitized_arg to construct the full path.
-        session.base_path.join(current_dir).join(&sanitized_arg)
+        let owner = state.owners().owner_of(&file_path);
+        let allowed = acl::may_modify(
+            &config.acl,
+            PathPermission::Delete,
+            &AclUser::from(&*session),
+            &site_path,
+            owner.as_deref(),
+        );
+        (file_path, owner, allowed)
     };
     info!("Constructed file path: {:?}", file_path);
 
+    if !allowed {
+        warn!("DELE refused by the delete rules: {:?}", file_path);
+        send_response(&writer, b"550 Permission denied.\r\n").await?;
+        return Ok(());
+    }
+
     // Canonicalize the chroot directory to resolve any symbolic links or relative paths.
     let chroot_dir = PathBuf::from(&config.server.chroot_dir).canonicalize()?;
     // Canonicalize the file path to ensure it's within the chroot directory.
@@ -75,11 +89,27 @@ pub async fn handle_dele_command(
         return Ok(());
     }
 
+    // Remember the size of the file to credit it back to its uploader's quota.
+    let size = fs::metadata(&resolved_path).await.map(|m| m.len()).ok();
+
     // Attempt to delete the file.
     match fs::remove_file(&resolved_path).await {
         Ok(_) => {
             // Send success response if the file was deleted successfully.
             info!("File deleted successfully: {:?}", resolved_path);
+            state.owners().remove(&file_path);
+            if let Some(size) = size {
+                state
+                    .dir_quotas()
+                    .record_remove(&file_path, DirUsage::file(size));
+            }
+            if let (Some(quota_manager), Some(owner), Some(size)) =
+                (state.quota_manager(), &owner, size)
+            {
+                if let Err(e) = quota_manager.record_delete(owner, size).await {
+                    error!("Failed to update quota for user {}: {}", owner, e);
+                }
+            }
             send_response(
                 &writer,
                 format!("250 \"{}\" file deleted.\r\n", sanitized_arg).as_bytes(),
@@ -95,3 +125,96 @@ pub async fn handle_dele_command(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_quota::config::{GroupQuotaConfig, QuotaConfig, UserQuotaConfig};
+    use crate::core_quota::manager::QuotaManager;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    /// Both ends of a control connection, the server one shared like the
+    /// handlers get it.
+    async fn control_connection() -> (Arc<Mutex<TcpStream>>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (Arc::new(Mutex::new(server)), client)
+    }
+
+    #[tokio::test]
+    async fn test_dele_credits_the_uploader() {
+        let dir = tempfile::tempdir().unwrap();
+        let chroot = dir.path().canonicalize().unwrap();
+        let site = chroot.join("site");
+        std::fs::create_dir(&site).unwrap();
+        let file_path = site.join("release.bin");
+        std::fs::write(&file_path, vec![0; 1000]).unwrap();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [server]
+            listen_port = 2121
+            pasv_address = "127.0.0.1"
+            ipc_key = "0x0000BEEF"
+            chroot_dir = "{}"
+            min_homedir = "site"
+            passwd_file = "{}/passwd"
+            "#,
+            chroot.display(),
+            chroot.display()
+        ))
+        .unwrap();
+        let quota_config = QuotaConfig {
+            quota_storage_file: chroot.join("quotas.json"),
+            ratio_storage_file: chroot.join("ratios.json"),
+            stats_storage_file: chroot.join("stats.json"),
+            ..QuotaConfig::default()
+        };
+        let quota_mgr = Arc::new(QuotaManager::new(
+            quota_config,
+            GroupQuotaConfig::new(),
+            UserQuotaConfig::new(),
+        ));
+        let state = Arc::new(ServerState::new(
+            config,
+            String::new(),
+            Some(quota_mgr.clone()),
+        ));
+
+        // alice uploaded the file, bob deletes it
+        state.owners().set_owner(&file_path, "alice");
+        quota_mgr
+            .get_or_create_user_quota("alice", site.clone())
+            .await
+            .unwrap();
+        quota_mgr.record_upload("alice", 1000).await.unwrap();
+        let mut session = Session::new(site.clone());
+        session.username = Some("bob".to_string());
+
+        let (writer, mut client) = control_connection().await;
+        handle_dele_command(
+            writer,
+            state.config(),
+            Arc::new(Mutex::new(session)),
+            "release.bin".to_string(),
+            state.clone(),
+        )
+        .await
+        .unwrap();
+
+        let mut reply = [0; 512];
+        let n = client.read(&mut reply).await.unwrap();
+        assert!(reply[..n].starts_with(b"250"));
+        assert!(!file_path.exists());
+        assert_eq!(state.owners().owner_of(&file_path), None);
+        let quota = quota_mgr
+            .get_or_create_user_quota("alice", site)
+            .await
+            .unwrap();
+        assert_eq!(quota.used_bytes, 0);
+    }
+}
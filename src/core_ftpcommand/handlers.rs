@@ -1,6 +1,7 @@
 use crate::core_ftpcommand::ftpcommand::FtpCommand;
 use crate::core_quota::manager::QuotaManager;
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use std::collections::HashMap;
@@ -22,6 +23,7 @@ type CommandHandler = Box<
             String,                             // Full command string
             Option<Arc<TokioMutex<TcpStream>>>, // Optional data stream
             Option<Arc<QuotaManager>>,          // Optional quota manager
+            Arc<ServerState>,                   // Shared server state
         ) -> Pin<Box<dyn Future<Output = Result<(), std::io::Error>> + Send>>
         + Send
         + Sync,
@@ -33,7 +35,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::MDTM,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::mdtm::handle_mdtm_command(
                     writer, config, session, arg,
                 ))
@@ -44,7 +46,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::SIZE,
         Arc::new(Box::new(
-            |writer, config, session, arg, data_stream, _quota_manager| {
+            |writer, config, session, arg, data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::size::handle_size_command(
                     writer,
                     config,
@@ -59,9 +61,13 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::ALLO,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::allo::handle_allo_command(
-                    writer, config, session, arg,
+                    writer,
+                    config,
+                    session,
+                    arg,
+                    quota_manager,
                 ))
             },
         )),
@@ -70,9 +76,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::CDUP,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::cdup::handle_cdup_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -81,7 +87,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::FEAT,
         Arc::new(Box::new(
-            |writer, _config, _session, arg, _data_stream, _quota_manager| {
+            |writer, _config, _session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::feat::handle_feat_command(
                     writer, arg,
                 ))
@@ -92,7 +98,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::SYST,
         Arc::new(Box::new(
-            |writer, _config, _session, _arg, _data_stream, _quota_manager| {
+            |writer, _config, _session, _arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::syst::handle_syst_command(writer))
             },
         )),
@@ -101,13 +107,14 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::SITE,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, quota_manager| {
+            |writer, config, session, arg, _data_stream, quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::site::handle_site_command(
                     writer,
                     config,
                     session,
                     arg,
                     quota_manager,
+                    state,
                 ))
             },
         )),
@@ -116,9 +123,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::USER,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::user::handle_user_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -127,9 +134,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::PASS,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::pass::handle_pass_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -138,7 +145,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::QUIT,
         Arc::new(Box::new(
-            |writer, config, _session, arg, _data_stream, _quota_manager| {
+            |writer, config, _session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::quit::handle_quit_command(
                     writer,
                     config,
@@ -151,7 +158,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::PWD,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::pwd::handle_pwd_command(
                     writer, config, session, arg,
                 ))
@@ -162,7 +169,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::LIST,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::list::handle_list_command(
                     writer, config, session, arg,
                 ))
@@ -173,9 +180,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::CWD,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::cwd::handle_cwd_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -184,7 +191,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::NOOP,
         Arc::new(Box::new(
-            |writer, config, _session, arg, _data_stream, _quota_manager| {
+            |writer, config, _session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::noop::handle_noop_command(
                     writer,
                     config,
@@ -197,9 +204,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::MKD,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::mkd::handle_mkd_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -208,9 +215,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::RMD,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::rmd::handle_rmd_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -219,9 +226,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::DELE,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::dele::handle_dele_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -230,9 +237,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::RNFR,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::rnfr::handle_rnfr_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -241,9 +248,9 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::RNTO,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, state| {
                 Box::pin(crate::core_ftpcommand::rnto::handle_rnto_command(
-                    writer, config, session, arg,
+                    writer, config, session, arg, state,
                 ))
             },
         )),
@@ -252,7 +259,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::RETR,
         Arc::new(Box::new(
-            |writer, config, session, arg, data_stream, quota_manager| {
+            |writer, config, session, arg, data_stream, quota_manager, state| {
                 Box::pin(async move {
                     crate::core_ftpcommand::retr::handle_retr_command(
                         writer,
@@ -261,6 +268,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
                         arg,
                         data_stream,
                         quota_manager,
+                        state,
                     )
                     .await?;
                     Ok(())
@@ -272,7 +280,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::STOR,
         Arc::new(Box::new(
-            |writer, config, session, arg, data_stream, quota_manager| {
+            |writer, config, session, arg, data_stream, quota_manager, state| {
                 Box::pin(async move {
                     crate::core_ftpcommand::stor::handle_stor_command(
                         writer,
@@ -281,6 +289,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
                         arg,
                         data_stream,
                         quota_manager,
+                        state,
                     )
                     .await?;
                     Ok(())
@@ -291,7 +300,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::TYPE,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(crate::core_ftpcommand::type_::handle_type_command(
                     writer, config, session, arg,
                 ))
@@ -302,7 +311,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::PASV,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(pasv::handle_pasv_command(writer, config, session, arg))
             },
         )),
@@ -311,7 +320,7 @@ pub fn initialize_command_handlers() -> HashMap<FtpCommand, Arc<CommandHandler>>
     handlers.insert(
         FtpCommand::PORT,
         Arc::new(Box::new(
-            |writer, config, session, arg, _data_stream, _quota_manager| {
+            |writer, config, session, arg, _data_stream, _quota_manager, _state| {
                 Box::pin(port::handle_port_command(writer, config, session, arg))
             },
         )),
@@ -1,3 +1,4 @@
+use crate::core_auth::acl::{self, AclUser};
 use crate::session::Session;
 use crate::Config;
 use log::{debug, error, info, warn};
@@ -50,6 +51,18 @@ pub async fn handle_list_command(
         return Ok(());
     }
 
+    // Private paths look like missing ones to the users they are closed to
+    let site_dir = session_lock.site_path("");
+    let user = AclUser::from(&*session_lock);
+    if acl::is_private(&config.acl, &user, &site_dir) {
+        warn!("Directory listing refused for private path: {:?}", site_dir);
+        let mut writer = writer.lock().await;
+        writer
+            .write_all(b"550 Failed to list directory.\r\n")
+            .await?;
+        return Ok(());
+    }
+
     let entries = match fs::read_dir(&canonical_dir_path) {
         Ok(entries) => entries,
         Err(e) => {
@@ -87,6 +100,9 @@ pub async fn handle_list_command(
             let date = "Jan 01 00:00";
 
             let file_name = entry.file_name().into_string().unwrap_or_default();
+            if acl::is_private(&config.acl, &user, &site_dir.join(&file_name)) {
+                continue;
+            }
             let file_entry = format!(
                 "{}rwxr-xr-x 1 {} {} {} {} {}\r\n",
                 file_type, owner, group, size, date, file_name
@@ -1,5 +1,8 @@
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::core_quota::dir_quota::DirUsage;
 use crate::helpers::{sanitize_input, send_response};
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use log::{error, info, warn};
@@ -20,37 +23,48 @@ use tokio::sync::Mutex;
 /// * `config` - A shared server configuration.
 /// * `session` - A shared, locked session containing the user's current state.
 /// * `arg` - The directory name to create.
+/// * `state` - The shared server state, recording who created the directory.
 ///
 /// # Returns
 ///
 /// Result<(), std::io::Error> indicating the success or failure of the operation.
 pub async fn handle_mkd_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     // Sanitize the input argument to prevent directory traversal attacks.
     let sanitized_arg = sanitize_input(&arg);
     info!("Received MKD command with argument: {}", sanitized_arg);
 
     // Construct the new directory path within the user's current directory.
-    let dir_path = {
+    let (dir_path, username, allowed) = {
         // Lock the session to get the current directory.
         let session = session.lock().await;
-        let base_path = session.base_path.clone();
-        let current_dir = session.current_dir.clone();
-        let dir_path = base_path
-            .join(current_dir.trim_start_matches('/'))
-            .join(&sanitized_arg);
+        let site_path = session.site_path(&sanitized_arg);
+        let dir_path = session.real_path(&sanitized_arg);
 
-        info!("base_path: {:?}", base_path);
-        info!("current_dir: {:?}", current_dir);
+        info!("base_path: {:?}", session.base_path);
+        info!("current_dir: {:?}", session.current_dir);
         info!("dir_path: {:?}", dir_path);
 
-        dir_path
+        let allowed = acl::is_allowed(
+            &config.acl,
+            PathPermission::MakeDir,
+            &AclUser::from(&*session),
+            &site_path,
+        );
+        (dir_path, session.username.clone(), allowed)
     };
 
+    if !allowed {
+        warn!("MKD refused by the makedir rules: {:?}", dir_path);
+        send_response(&writer, b"550 Permission denied.\r\n").await?;
+        return Ok(());
+    }
+
     // Log the constructed directory path
     info!("Constructed directory path: {:?}", dir_path);
 
@@ -74,11 +88,29 @@ pub async fn handle_mkd_command(
         return Ok(());
     }
 
+    // Check the file limit of the directory quotas, counting each missing parent.
+    let created = DirUsage {
+        bytes: 0,
+        files: dir_path
+            .ancestors()
+            .take_while(|path| !path.exists())
+            .count() as u64,
+    };
+    if let Err(e) = state.dir_quotas().check(&dir_path, created) {
+        warn!("MKD of {:?} refused: {}", dir_path, e);
+        send_response(&writer, format!("{}\r\n", e.to_ftp_response()).as_bytes()).await?;
+        return Ok(());
+    }
+
     // Attempt to create the directory.
     match fs::create_dir_all(&dir_path).await {
         Ok(_) => {
             // Send success response if the directory was created successfully.
             info!("Directory created successfully: {:?}", dir_path);
+            state.dir_quotas().record_add(&dir_path, created);
+            if let Some(username) = &username {
+                state.owners().set_owner(&dir_path, username);
+            }
             send_response(
                 &writer,
                 format!("257 \"{}\" directory created.\r\n", sanitized_arg).as_bytes(),
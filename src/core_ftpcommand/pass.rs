@@ -1,7 +1,10 @@
-use crate::core_auth::helper::load_passwd_file;
-use crate::session::Session;
+use crate::constants::EXEMPT;
+use crate::core_auth::anonymous::{is_anonymous_username, is_valid_email};
+use crate::core_auth::password::must_change_password;
+use crate::core_auth::{AuthBackend, Verification};
+use crate::session::{LoginLimits, Session};
+use crate::state::ServerState;
 use crate::Config;
-use bcrypt::verify;
 use colored::*;
 use log::{error, info, warn};
 use std::sync::Arc;
@@ -16,8 +19,10 @@ use tokio::sync::Mutex;
 /// # Arguments
 ///
 /// * `writer` - A shared, locked TCP stream for writing responses to the client.
-/// * `_config` - A shared server configuration (not used in this command).
-/// * `_password` - The password provided by the user.
+/// * `config` - A shared server configuration.
+/// * `session` - The current session.
+/// * `password` - The password provided by the user.
+/// * `state` - The shared server state, used to enforce the login limits.
 ///
 /// # Returns
 ///
@@ -27,6 +32,7 @@ pub async fn handle_pass_command(
     config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     password: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Received PASS command. Authenticating user.");
 
@@ -35,39 +41,125 @@ pub async fn handle_pass_command(
         session.username.clone()
     };
 
-    let passwd_file_path = config.server.passwd_file.clone();
-    let passwd_map = load_passwd_file(&passwd_file_path).await;
+    let (session_id, peer_addr) = {
+        let session = session.lock().await;
+        (session.id, session.peer_addr)
+    };
 
-    let response: &[u8] = if let Some(username) = username {
-        if username.to_lowercase() == "anonymous" {
+    // The IP may have been banned while this connection was open
+    let ban = peer_addr.and_then(|peer_addr| state.bans().ban_of(peer_addr.ip()));
+
+    let response: Vec<u8> = if let Some(ban) = ban {
+        warn!("PASS refused from a banned IP: {}", ban.reason);
+        session.lock().await.close_requested = true;
+        b"421 Your IP is temporarily banned.\r\n".to_vec()
+    } else if let Some(username) = username {
+        if is_anonymous_username(&username) {
             info!("Anonymous login with password: {}", password.yellow());
-            b"230 Anonymous user logged in, proceed.\r\n"
-        } else if let Some(entry) = passwd_map.get(&username) {
-            if verify(&password, &entry.get_hashed_password()).unwrap_or(false) {
-                {
-                    let mut session = session.lock().await;
-                    session.is_authenticated = true;
-                }
-                info!("User {} authenticated successfully.", username.cyan());
-                b"230 User logged in, proceed.\r\n"
+            if config.anonymous.require_email_password() && !is_valid_email(&password) {
+                warn!("Anonymous login refused, not an e-mail address: {}", password);
+                b"530 Login incorrect, send your complete e-mail address as password.\r\n"
+                    .to_vec()
             } else {
-                warn!("Authentication failed for user {}.", username.magenta());
-                b"530 Login incorrect.\r\n"
+                anonymous_login(&config, &session, &state, &username).await
             }
         } else {
-            warn!("User {} not found in passwd file.", username.magenta());
-            b"530 Login incorrect.\r\n"
+            match state.auth().verify(&username, &password).await {
+                Ok(Verification::Accepted) => {
+                    if let Some(peer_addr) = peer_addr {
+                        state.bans().record_success(peer_addr.ip(), &username);
+                    }
+                    let user = match state.users().get(&username).await {
+                        Ok(user) => user.unwrap_or_default(),
+                        Err(e) => {
+                            error!("Could not read the userfile of {}: {}", username, e);
+                            Default::default()
+                        }
+                    };
+                    let positive = |value: i64| (value > 0).then_some(value as u64);
+                    let idle_time = positive(user.general.idle_time);
+                    let max_download_speed = positive(user.general.max_download_speed);
+                    let max_upload_speed = positive(user.general.max_upload_speed);
+                    let groups = user.group_names();
+                    let login_limits = user.login_limits();
+                    let flags = user.flags();
+                    let credits = user.credits_bytes();
+                    let exempt = flags.contains(&EXEMPT);
+
+                    match state.sessions().log_in(
+                        session_id,
+                        &username,
+                        false,
+                        exempt,
+                        &config,
+                        &login_limits,
+                    ) {
+                        Ok(()) => {
+                            let must_change = must_change_password(&config, &username).await;
+                            let quota_warning = match state.quota_manager() {
+                                Some(quota_manager) => {
+                                    quota_manager.soft_quota_warning(&username).await
+                                }
+                                None => None,
+                            };
+                            let ratio = state.ratio_display(&username).await;
+                            let mut session = session.lock().await;
+                            session.is_authenticated = true;
+                            session.must_change_password = must_change;
+                            session.flags = flags;
+                            session.idle_timeout = idle_time;
+                            session.login_limits = login_limits;
+                            session.max_download_speed = max_download_speed;
+                            session.max_upload_speed = max_upload_speed;
+                            session.groups = groups;
+                            session.credits = credits;
+                            session.ratio = ratio;
+                            info!("User {} authenticated successfully.", username.cyan());
+                            if config.auth.rehash_on_login() {
+                                upgrade_hash(&state, &config, &username, &password);
+                            }
+                            let mut response = String::new();
+                            if must_change {
+                                response.push_str("230-Your password must be changed, use SITE PASSWD <new password>.\r\n");
+                            }
+                            if let Some(warning) = quota_warning {
+                                response.push_str(&format!("230-{}\r\n", warning));
+                            }
+                            response.push_str("230 User logged in, proceed.\r\n");
+                            response.into_bytes()
+                        }
+                        Err(limit) => {
+                            warn!("Login refused for user {}: {:?}", username.magenta(), limit);
+                            session.lock().await.close_requested = limit.closes_connection();
+                            limit.to_ftp_response(&config.limits).into_bytes()
+                        }
+                    }
+                }
+                Ok(Verification::Rejected) => {
+                    warn!("Authentication failed for user {}.", username.magenta());
+                    login_failed(&config, &session, &state, Some(&username)).await
+                }
+                Ok(Verification::UnknownUser) => {
+                    warn!("User {} not found by any auth backend.", username.magenta());
+                    login_failed(&config, &session, &state, None).await
+                }
+                Err(e) => {
+                    error!("Could not authenticate user {}: {}", username, e);
+                    session.lock().await.close_requested = true;
+                    b"421 Authentication service unavailable, try again later.\r\n".to_vec()
+                }
+            }
         }
     } else {
         warn!(
             "{}",
             "PASS command received without a preceding USER command.".magenta()
         );
-        b"503 Bad sequence of commands.\r\n"
+        b"503 Bad sequence of commands.\r\n".to_vec()
     };
 
     let mut writer = writer.lock().await;
-    if let Err(e) = writer.write_all(response).await {
+    if let Err(e) = writer.write_all(&response).await {
         error!("Failed to send PASS response: {}", e.to_string().red());
         return Err(e);
     }
@@ -75,3 +167,98 @@ pub async fn handle_pass_command(
     info!("Sent PASS response.");
     Ok(())
 }
+
+/// Logs an anonymous user in, jailed in the anonymous root.
+async fn anonymous_login(
+    config: &Config,
+    session: &Arc<Mutex<Session>>,
+    state: &ServerState,
+    username: &str,
+) -> Vec<u8> {
+    let root = match config.anonymous_root().canonicalize() {
+        Ok(root) => root,
+        Err(e) => {
+            error!("Anonymous root {:?} unavailable: {}", config.anonymous_root(), e);
+            return b"530 Anonymous access is unavailable.\r\n".to_vec();
+        }
+    };
+
+    let session_id = session.lock().await.id;
+    match state.sessions().log_in(
+        session_id,
+        username,
+        true,
+        false,
+        config,
+        &LoginLimits::default(),
+    ) {
+        Ok(()) => {
+            let mut session = session.lock().await;
+            session.is_authenticated = true;
+            session.is_anonymous = true;
+            session.base_path = root;
+            session.current_dir = "/".to_string();
+            b"230 Anonymous user logged in, proceed.\r\n".to_vec()
+        }
+        Err(limit) => {
+            warn!("Anonymous login refused: {:?}", limit);
+            session.lock().await.close_requested = limit.closes_connection();
+            limit.to_ftp_response(&config.limits).into_bytes()
+        }
+    }
+}
+
+/// Records a failed login and builds the reply.
+///
+/// The reply is delayed according to the number of recent failures of the IP
+/// and the account, `None` when it does not exist (tarpitting). When the IP
+/// gets banned, the reply is a 421 and the control connection is closed.
+async fn login_failed(
+    config: &Config,
+    session: &Arc<Mutex<Session>>,
+    state: &ServerState,
+    username: Option<&str>,
+) -> Vec<u8> {
+    let peer_addr = session.lock().await.peer_addr;
+    let Some(peer_addr) = peer_addr else {
+        return b"530 Login incorrect.\r\n".to_vec();
+    };
+
+    let failure = state
+        .bans()
+        .record_failure(peer_addr.ip(), username, &config.bans)
+        .await;
+    if !failure.delay.is_zero() {
+        info!(
+            "Delaying PASS reply to {} by {} ms",
+            peer_addr.ip(),
+            failure.delay.as_millis()
+        );
+        tokio::time::sleep(failure.delay).await;
+    }
+
+    if failure.banned {
+        session.lock().await.close_requested = true;
+        b"421 Too many failed logins, your IP is temporarily banned.\r\n".to_vec()
+    } else {
+        b"530 Login incorrect.\r\n".to_vec()
+    }
+}
+
+/// Re-hashes the password of a user who just logged in with the preferred
+/// algorithm, in the background so the 230 reply is not delayed.
+fn upgrade_hash(state: &Arc<ServerState>, config: &Config, username: &str, password: &str) {
+    let state = Arc::clone(state);
+    let algorithm = config.auth.preferred_hash();
+    let username = username.to_string();
+    let password = password.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = state
+            .auth()
+            .upgrade_hash(&username, &password, algorithm)
+            .await
+        {
+            error!("Failed to rehash the password of {}: {}", username, e);
+        }
+    });
+}
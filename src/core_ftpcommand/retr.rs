@@ -1,9 +1,12 @@
-use crate::constants::MESSAGE_LENGTH;
+use crate::constants::{EXEMPT, MESSAGE_LENGTH};
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::core_network::throttle::Throttle;
 use crate::core_quota::manager::QuotaManager;
 use crate::helpers::pad_message;
 use crate::{
     helpers::{sanitize_input, send_response},
-    session::Session,
+    session::{LoginLimits, Session},
+    state::ServerState,
     Config,
 };
 use log::{error, info, trace, warn};
@@ -14,6 +17,7 @@ use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
     sync::Mutex,
+    time::timeout,
 };
 
 /// Handles the RETR (Retrieve File) FTP command.
@@ -27,6 +31,7 @@ pub async fn handle_retr_command(
     arg: String,
     data_stream: Option<Arc<Mutex<TcpStream>>>,
     quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> io::Result<()> {
     if arg.trim().is_empty() {
         warn!("RETR command received with no arguments");
@@ -37,33 +42,10 @@ pub async fn handle_retr_command(
     let sanitized_arg = sanitize_input(&arg);
     info!("Received RETR command with argument: {}", sanitized_arg);
 
-    // 0. Check ratio before proceeding
-    if let Some(quota_mgr) = &quota_manager {
-        let session = session.lock().await;
-        let username = session
-            .username
-            .clone()
-            .unwrap_or_else(|| "anonymous".to_string());
-
-        // Get file size to check ratio
-        let temp_file_path = session.base_path.join(&sanitized_arg);
-        if let Ok(metadata) = tokio::fs::metadata(&temp_file_path).await {
-            let file_size = metadata.len();
-            match quota_mgr.check_download(&username, file_size).await {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("Ratio check failed for user {}: {}", username, e);
-                    send_response(&writer, e.to_ftp_response().as_bytes()).await?;
-                    return Ok(());
-                }
-            }
-        }
-    }
-
     // 1. Secure Path Construction:
     let file_path = {
         let session = session.lock().await;
-        let file_path = session.base_path.join(&sanitized_arg);
+        let file_path = session.real_path(&arg);
 
         // Ensure the file path is within the base path
         if !file_path.starts_with(&session.base_path) {
@@ -71,6 +53,14 @@ pub async fn handle_retr_command(
             send_response(&writer, b"550 Path is outside of the allowed area.\r\n").await?;
             return Ok(());
         }
+
+        let site_path = session.site_path(&arg);
+        let user = AclUser::from(&*session);
+        if !acl::is_allowed(&config.acl, PathPermission::Download, &user, &site_path) {
+            warn!("RETR refused by the download rules: {:?}", file_path);
+            send_response(&writer, b"550 Permission denied.\r\n").await?;
+            return Ok(());
+        }
         file_path
     };
 
@@ -96,6 +86,55 @@ pub async fn handle_retr_command(
         }
     };
 
+    // Enforce the simultaneous downloads limit from the userfile
+    let (session_id, exempt, max_sim) = {
+        let session = session.lock().await;
+        (
+            session.id,
+            session.has_flag(EXEMPT),
+            session.login_limits.max_sim_downloads,
+        )
+    };
+    let _transfer = match state
+        .sessions()
+        .start_transfer(session_id, false, exempt, max_sim)
+    {
+        Some(guard) => guard,
+        None => {
+            warn!("Simultaneous downloads limit reached for session {}", session_id);
+            send_response(
+                &writer,
+                format!(
+                    "450 Too many simultaneous downloads (max {}).\r\n",
+                    LoginLimits::display(max_sim)
+                )
+                .as_bytes(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    // Take the credits of the whole file before sending it, so that parallel
+    // downloads cannot spend them twice. Only users with a userfile have some.
+    let credited_user = {
+        let session = session.lock().await;
+        session.username.clone().filter(|_| !session.is_anonymous)
+    };
+    let mut taken = 0;
+    if let Some(username) = &credited_user {
+        let file_size = file.metadata().await.map_or(0, |metadata| metadata.len());
+        match state.take_download_credits(username, file_size).await {
+            Ok(credits) => taken = credits,
+            Err(e) => {
+                error!("Ratio check failed for user {}: {}", username, e);
+                let response = format!("{}\r\n", e.to_ftp_response());
+                send_response(&writer, response.as_bytes()).await?;
+                return Ok(());
+            }
+        }
+    }
+
     // 3. Data Transfer:
     info!("Sending file: {:?}", file_path);
     send_response(
@@ -106,13 +145,16 @@ pub async fn handle_retr_command(
 
     // Ensure data_stream is provided
     trace!("Attempting to lock data stream for file transfer.");
-    let data_stream = match data_stream {
+    let data_stream = match data_stream.or(session.lock().await.data_stream.take()) {
         Some(stream) => {
             trace!("Data stream found, locking...");
             stream
         }
         None => {
             error!("Data stream is None");
+            if let Some(username) = &credited_user {
+                refund_credits(&state, &session, username, taken, 0).await;
+            }
             send_response(&writer, b"425 Can't open data connection.\r\n").await?;
             return Ok(());
         }
@@ -122,6 +164,26 @@ pub async fn handle_retr_command(
     trace!("Data stream locked for file transfer.");
     let buffer_size = config.server.upload_buffer_size.unwrap_or(65536);
     let mut buffer = vec![0; buffer_size];
+    let data_timeout = config.server.data_timeout_duration();
+    let throttle = {
+        let session = session.lock().await;
+        let site_path = file_path
+            .strip_prefix(&session.base_path)
+            .unwrap_or(&file_path);
+        Throttle::for_transfer(
+            &config,
+            &session,
+            site_path,
+            false,
+            state.bandwidth_bucket(false),
+        )
+    };
+
+    // Bytes actually sent, charged even when the transfer is cut short
+    let mut sent: u64 = 0;
+    // Why the transfer ended before the end of the file, if it did
+    let mut aborted: Option<&[u8]> = None;
+    let mut write_error = None;
 
     loop {
         // Read from file into buffer
@@ -130,46 +192,97 @@ pub async fn handle_retr_command(
             Ok(n) => n,
             Err(e) => {
                 error!("Error reading file: {}", e);
-                send_response(&writer, b"550 File read error.\r\n").await?;
-                return Ok(());
+                aborted = Some(b"550 File read error.\r\n");
+                break;
             }
         };
 
-        // Write from buffer to the data stream
-        if let Err(e) = data_stream.write_all(&buffer[..bytes_read]).await {
-            error!("Error writing to data stream: {}", e);
-            return Err(e);
+        throttle.consume(bytes_read).await;
+
+        // Write from buffer to the data stream, aborting if the client stalls
+        match timeout(data_timeout, data_stream.write_all(&buffer[..bytes_read])).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Error writing to data stream: {}", e);
+                write_error = Some(e);
+                break;
+            }
+            Err(_) => {
+                warn!(
+                    "Data transfer stalled for {} seconds, aborting: {:?}",
+                    data_timeout.as_secs(),
+                    file_path
+                );
+                aborted = Some(b"426 Data connection timed out; transfer aborted.\r\n");
+                break;
+            }
         }
+        sent += bytes_read as u64;
         trace!("Transferred {} bytes to data stream.", bytes_read);
     }
 
     // Shut down data stream when done
-    if let Err(e) = data_stream.shutdown().await {
-        error!("Error shutting down data stream: {}", e);
-        // Send error response to the client
-        send_response(&writer, b"426 Connection closed; transfer aborted.\r\n").await?;
-        return Ok(());
+    if write_error.is_none() {
+        if let Err(e) = data_stream.shutdown().await {
+            error!("Error shutting down data stream: {}", e);
+            aborted = aborted.or(Some(b"426 Connection closed; transfer aborted.\r\n"));
+        }
     }
 
-    send_response(&writer, b"226 File transfer complete.\r\n").await?;
-    info!("File transferred successfully: {:?}", file_path);
+    if let Some(response) = aborted {
+        send_response(&writer, response).await?;
+    } else if write_error.is_none() {
+        // Warn users over their soft quota after each transfer
+        let mut response = String::new();
+        if let Some(quota_mgr) = &quota_manager {
+            let username = session.lock().await.username.clone();
+            if let Some(username) = username {
+                if let Some(warning) = quota_mgr.soft_quota_warning(&username).await {
+                    response.push_str(&format!("226-{}\r\n", warning));
+                }
+            }
+        }
+        response.push_str("226 File transfer complete.\r\n");
+        send_response(&writer, response.as_bytes()).await?;
+        info!("File transferred successfully: {:?}", file_path);
+    }
 
-    // Update ratio after successful download
+    // Update ratio with what was sent, the whole file or not
     if let Some(quota_mgr) = &quota_manager {
-        let session = session.lock().await;
         let username = session
+            .lock()
+            .await
             .username
             .clone()
             .unwrap_or_else(|| "anonymous".to_string());
 
-        // Get the actual file size
-        if let Ok(metadata) = tokio::fs::metadata(&file_path).await {
-            let file_size = metadata.len();
-            if let Err(e) = quota_mgr.record_download(&username, file_size).await {
-                error!("Failed to update ratio for user {}: {}", username, e);
-            }
+        if let Err(e) = quota_mgr.record_download(&username, sent).await {
+            error!("Failed to update ratio for user {}: {}", username, e);
         }
     }
 
-    Ok(())
+    // Downloads consume credits, give back those of what was not sent
+    if let Some(username) = &credited_user {
+        refund_credits(&state, &session, username, taken, sent).await;
+    }
+
+    write_error.map_or(Ok(()), Err)
+}
+
+/// Gives back to a user the credits taken for a download, less those of the
+/// `sent` bytes, rounded up to the kilobyte like the credits.
+async fn refund_credits(
+    state: &ServerState,
+    session: &Mutex<Session>,
+    username: &str,
+    taken: u64,
+    sent: u64,
+) {
+    if taken == 0 {
+        return;
+    }
+    let refund = taken.saturating_sub(sent.div_ceil(1024) * 1024);
+    if let Some(credits) = state.add_credits(username, refund as i64).await {
+        session.lock().await.credits = credits;
+    }
 }
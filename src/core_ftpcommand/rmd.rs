@@ -1,5 +1,8 @@
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::core_quota::dir_quota::DirUsage;
 use crate::helpers::{sanitize_input, send_response};
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use log::{error, info, warn};
@@ -20,37 +23,50 @@ use tokio::sync::Mutex;
 /// * `config` - A shared server configuration.
 /// * `session` - A shared, locked session containing the user's current state.
 /// * `arg` - The directory name to delete.
+/// * `state` - The shared server state, holding the owners of the files.
 ///
 /// # Returns
 ///
 /// Result<(), std::io::Error> indicating the success or failure of the operation.
 pub async fn handle_rmd_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     // Sanitize the input argument to prevent directory traversal attacks.
     let sanitized_arg = sanitize_input(&arg);
     info!("Received RMD command with argument: {}", sanitized_arg);
 
     // Construct the directory path within the user's current directory.
-    let dir_path = {
+    let (dir_path, allowed) = {
         // Lock the session to get the current directory.
         let session = session.lock().await;
-        let base_path = session.base_path.clone();
-        let current_dir = session.current_dir.clone();
-        let dir_path = base_path
-            .join(current_dir.trim_start_matches('/'))
-            .join(&sanitized_arg);
+        let site_path = session.site_path(&sanitized_arg);
+        let dir_path = session.real_path(&sanitized_arg);
 
-        info!("base_path: {:?}", base_path);
-        info!("current_dir: {:?}", current_dir);
+        info!("base_path: {:?}", session.base_path);
+        info!("current_dir: {:?}", session.current_dir);
         info!("dir_path: {:?}", dir_path);
 
-        dir_path
+        let owner = state.owners().owner_of(&dir_path);
+        let allowed = acl::may_modify(
+            &config.acl,
+            PathPermission::Delete,
+            &AclUser::from(&*session),
+            &site_path,
+            owner.as_deref(),
+        );
+        (dir_path, allowed)
     };
 
+    if !allowed {
+        warn!("RMD refused by the delete rules: {:?}", dir_path);
+        send_response(&writer, b"550 Permission denied.\r\n").await?;
+        return Ok(());
+    }
+
     // Log the constructed directory path
     info!("Constructed directory path: {:?}", dir_path);
 
@@ -78,6 +94,9 @@ pub async fn handle_rmd_command(
         Ok(_) => {
             // Send success response if the directory was deleted successfully.
             info!("Directory removed successfully: {:?}", resolved_path);
+            state.owners().remove(&dir_path);
+            let removed = DirUsage { bytes: 0, files: 1 };
+            state.dir_quotas().record_remove(&dir_path, removed);
             send_response(
                 &writer,
                 format!("250 \"{}\" directory removed.\r\n", sanitized_arg).as_bytes(),
@@ -1,5 +1,7 @@
+use crate::core_auth::acl::{self, AclUser, PathPermission};
 use crate::helpers::{sanitize_input, send_response};
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use log::{error, info, warn};
@@ -20,30 +22,47 @@ use tokio::sync::Mutex;
 /// * `config` - A shared server configuration.
 /// * `session` - A shared, locked session containing the user's current state.
 /// * `arg` - The current name of the file or directory.
+/// * `state` - The shared server state, holding the owners of the files.
 ///
 /// # Returns
 ///
 /// Result<(), std::io::Error> indicating the success or failure of the operation.
 pub async fn handle_rnfr_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     // Sanitize the input argument to prevent directory traversal attacks.
     let sanitized_arg = sanitize_input(&arg);
     info!("Received RNFR command with argument: {}", sanitized_arg);
 
     // Construct the path of the file or directory to be renamed.
-    let (base_path, path) = {
+    let (base_path, path, allowed) = {
         // Lock the session to get the current directory.
         let session = session.lock().await;
-        let base_path = session.base_path.clone();
-        let path = base_path.join(&session.current_dir).join(&sanitized_arg);
-        (base_path, path)
+        let site_path = session.site_path(&sanitized_arg);
+        let path = session.real_path(&sanitized_arg);
+
+        let owner = state.owners().owner_of(&path);
+        let allowed = acl::may_modify(
+            &config.acl,
+            PathPermission::Rename,
+            &AclUser::from(&*session),
+            &site_path,
+            owner.as_deref(),
+        );
+        (session.base_path.clone(), path, allowed)
     };
     info!("Constructed path: {:?}", path);
 
+    if !allowed {
+        warn!("RNFR refused by the rename rules: {:?}", path);
+        send_response(&writer, b"550 Permission denied.\r\n").await?;
+        return Ok(());
+    }
+
     // Canonicalize the path to ensure it's within the chroot directory.
     let resolved_path = path.canonicalize().unwrap_or_else(|_| path.clone());
 
@@ -64,9 +83,9 @@ pub async fn handle_rnfr_command(
     // Store the path in the session for use by the RNTO command.
     {
         let mut session = session.lock().await;
-        session.rename_from = Some(resolved_path.clone());
+        session.rename_from = Some(path.clone());
     }
-    info!("Stored path for renaming: {:?}", resolved_path);
+    info!("Stored path for renaming: {:?}", path);
 
     // Send success response.
     send_response(&writer, b"350 Ready for RNTO.\r\n").await?;
@@ -1,5 +1,8 @@
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::core_quota::dir_quota::DirUsage;
 use crate::helpers::{sanitize_input, send_response};
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use log::{error, info, warn};
@@ -21,15 +24,17 @@ use tokio::sync::Mutex;
 /// * `config` - A shared server configuration.
 /// * `session` - A shared, locked session containing the user's current state.
 /// * `arg` - The new name of the file or directory.
+/// * `state` - The shared server state, holding the owners of the files and the quotas.
 ///
 /// # Returns
 ///
 /// Result<(), std::io::Error> indicating the success or failure of the operation.
 pub async fn handle_rnto_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     // Sanitize the input argument to prevent directory traversal attacks.
     let sanitized_arg = sanitize_input(&arg);
@@ -50,15 +55,29 @@ pub async fn handle_rnto_command(
     info!("Old path to rename from: {:?}", old_path);
 
     // Construct the new path of the file or directory.
-    let new_path = {
+    let (new_path, allowed) = {
         let session = session.lock().await;
-        session
-            .base_path
-            .join(&session.current_dir)
-            .join(&sanitized_arg)
+        let site_path = session.site_path(&sanitized_arg);
+        let new_path = session.real_path(&sanitized_arg);
+
+        let owner = state.owners().owner_of(&old_path);
+        let allowed = acl::may_modify(
+            &config.acl,
+            PathPermission::Rename,
+            &AclUser::from(&*session),
+            &site_path,
+            owner.as_deref(),
+        );
+        (new_path, allowed)
     };
     info!("New path to rename to: {:?}", new_path);
 
+    if !allowed {
+        warn!("RNTO refused by the rename rules: {:?}", new_path);
+        send_response(&writer, b"550 Permission denied.\r\n").await?;
+        return Ok(());
+    }
+
     // Canonicalize the new path to ensure it's within the chroot directory.
     let base_path = {
         let session = session.lock().await;
@@ -76,6 +95,19 @@ pub async fn handle_rnto_command(
         return Ok(());
     }
 
+    // What enters a directory quota must fit in it
+    let moved = if state.dir_quotas().moves_across(&old_path, &new_path) {
+        let moved = DirUsage::measure(old_path.clone()).await;
+        if let Err(e) = state.dir_quotas().check_move(&old_path, &new_path, moved) {
+            warn!("RNTO of {:?} refused: {}", old_path, e);
+            send_response(&writer, format!("{}\r\n", e.to_ftp_response()).as_bytes()).await?;
+            return Ok(());
+        }
+        Some(moved)
+    } else {
+        None
+    };
+
     // Attempt to rename the file or directory.
     match fs::rename(&old_path, &resolved_new_path).await {
         Ok(_) => {
@@ -84,6 +116,21 @@ pub async fn handle_rnto_command(
                 "File or directory renamed successfully from {:?} to {:?}",
                 old_path, resolved_new_path
             );
+            state.owners().rename(&old_path, &new_path);
+            if let Some(moved) = moved {
+                state.dir_quotas().record_move(&old_path, &new_path, moved);
+            }
+            if let Some(quota_manager) = state.quota_manager() {
+                // Files leaving or entering their uploader's quota tree
+                for (owner, bytes) in state.owners().usage_below(&new_path).await {
+                    if let Err(e) = quota_manager
+                        .record_move(&owner, bytes, &old_path, &new_path)
+                        .await
+                    {
+                        error!("Failed to update quota for user {}: {}", owner, e);
+                    }
+                }
+            }
             send_response(&writer, b"250 File or directory renamed successfully.\r\n").await?;
         }
         Err(e) => {
@@ -1,33 +1,126 @@
-use crate::core_ftpcommand::site::helper::respond_with_error;
+use crate::constants::{GADMIN, SITEOP, USEREDIT, USERS};
+use crate::core_ftpcommand::site::helper::{get_flag_name, respond_with_error};
 use crate::core_ftpcommand::site::site_addip::handle_site_addip_command;
-use crate::core_ftpcommand::site::site_adduser::handle_site_adduser_command;
+use crate::core_ftpcommand::site::site_adduser::{
+    handle_site_adduser_command, handle_site_gadduser_command,
+};
+use crate::core_ftpcommand::site::site_ban::{
+    handle_site_banlist_command, handle_site_unban_command,
+};
+use crate::core_ftpcommand::site::site_change::handle_site_change_command;
+use crate::core_ftpcommand::site::site_chgrp::{
+    handle_site_chgadmin_command, handle_site_chgrp_command,
+};
 use crate::core_ftpcommand::site::site_chmod::handle_site_chmod_command;
 use crate::core_ftpcommand::site::site_delip::handle_site_delip_command;
 use crate::core_ftpcommand::site::site_deluser::handle_site_deluser_command;
-use crate::core_ftpcommand::site::site_group::handle_site_group_command;
+use crate::core_ftpcommand::site::site_dirquota::handle_site_dirquota_command;
+use crate::core_ftpcommand::site::site_group::{
+    handle_site_ginfo_command, handle_site_group_command, handle_site_groups_command,
+    handle_site_grpadd_command, handle_site_grpchange_command, handle_site_grpdel_command,
+};
 use crate::core_ftpcommand::site::site_idle::handle_site_idle_command;
 use crate::core_ftpcommand::site::site_new::handle_site_new_command;
+use crate::core_ftpcommand::site::site_passwd::{
+    handle_site_chpass_command, handle_site_passwd_command,
+};
 use crate::core_ftpcommand::site::site_quota::handle_site_quota_command;
 use crate::core_ftpcommand::site::site_ratio::handle_site_ratio_command;
+use crate::core_ftpcommand::site::site_rehash::handle_site_rehash_command;
 use crate::core_ftpcommand::site::site_user::handle_site_user_command;
 use crate::core_ftpcommand::site::site_utime::handle_site_utime_command;
 use crate::core_ftpcommand::site::site_who::handle_site_who_command;
 
 use crate::core_quota::manager::QuotaManager;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use log::{info, warn};
 use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
+/// Flags needed by each SITE command, glftpd style: any one of them is enough,
+/// an empty list lets every logged in user run the command.
+const SITE_COMMAND_FLAGS: &[(&str, &[u8])] = &[
+    ("ADDUSER", &[SITEOP, USEREDIT]),
+    ("GADDUSER", &[SITEOP, GADMIN, USEREDIT]),
+    ("DELUSER", &[SITEOP, GADMIN, USEREDIT]),
+    ("CHANGE", &[SITEOP, GADMIN, USEREDIT]),
+    ("ADDIP", &[SITEOP, GADMIN, USEREDIT]),
+    ("DELIP", &[SITEOP, GADMIN, USEREDIT]),
+    ("USER", &[SITEOP, GADMIN, USEREDIT, USERS]),
+    ("CHPASS", &[SITEOP]),
+    ("GRPADD", &[SITEOP]),
+    ("GRPDEL", &[SITEOP]),
+    ("GRPCHANGE", &[SITEOP]),
+    ("CHGADMIN", &[SITEOP]),
+    ("CHGRP", &[SITEOP, GADMIN, USEREDIT]),
+    ("GINFO", &[SITEOP, GADMIN, USEREDIT, USERS]),
+    ("GROUPS", &[SITEOP, GADMIN, USEREDIT, USERS]),
+    ("REHASH", &[SITEOP]),
+    ("DIRQUOTA", &[SITEOP]),
+    ("BANLIST", &[SITEOP]),
+    ("UNBAN", &[SITEOP]),
+    ("WHO", &[]),
+    ("PASSWD", &[]),
+    ("IDLE", &[]),
+    ("RATIO", &[]),
+    ("QUOTA", &[]),
+    ("GROUP", &[]),
+    ("CHMOD", &[]),
+    ("UTIME", &[]),
+    ("NEW", &[]),
+];
+
+/// Returns the flags needed by a SITE command, `None` for an unknown command.
+fn required_flags(subcommand: &str) -> Option<&'static [u8]> {
+    SITE_COMMAND_FLAGS
+        .iter()
+        .find(|(name, _)| *name == subcommand)
+        .map(|(_, flags)| *flags)
+}
+
+/// Checks that the session may run a SITE command.
+///
+/// # Returns
+///
+/// The reply to send when it may not: 530 before login, 550 without the flags.
+fn check_permission(session: &Session, subcommand: &str) -> Result<(), String> {
+    if !session.is_authenticated {
+        return Err("530 Not logged in.\r\n".to_string());
+    }
+
+    match required_flags(subcommand) {
+        Some(flags) if !flags.is_empty() && !flags.iter().any(|flag| session.has_flag(*flag)) => {
+            let names: Vec<&str> = flags
+                .iter()
+                .filter_map(|flag| get_flag_name(*flag))
+                .collect();
+            Err(format!(
+                "550 Permission denied, SITE {} requires the {} flag.\r\n",
+                subcommand,
+                names.join(" or ")
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
 pub async fn handle_site_command(
     writer: Arc<Mutex<TcpStream>>,
     config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
     quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     let mut args: Vec<&str> = arg.trim().split(' ').collect();
+    // What follows the subcommand, as sent, for the commands taking a password
+    let rest = arg
+        .trim_start()
+        .split_once(' ')
+        .map_or("", |(_, rest)| rest)
+        .to_string();
 
     if args.is_empty() {
         warn!("No subcommand provided for SITE command.");
@@ -36,29 +129,51 @@ pub async fn handle_site_command(
     }
 
     let subcommand = args.remove(0).to_ascii_uppercase();
-    let sub_args = args.iter().map(|s| s.to_string()).collect();
+    let sub_args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+
+    let permission = check_permission(&*session.lock().await, &subcommand);
+    if let Err(response) = permission {
+        warn!("SITE {} refused: {}", subcommand, response.trim_end());
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
+    }
 
     match subcommand.as_str() {
         "ADDUSER" => {
-            info!("Handling SITE ADDUSER command with args: {:?}", sub_args);
-            handle_site_adduser_command(writer, config, session, sub_args).await
+            info!(
+                "Handling SITE ADDUSER command for user: {:?}",
+                sub_args.first()
+            );
+            handle_site_adduser_command(writer, config, session, sub_args, state).await
         }
         "ADDIP" => {
             info!("Handling SITE ADDIP command with args: {:?}", sub_args);
-            handle_site_addip_command(writer, config, session, sub_args).await
+            handle_site_addip_command(writer, config, session, sub_args, state).await
         }
         "DELIP" => {
             info!("Handling SITE DELIP command with args: {:?}", sub_args);
-            handle_site_delip_command(writer, config, session, sub_args).await
+            handle_site_delip_command(writer, config, session, sub_args, state).await
+        }
+        "GADDUSER" => {
+            info!(
+                "Handling SITE GADDUSER command for user: {:?}",
+                sub_args.get(1)
+            );
+            handle_site_gadduser_command(writer, config, session, sub_args, state).await
         }
         "DELUSER" => {
             info!("Handling SITE DELUSER command with args: {:?}", sub_args);
-            handle_site_deluser_command(writer, config, session, sub_args).await
+            handle_site_deluser_command(writer, config, session, sub_args, state).await
+        }
+        "CHANGE" => {
+            info!("Handling SITE CHANGE command with args: {:?}", sub_args);
+            handle_site_change_command(writer, config, session, sub_args, state).await
         }
         "USER" => {
             if sub_args.len() == 1 {
                 info!("Handling SITE USER command for user: {:?}", sub_args[0]);
-                handle_site_user_command(writer, config, session, sub_args[0].clone()).await
+                handle_site_user_command(writer, config, session, sub_args[0].clone(), state)
+                    .await
             } else {
                 warn!("Invalid arguments for SITE USER command: {:?}", sub_args);
                 respond_with_error(&writer, b"501 Syntax error in parameters or arguments.\r\n")
@@ -67,27 +182,60 @@ pub async fn handle_site_command(
         }
         "UTIME" => {
             info!("Handling SITE UTIME command with args: {:?}", sub_args);
-            handle_site_utime_command(writer, config, session, sub_args.join(" ")).await
+            handle_site_utime_command(writer, config, session, sub_args.join(" "), state).await
         }
         "RATIO" => {
             info!("Handling SITE RATIO command");
-            handle_site_ratio_command(writer, config, session, sub_args, quota_manager).await
+            handle_site_ratio_command(writer, config, session, sub_args, quota_manager, state).await
         }
         "QUOTA" => {
             info!("Handling SITE QUOTA command");
-            handle_site_quota_command(writer, config, session, sub_args, quota_manager).await
+            handle_site_quota_command(writer, config, session, sub_args, quota_manager, state)
+                .await
+        }
+        "DIRQUOTA" => {
+            info!("Handling SITE DIRQUOTA command");
+            handle_site_dirquota_command(writer, config, session, sub_args, state).await
         }
         "GROUP" => {
             info!("Handling SITE GROUP command");
-            handle_site_group_command(writer, config, session, sub_args, quota_manager).await
+            handle_site_group_command(writer, config, session, sub_args, state).await
+        }
+        "GROUPS" => {
+            info!("Handling SITE GROUPS command");
+            handle_site_groups_command(writer, config, session, sub_args, state).await
+        }
+        "GINFO" => {
+            info!("Handling SITE GINFO command");
+            handle_site_ginfo_command(writer, config, session, sub_args, state).await
+        }
+        "GRPADD" => {
+            info!("Handling SITE GRPADD command");
+            handle_site_grpadd_command(writer, config, session, sub_args, state).await
+        }
+        "GRPDEL" => {
+            info!("Handling SITE GRPDEL command");
+            handle_site_grpdel_command(writer, config, session, sub_args, state).await
+        }
+        "GRPCHANGE" => {
+            info!("Handling SITE GRPCHANGE command");
+            handle_site_grpchange_command(writer, config, session, sub_args, state).await
+        }
+        "CHGRP" => {
+            info!("Handling SITE CHGRP command");
+            handle_site_chgrp_command(writer, config, session, sub_args, state).await
+        }
+        "CHGADMIN" => {
+            info!("Handling SITE CHGADMIN command");
+            handle_site_chgadmin_command(writer, config, session, sub_args, state).await
         }
         "CHMOD" => {
             info!("Handling SITE CHMOD command");
-            handle_site_chmod_command(writer, config, session, sub_args, quota_manager).await
+            handle_site_chmod_command(writer, config, session, sub_args, state).await
         }
         "WHO" => {
             info!("Handling SITE WHO command");
-            handle_site_who_command(writer, config, session, sub_args, quota_manager).await
+            handle_site_who_command(writer, config, session, sub_args, state).await
         }
         "NEW" => {
             info!("Handling SITE NEW command");
@@ -97,6 +245,26 @@ pub async fn handle_site_command(
             info!("Handling SITE IDLE command");
             handle_site_idle_command(writer, config, session, sub_args, quota_manager).await
         }
+        "REHASH" => {
+            info!("Handling SITE REHASH command");
+            handle_site_rehash_command(writer, config, session, sub_args, state).await
+        }
+        "BANLIST" => {
+            info!("Handling SITE BANLIST command");
+            handle_site_banlist_command(writer, config, session, sub_args, state).await
+        }
+        "UNBAN" => {
+            info!("Handling SITE UNBAN command");
+            handle_site_unban_command(writer, config, session, sub_args, state).await
+        }
+        "PASSWD" => {
+            info!("Handling SITE PASSWD command");
+            handle_site_passwd_command(writer, config, session, rest, state).await
+        }
+        "CHPASS" => {
+            info!("Handling SITE CHPASS command");
+            handle_site_chpass_command(writer, config, session, rest, state).await
+        }
         _ => {
             warn!("Unknown SITE subcommand: {}", subcommand);
             respond_with_error(&writer, b"502 Command not implemented.\r\n").await?;
@@ -104,3 +272,25 @@ pub async fn handle_site_command(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_check_permission() {
+        let mut session = Session::new(PathBuf::from("/"));
+        assert!(check_permission(&session, "WHO").unwrap_err().starts_with("530"));
+
+        session.is_authenticated = true;
+        assert!(check_permission(&session, "WHO").is_ok());
+        assert!(check_permission(&session, "ADDUSER")
+            .unwrap_err()
+            .starts_with("550"));
+
+        session.flags = vec![USEREDIT];
+        assert!(check_permission(&session, "ADDUSER").is_ok());
+        assert!(check_permission(&session, "REHASH").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::constants::{STATLINE_PATH, USERNAME_REGEX};
+use crate::core_auth::mask::IdentMask;
 use log::error;
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
@@ -7,9 +8,13 @@ use tokio::sync::Mutex;
 use url::Url;
 
 use crate::constants::*;
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::session::Session;
+use crate::state::ServerState;
 use crate::tokio::fs;
 use crate::Config;
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 pub async fn respond_with_error(
     writer: &Arc<Mutex<TcpStream>>,
@@ -27,6 +32,55 @@ pub async fn respond_with_success(
     writer.write_all(msg).await
 }
 
+/// Resolves the path argument of a SITE command working on an existing file,
+/// such as CHMOD and UTIME.
+///
+/// The file must exist inside the session's area, symbolic links included.
+///
+/// # Returns
+///
+/// The canonical path of the file on disk, or the reply to send.
+pub fn resolve_site_path(session: &Session, arg: &str) -> Result<PathBuf, &'static [u8]> {
+    let Ok(resolved_path) = session.real_path(arg).canonicalize() else {
+        return Err(b"550 File not found.\r\n");
+    };
+    let base_path = session
+        .base_path
+        .canonicalize()
+        .unwrap_or_else(|_| session.base_path.clone());
+    if !resolved_path.starts_with(&base_path) {
+        return Err(b"550 Path is outside of the allowed area.\r\n");
+    }
+    Ok(resolved_path)
+}
+
+/// Resolves the path argument of a SITE command changing a file, as
+/// `resolve_site_path` does, then checks that the user may delete the file, or
+/// owns it under a deleteown rule.
+///
+/// # Returns
+///
+/// The canonical path of the file on disk, or the reply to send.
+pub fn resolve_modifiable_path(
+    config: &Config,
+    session: &Session,
+    state: &ServerState,
+    arg: &str,
+) -> Result<PathBuf, &'static [u8]> {
+    let resolved_path = resolve_site_path(session, arg)?;
+    let owner = state.owners().owner_of(&session.real_path(arg));
+    if !acl::may_modify(
+        &config.acl,
+        PathPermission::Delete,
+        &AclUser::from(session),
+        &session.site_path(arg),
+        owner.as_deref(),
+    ) {
+        return Err(b"550 Permission denied.\r\n");
+    }
+    Ok(resolved_path)
+}
+
 /// Checks if a string is a valid IPv4 address or hostname.
 ///
 /// # Arguments
@@ -42,8 +96,8 @@ pub fn is_valid_ip_or_hostname(ip: &str) -> bool {
         return false;
     }
 
-    // Check if it's a valid IPv4 address
-    if ip.parse::<std::net::Ipv4Addr>().is_ok() {
+    // Check if it's a valid IP address
+    if ip.parse::<std::net::IpAddr>().is_ok() {
         return true;
     }
 
@@ -52,17 +106,13 @@ pub fn is_valid_ip_or_hostname(ip: &str) -> bool {
 }
 
 pub fn is_valid_ident_ip(ident_ip: &str) -> bool {
-    let parts: Vec<&str> = ident_ip.split('@').collect();
-    if parts.len() != 2 {
+    if IdentMask::parse(ident_ip).is_none() {
         return false;
     }
-    let ident = parts[0];
-    let ip_or_hostname = parts[1];
+    let ip_or_hostname = ident_ip.split_once('@').map_or("", |(_, host)| host);
 
-    if ident.is_empty() || !is_valid_ip_or_hostname(ip_or_hostname) {
-        return false;
-    }
-    true
+    // Wildcard patterns and CIDR blocks were validated by the mask parser
+    ip_or_hostname.contains(['*', '?', '/']) || is_valid_ip_or_hostname(ip_or_hostname)
 }
 
 /// Validates the username according to the defined rules.
@@ -82,6 +132,12 @@ pub fn is_valid_username(username: &str) -> bool {
     re.is_match(username)
 }
 
+/// Validates a group name: letters, digits, `-` and `_`, up to 32 characters.
+pub fn is_valid_group_name(group: &str) -> bool {
+    let re = regex::Regex::new(GROUPNAME_REGEX).unwrap();
+    re.is_match(group)
+}
+
 /// Performs a basic validation of the password.
 ///
 /// # Arguments
@@ -153,6 +209,35 @@ pub fn get_flag_name(flag_value: u8) -> Option<&'static str> {
     }
 }
 
+/// Parses the value of a userfile FLAGS line into flag values.
+///
+/// Flags are stored glftpd style as single characters (`"13"` is SITEOP and GLOCK),
+/// `A` to `Z` standing for the values 10 and above. Commas, as written by SITE DELUSER
+/// (`"3,6"`), are ignored.
+pub fn parse_flags(flags: &str) -> Vec<u8> {
+    let mut parsed = Vec::new();
+
+    for token in flags.split(|c: char| c == ',' || c.is_whitespace()) {
+        if token.is_empty() {
+            continue;
+        }
+
+        for c in token.chars() {
+            let value = match c {
+                '1'..='9' => c as u8 - b'0',
+                'A'..='Z' => c as u8 - b'A' + 10,
+                'a'..='z' => c as u8 - b'a' + 10,
+                _ => continue,
+            };
+            if !parsed.contains(&value) {
+                parsed.push(value);
+            }
+        }
+    }
+
+    parsed
+}
+
 pub async fn load_statline(config: Arc<Config>) -> Result<String, std::io::Error> {
     let statline_path: String = format!("{}{}", config.server.chroot_dir, STATLINE_PATH);
 
@@ -3,14 +3,20 @@ mod handler;
 pub mod helper;
 pub mod site_addip;
 pub mod site_adduser;
+pub mod site_ban;
+pub mod site_change;
+pub mod site_chgrp;
 pub mod site_chmod;
 pub mod site_delip;
+pub mod site_dirquota;
 pub mod site_deluser;
 pub mod site_group;
 pub mod site_idle;
 pub mod site_new;
+pub mod site_passwd;
 pub mod site_quota;
 pub mod site_ratio;
+pub mod site_rehash;
 pub mod site_user;
 pub mod site_utime;
 pub mod site_who;
@@ -2,22 +2,21 @@ use crate::constants::{MAX_ADDIP_IPS, MIN_ADDIP_ARGS, SITE_ADDIP_HELP_PATH};
 use crate::core_ftpcommand::site::helper::{
     is_valid_ident_ip, respond_with_error, respond_with_success,
 };
+use crate::core_ftpcommand::site::site_group::can_manage_username;
+use crate::core_users::error::UserError;
 use crate::helpers::send_file_to_client;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use log::{info, warn};
-use std::{
-    fs::{self, OpenOptions},
-    io::Write,
-    path::{Path, PathBuf},
-    sync::Arc,
-};
+use std::sync::Arc;
 use tokio::{net::TcpStream, sync::Mutex};
 
 pub async fn handle_site_addip_command(
     writer: Arc<Mutex<TcpStream>>,
     config: Arc<Config>,
-    _session: Arc<Mutex<Session>>, // Session not used in this command
+    session: Arc<Mutex<Session>>,
     args: Vec<String>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     if args.len() < MIN_ADDIP_ARGS {
         warn!("Insufficient arguments for SITE ADDIP: {:?}", args);
@@ -31,6 +30,19 @@ pub async fn handle_site_addip_command(
     let username = &args[0];
     let idents_ips = &args[1..];
 
+    if !can_manage_username(&state, &session, username).await {
+        warn!(
+            "SITE ADDIP {} refused, not in a group the caller administers",
+            username
+        );
+        respond_with_error(
+            &writer,
+            b"550 Permission denied, not one of your users.\r\n",
+        )
+        .await?;
+        return Ok(());
+    }
+
     if idents_ips.len() > MAX_ADDIP_IPS {
         warn!("Too many IPs for SITE ADDIP: {:?}", idents_ips);
         respond_with_error(&writer, b"501 Too many IPs, max 10 allowed.\r\n").await?;
@@ -52,22 +64,19 @@ pub async fn handle_site_addip_command(
         valid_idents_ips.push(ident_ip.clone());
     }
 
-    let user_file_path = PathBuf::from(&config.server.chroot_dir)
-        .join("ftp-data/users")
-        .join(format!("{}.user", username));
-
-    // Check if user exists
-    if !user_file_path.exists() {
-        respond_with_error(&writer, b"550 User does not exist.\r\n").await?;
-        return Ok(());
-    }
-
     // Add IPs to user file
-    match add_idents_ips_to_user_file(&user_file_path, valid_idents_ips) {
-        Ok(_) => {
+    match state
+        .users()
+        .update(username, |user| user.ips.extend(valid_idents_ips))
+        .await
+    {
+        Ok(()) => {
             info!("Ident@IPs added to user {} successfully", username);
             respond_with_success(&writer, b"200 ident@IPs added successfully.\r\n").await?;
         }
+        Err(UserError::NotFound(_)) => {
+            respond_with_error(&writer, b"550 User does not exist.\r\n").await?;
+        }
         Err(e) => {
             warn!("Failed to add ident@IPs to user file: {}", e);
             respond_with_error(&writer, b"550 Failed to add ident@IPs.\r\n").await?;
@@ -75,22 +84,3 @@ pub async fn handle_site_addip_command(
     }
     Ok(())
 }
-
-fn add_idents_ips_to_user_file(
-    user_file_path: &Path,
-    idents_ips: Vec<String>,
-) -> std::io::Result<()> {
-    let mut user_data = fs::read_to_string(user_file_path)?;
-
-    for ident_ip in idents_ips {
-        user_data.push_str(&format!("IP {}\n", ident_ip));
-    }
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(user_file_path)?;
-    file.write_all(user_data.as_bytes())?;
-
-    Ok(())
-}
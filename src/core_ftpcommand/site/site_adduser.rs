@@ -1,18 +1,18 @@
+use crate::core_auth::AuthBackend;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use log::{error, info, warn};
-use std::{
-    fs::{self, OpenOptions},
-    io::Write,
-    path::{Path, PathBuf},
-    sync::Arc,
-};
+use std::sync::Arc;
 use tokio::{net::TcpStream, sync::Mutex};
 
-use crate::constants::SITE_ADDUSER_HELP_PATH;
+use crate::constants::{SITE_ADDUSER_HELP_PATH, SITE_GADDUSER_HELP_PATH};
 use crate::core_ftpcommand::site::helper::{
     is_valid_ip_or_hostname, is_valid_password, is_valid_username, respond_with_error,
     respond_with_success,
 };
+use crate::core_ftpcommand::site::site_group::{can_manage_group, check_slots};
+use crate::core_groups::error::GroupError;
+use crate::core_users::userfile::UserGroup;
 use crate::helpers::send_file_to_client;
 
 /// Handles the SITE ADDUSER command.
@@ -25,6 +25,7 @@ use crate::helpers::send_file_to_client;
 /// * `config` - The server configuration.
 /// * `session` - The current FTP session (not used in this command).
 /// * `args` - The command arguments.
+/// * `state` - The shared server state, holding the user store.
 ///
 /// # Returns
 ///
@@ -34,6 +35,7 @@ pub async fn handle_site_adduser_command(
     config: Arc<Config>,
     _session: Arc<Mutex<Session>>, // Session not used in this command
     args: Vec<String>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     const MIN_ARGS: usize = 2;
 
@@ -49,78 +51,204 @@ pub async fn handle_site_adduser_command(
     let password = &args[1];
     let idents_ips = &args[2..];
 
-    // Validate username and password
-    if !is_valid_username(username) || !is_valid_password(password) {
-        respond_with_error(&writer, b"501 Invalid username or password.\r\n").await?;
-        return Ok(());
-    }
-
-    let user_file_path = PathBuf::from(&config.server.chroot_dir)
-        .join("ftp-data/users")
-        .join(format!("{}.user", username));
-
-    // Check if user already exists
-    if user_file_path.exists() {
-        respond_with_error(&writer, b"550 User already exists.\r\n").await?;
+    if let Err(response) = check_new_user(&state, username, password, idents_ips).await {
+        respond_with_error(&writer, response.as_bytes()).await?;
         return Ok(());
     }
 
     // Create user file based on default template
-    match create_user_file(&user_file_path, username, password, idents_ips) {
-        Ok(_) => {
+    match create_user(&state, &config, username, password, idents_ips, None).await {
+        Ok(()) => {
+            state.refresh_quota_settings().await;
             info!("User {} added successfully", username);
             respond_with_success(&writer, b"200 User added successfully.\r\n").await?;
         }
         Err(e) => {
-            error!("Failed to create user file: {}", e);
+            error!("Failed to create user {}: {}", username, e);
             respond_with_error(&writer, b"550 Failed to create user.\r\n").await?;
         }
     }
     Ok(())
 }
 
-/// Creates a new user file based on a default template.
+/// Handles the SITE GADDUSER command.
+///
+/// This command adds a new user to the FTP server as a member of a group. Group
+/// admins may only add users to the groups they administer, within the slots
+/// of the group.
 ///
 /// # Arguments
 ///
-/// * `user_file_path` - The path to the user file.
-/// * `username` - The username for the new user.
-/// * `password` - The password for the new user.
-/// * `ips` - A slice of allowed IP addresses or hostnames.
+/// * `writer` - The TCP stream writer to send responses.
+/// * `config` - The server configuration.
+/// * `session` - The current FTP session, whose user must manage the group.
+/// * `args` - The command arguments.
+/// * `state` - The shared server state, holding the user store and the groups.
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` on success, or an `std::io::Error` on failure.
-fn create_user_file(
-    user_file_path: &Path,
+pub async fn handle_site_gadduser_command(
+    writer: Arc<Mutex<TcpStream>>,
+    config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    const MIN_ARGS: usize = 3;
+
+    if args.len() < MIN_ARGS {
+        warn!("Insufficient arguments for SITE GADDUSER: {:?}", args);
+        send_file_to_client(&writer, &config.server.chroot_dir, SITE_GADDUSER_HELP_PATH).await?;
+
+        respond_with_error(&writer, b"501 Syntax error in parameters or arguments.\r\n").await?;
+        return Ok(());
+    }
+
+    let group = &args[0];
+    let username = &args[1];
+    let password = &args[2];
+    let idents_ips = &args[3..];
+
+    if state.groups().get_group(group).is_none() {
+        let response = format!(
+            "{}\r\n",
+            GroupError::GroupNotFound(group.clone()).to_ftp_response()
+        );
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    if let Err(response) = check_new_user(&state, username, password, idents_ips).await {
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    // The new user takes a slot of the group, and a leech slot if the default
+    // userfile gives no ratio
+    let leech = match state.users().template(username).await {
+        Ok(template) => template.ratio.first() == Some(&0),
+        Err(_) => false,
+    };
+    let refused = {
+        let session = session.lock().await;
+        if !can_manage_group(&state, &session, group).await {
+            Some("550 Permission denied, not your group.\r\n".to_string())
+        } else {
+            check_slots(&state, &session, group, true, leech)
+                .await
+                .err()
+                .map(|e| format!("{}\r\n", e.to_ftp_response()))
+        }
+    };
+    if let Some(response) = refused {
+        warn!("SITE GADDUSER {} {} refused", group, username);
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    if let Err(e) = create_user(&state, &config, username, password, idents_ips, Some(group)).await
+    {
+        error!("Failed to create user {}: {}", username, e);
+        respond_with_error(&writer, b"550 Failed to create user.\r\n").await?;
+        return Ok(());
+    }
+
+    let groups = state.groups();
+    if let Err(e) = groups
+        .add_user_to_group(username, group)
+        .and_then(|()| groups.save())
+    {
+        warn!("Failed to add {} to group {}: {}", username, group, e);
+    }
+
+    state.refresh_quota_settings().await;
+    info!("User {} added to group {}", username, group);
+    respond_with_success(&writer, b"200 User added successfully.\r\n").await?;
+    Ok(())
+}
+
+/// Checks the username, password and ident@IPs of a user to add.
+///
+/// # Returns
+///
+/// The reply to send when the user cannot be added.
+async fn check_new_user(
+    state: &ServerState,
     username: &str,
     password: &str,
-    ips: &[String],
-) -> std::io::Result<()> {
-    let default_user_file = PathBuf::from("ftp-data/users/default.user");
-    let mut user_data = fs::read_to_string(&default_user_file)?;
+    idents_ips: &[String],
+) -> Result<(), String> {
+    // Validate username and password
+    if !is_valid_username(username) || !is_valid_password(password) {
+        return Err("501 Invalid username or password.\r\n".to_string());
+    }
 
-    // Customize user data
-    user_data = user_data.replace("No Tagline Set", username);
-    user_data.push_str(&format!("USER {}\n", username));
-    user_data.push_str(&format!("PASSWORD {}\n", password));
+    if state.users().exists(username).await {
+        return Err("550 User already exists.\r\n".to_string());
+    }
 
-    for ip in ips {
+    for ip in idents_ips {
         if !is_valid_ip_or_hostname(ip) {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Invalid IP or hostname: {}", ip),
-            ));
+            return Err(format!("501 Invalid IP or hostname: {}\r\n", ip));
         }
-        user_data.push_str(&format!("IP {}\n", ip));
     }
+    Ok(())
+}
 
-    // Write to new user file
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(user_file_path)?;
-    file.write_all(user_data.as_bytes())?;
+/// Creates a new user from the default userfile.
+///
+/// The userfile is written through the user store, the password is hashed
+/// with the preferred algorithm and stored by the authentication backends.
+///
+/// # Arguments
+///
+/// * `state` - The shared server state, holding the user store and the backends.
+/// * `config` - The server configuration.
+/// * `username` - The username for the new user.
+/// * `password` - The password for the new user.
+/// * `ips` - A slice of allowed IP addresses or hostnames.
+/// * `group` - The group to make the user a member of, as its primary group.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a description of the failure.
+async fn create_user(
+    state: &ServerState,
+    config: &Config,
+    username: &str,
+    password: &str,
+    ips: &[String],
+    group: Option<&str>,
+) -> Result<(), String> {
+    let users = state.users();
+    let mut user = users.template(username).await.map_err(|e| e.to_string())?;
 
-    Ok(())
+    // Customize user data
+    if user.tagline == "No Tagline Set" {
+        user.tagline = username.to_string();
+    }
+    user.ips.extend(ips.iter().cloned());
+    if let Some(group) = group {
+        user.groups.retain(|g| g.name != group);
+        user.groups.insert(
+            0,
+            UserGroup {
+                name: group.to_string(),
+                gadmin: false,
+            },
+        );
+    }
+    users.create(user).await.map_err(|e| e.to_string())?;
+
+    let algorithm = config.auth.preferred_hash();
+    let hash = algorithm
+        .hash_blocking(password)
+        .await
+        .ok_or_else(|| format!("could not hash the password with {:?}", algorithm))?;
+    match state.auth().add_user(username, &hash).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("no authentication backend can store the password".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
 }
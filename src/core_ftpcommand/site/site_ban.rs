@@ -0,0 +1,68 @@
+// Commandes SITE BANLIST et SITE UNBAN - Gestion des bannissements d'IP
+// Les IP sont bannies temporairement après trop d'échecs de connexion
+
+use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::state::ServerState;
+use crate::{session::Session, Config};
+use chrono::{Local, TimeZone};
+use log::info;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Gère la commande SITE BANLIST
+/// Affiche les IP bannies, avec la date d'expiration et la raison du bannissement.
+pub async fn handle_site_banlist_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    _session: Arc<Mutex<Session>>,
+    _args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let bans = state.bans().list();
+
+    let mut response = String::from("200- IP address        Expires              Reason\r\n");
+    for (ip, ban) in &bans {
+        let expires = Local
+            .timestamp_opt(ban.until as i64, 0)
+            .single()
+            .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| ban.until.to_string());
+        response.push_str(&format!("200- {:<18} {:<20} {}\r\n", ip, expires, ban.reason));
+    }
+    response.push_str(&format!("200 {} IP(s) banned.\r\n", bans.len()));
+
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE UNBAN <ip>
+/// Lève le bannissement d'une IP et remet à zéro ses échecs de connexion.
+pub async fn handle_site_unban_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let username = session.lock().await.username.clone().unwrap_or_default();
+
+    if args.len() != 1 {
+        return respond_with_error(&writer, b"501 Usage: SITE UNBAN <ip>\r\n").await;
+    }
+
+    let ip: IpAddr = match args[0].parse() {
+        Ok(ip) => ip,
+        Err(_) => {
+            return respond_with_error(&writer, b"501 Invalid IP address.\r\n").await;
+        }
+    };
+
+    if state.bans().unban(ip).await {
+        info!("{} unbanned {}", username, ip);
+        respond_with_success(&writer, format!("200 {} is no longer banned.\r\n", ip).as_bytes())
+            .await
+    } else {
+        respond_with_error(&writer, format!("550 {} is not banned.\r\n", ip).as_bytes()).await
+    }
+}
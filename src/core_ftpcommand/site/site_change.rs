@@ -0,0 +1,227 @@
+use crate::constants::{SITEOP, SITE_CHANGE_HELP_PATH, USEREDIT};
+use crate::core_ftpcommand::site::helper::{parse_flags, respond_with_error, respond_with_success};
+use crate::core_ftpcommand::site::site_group::{can_manage_user, check_slots, gadmin_group_of};
+use crate::core_users::userfile::UserFile;
+use crate::helpers::send_file_to_client;
+use crate::state::ServerState;
+use crate::{session::Session, Config};
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::{net::TcpStream, sync::Mutex};
+
+/// The fields group admins may change on the members of their groups.
+const GADMIN_FIELDS: &[&str] = &["ratio", "tagline"];
+
+/// A change of a userfile field.
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    /// Ratio of the first section, 0 = leech
+    Ratio(i64),
+    Tagline(String),
+    /// Idle time in seconds, -1 = the server default
+    Idle(i64),
+    /// Maximum simultaneous logins, 0 = unlimited
+    Logins(i64),
+    /// Flags to add (`true`) or remove
+    Flags(bool, Vec<u8>),
+}
+
+impl Change {
+    /// Parses the `<field> <value>` of SITE CHANGE.
+    ///
+    /// # Returns
+    ///
+    /// The reply to send when the field is unknown or the value invalid.
+    fn parse(field: &str, value: &str) -> Result<Self, String> {
+        let invalid = || format!("501 Invalid value for {}.\r\n", field);
+        match field {
+            "ratio" => match value.parse() {
+                Ok(ratio) if ratio >= 0 => Ok(Change::Ratio(ratio)),
+                _ => Err(invalid()),
+            },
+            "tagline" if !value.is_empty() => Ok(Change::Tagline(value.to_string())),
+            "idle" => match value.parse() {
+                Ok(idle) if idle >= -1 => Ok(Change::Idle(idle)),
+                _ => Err(invalid()),
+            },
+            "logins" => match value.parse() {
+                Ok(logins) if logins >= 0 => Ok(Change::Logins(logins)),
+                _ => Err(invalid()),
+            },
+            "flags" => {
+                let (add, flags) = if let Some(flags) = value.strip_prefix('+') {
+                    (true, parse_flags(flags))
+                } else if let Some(flags) = value.strip_prefix('-') {
+                    (false, parse_flags(flags))
+                } else {
+                    return Err(invalid());
+                };
+                if flags.is_empty() {
+                    return Err(invalid());
+                }
+                Ok(Change::Flags(add, flags))
+            }
+            "tagline" => Err(invalid()),
+            _ => Err(format!("501 Unknown field: {}.\r\n", field)),
+        }
+    }
+
+    fn apply(self, user: &mut UserFile) {
+        match self {
+            Change::Ratio(ratio) => match user.ratio.first_mut() {
+                Some(first) => *first = ratio,
+                None => user.ratio.push(ratio),
+            },
+            Change::Tagline(tagline) => user.tagline = tagline,
+            Change::Idle(idle) => user.general.idle_time = idle,
+            Change::Logins(logins) => user.logins.max_logins = logins,
+            Change::Flags(add, flags) => {
+                for flag in flags {
+                    if add {
+                        user.add_flag(flag);
+                    } else {
+                        user.remove_flag(flag);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Handles the SITE CHANGE command.
+///
+/// This command changes a field of a userfile: `ratio`, `tagline`, `idle`,
+/// `logins` or `flags` (`+<flags>` or `-<flags>`). Group admins may only change
+/// the ratio and tagline of the members of their groups, making a user leech
+/// takes a leech slot of the group. Only siteops may change flags.
+///
+/// # Arguments
+///
+/// * `writer` - The TCP stream writer to send responses.
+/// * `config` - The server configuration.
+/// * `session` - The current FTP session, whose user must manage the account.
+/// * `args` - The command arguments.
+/// * `state` - The shared server state, holding the user store and the groups.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or an `std::io::Error` on failure.
+pub async fn handle_site_change_command(
+    writer: Arc<Mutex<TcpStream>>,
+    config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    const MIN_ARGS: usize = 3;
+
+    if args.len() < MIN_ARGS {
+        warn!("Insufficient arguments for SITE CHANGE: {:?}", args);
+        send_file_to_client(&writer, &config.server.chroot_dir, SITE_CHANGE_HELP_PATH).await?;
+
+        respond_with_error(&writer, b"501 Syntax error in parameters or arguments.\r\n").await?;
+        return Ok(());
+    }
+
+    let username = &args[0];
+    let field = args[1].to_ascii_lowercase();
+    let value = args[2..].join(" ");
+
+    let change = match Change::parse(&field, &value) {
+        Ok(change) => change,
+        Err(response) => {
+            respond_with_error(&writer, response.as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    let target = match state.users().get(username).await {
+        Ok(Some(target)) => target,
+        Ok(None) => {
+            respond_with_error(&writer, b"550 User does not exist.\r\n").await?;
+            return Ok(());
+        }
+        Err(e) => {
+            let response = format!("{}\r\n", e.to_ftp_response());
+            respond_with_error(&writer, response.as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    let refused = {
+        let session = session.lock().await;
+        if !can_manage_user(&state, &session, &target).await {
+            Some("550 Permission denied, not one of your users.\r\n".to_string())
+        } else if field == "flags" && !session.has_flag(SITEOP) {
+            Some("550 Permission denied, only siteops can change flags.\r\n".to_string())
+        } else if !session.has_flag(SITEOP)
+            && !session.has_flag(USEREDIT)
+            && !GADMIN_FIELDS.contains(&field.as_str())
+        {
+            Some(format!(
+                "550 Permission denied, group admins cannot change {}.\r\n",
+                field
+            ))
+        } else if change == Change::Ratio(0) && target.ratio.first() != Some(&0) {
+            // A new leech takes a leech slot of the group admin's group
+            match gadmin_group_of(&state, &session, &target).await {
+                Some(group) => check_slots(&state, &session, &group, false, true)
+                    .await
+                    .err()
+                    .map(|e| format!("{}\r\n", e.to_ftp_response())),
+                None => None,
+            }
+        } else {
+            None
+        }
+    };
+    if let Some(response) = refused {
+        warn!("SITE CHANGE {} {} refused", username, field);
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    if let Err(e) = state
+        .users()
+        .update(username, |user| change.apply(user))
+        .await
+    {
+        warn!("SITE CHANGE failed for {}: {}", username, e);
+        let response = format!("{}\r\n", e.to_ftp_response());
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    state.refresh_quota_settings().await;
+    info!("{} of user {} set to {}", field, username, value);
+    let response = format!("200 {} of {} set to {}.\r\n", field, username, value);
+    respond_with_success(&writer, response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GADMIN;
+
+    #[test]
+    fn test_parse_change() {
+        assert_eq!(Change::parse("ratio", "0"), Ok(Change::Ratio(0)));
+        assert!(Change::parse("ratio", "-2").is_err());
+        assert_eq!(
+            Change::parse("flags", "+2"),
+            Ok(Change::Flags(true, vec![GADMIN]))
+        );
+        assert!(Change::parse("flags", "2").is_err());
+        assert!(Change::parse("tagline", "").is_err());
+        assert!(Change::parse("homedir", "/")
+            .unwrap_err()
+            .starts_with("501 Unknown"));
+
+        let mut user = UserFile::default();
+        Change::Ratio(0).apply(&mut user);
+        Change::Flags(true, vec![GADMIN]).apply(&mut user);
+        assert_eq!(user.ratio, vec![0]);
+        assert!(user.has_flag(GADMIN));
+    }
+}
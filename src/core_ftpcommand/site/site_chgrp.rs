@@ -0,0 +1,211 @@
+// Commandes SITE CHGRP et CHGADMIN - Appartenance et administration des groupes
+// Inspiré de glFTPd
+
+use crate::constants::GADMIN;
+use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::core_ftpcommand::site::site_group::{can_manage_group, can_manage_user, check_slots};
+use crate::core_groups::error::GroupError;
+use crate::core_users::userfile::UserGroup;
+use crate::state::ServerState;
+use crate::{session::Session, Config};
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Gère la commande SITE CHGRP <utilisateur> <groupe> [groupe...]
+/// Ajoute l'utilisateur à chaque groupe dont il n'est pas membre et le retire
+/// de ceux dont il l'est. Les GADMIN ne peuvent changer que leurs groupes, pour
+/// les membres de leurs groupes et dans la limite de leurs slots.
+pub async fn handle_site_chgrp_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    if args.len() < 2 {
+        return respond_with_error(
+            &writer,
+            b"501 Usage: SITE CHGRP <user> <group> [group...]\r\n",
+        )
+        .await;
+    }
+    let (username, groups) = (&args[0], &args[1..]);
+
+    let target = match state.users().get(username).await {
+        Ok(Some(target)) => target,
+        Ok(None) => return respond_with_error(&writer, b"550 User does not exist.\r\n").await,
+        Err(e) => {
+            let response = format!("{}\r\n", e.to_ftp_response());
+            return respond_with_error(&writer, response.as_bytes()).await;
+        }
+    };
+    let leech = target.ratio.first() == Some(&0);
+
+    // Un GADMIN ne peut pas attirer dans son groupe le membre d'un autre groupe
+    if !can_manage_user(&state, &*session.lock().await, &target).await {
+        warn!(
+            "SITE CHGRP {} refused, not one of the caller's users",
+            username
+        );
+        return respond_with_error(
+            &writer,
+            b"550 Permission denied, not one of your users.\r\n",
+        )
+        .await;
+    }
+
+    for group in groups {
+        if state.groups().get_group(group).is_none() {
+            let response = format!(
+                "{}\r\n",
+                GroupError::GroupNotFound(group.clone()).to_ftp_response()
+            );
+            return respond_with_error(&writer, response.as_bytes()).await;
+        }
+        let session = session.lock().await;
+        if !can_manage_group(&state, &session, group).await {
+            warn!("SITE CHGRP {} {} refused", username, group);
+            return respond_with_error(&writer, b"550 Permission denied, not your group.\r\n")
+                .await;
+        }
+        // Seuls les ajouts occupent un slot
+        if !target.is_member_of(group) {
+            if let Err(e) = check_slots(&state, &session, group, true, leech).await {
+                warn!("SITE CHGRP {} {} refused: {}", username, group, e);
+                let response = format!("{}\r\n", e.to_ftp_response());
+                return respond_with_error(&writer, response.as_bytes()).await;
+            }
+        }
+    }
+
+    // Bascule l'appartenance dans le userfile, qui fait foi
+    let result = state
+        .users()
+        .update(username, |user| {
+            let mut changes = Vec::new();
+            let mut was_gadmin = false;
+            for group in groups {
+                if user.is_member_of(group) {
+                    was_gadmin |= user.is_gadmin_of(group);
+                    user.groups.retain(|g| &g.name != group);
+                    changes.push((group.clone(), false));
+                } else {
+                    user.groups.push(UserGroup {
+                        name: group.clone(),
+                        gadmin: false,
+                    });
+                    changes.push((group.clone(), true));
+                }
+            }
+            if was_gadmin && !user.groups.iter().any(|g| g.gadmin) {
+                user.remove_flag(GADMIN);
+            }
+            changes
+        })
+        .await;
+    let changes = match result {
+        Ok(changes) => changes,
+        Err(e) => {
+            warn!("SITE CHGRP failed for {}: {}", username, e);
+            let response = format!("{}\r\n", e.to_ftp_response());
+            return respond_with_error(&writer, response.as_bytes()).await;
+        }
+    };
+
+    // Et la reporte dans groups.json
+    let mut response = String::new();
+    let mut failed = None;
+    for (group, added) in &changes {
+        let synced = if *added {
+            state.groups().add_user_to_group(username, group)
+        } else {
+            // Déjà absent de groups.json : rien à reporter
+            match state.groups().remove_user_from_group(username, group) {
+                Err(GroupError::UserNotFound(_)) => Ok(()),
+                result => result,
+            }
+        };
+        match synced {
+            Ok(()) if *added => {
+                response.push_str(&format!("200- {} added to group {}.\r\n", username, group))
+            }
+            Ok(()) => response.push_str(&format!(
+                "200- {} removed from group {}.\r\n",
+                username, group
+            )),
+            Err(e) => {
+                warn!("Failed to update group {} for {}: {}", group, username, e);
+                failed.get_or_insert(e);
+            }
+        }
+    }
+    if let Err(e) = state.groups().save() {
+        warn!("Failed to save the groups: {}", e);
+        let response = format!("{}\r\n", e.to_ftp_response());
+        return respond_with_error(&writer, response.as_bytes()).await;
+    }
+    if let Some(e) = failed {
+        response.push_str(&format!("{}\r\n", e.to_ftp_response()));
+        return respond_with_error(&writer, response.as_bytes()).await;
+    }
+
+    // Le groupe principal a pu changer
+    state.refresh_quota_settings().await;
+    info!("Groups of {} changed: {:?}", username, changes);
+    response.push_str("200 Command successful.\r\n");
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE CHGADMIN <utilisateur> <groupe>
+/// Fait de l'utilisateur un administrateur du groupe, ou lui retire ce rôle.
+/// Le flag GADMIN suit : il est ajouté avec le premier groupe administré et
+/// retiré avec le dernier.
+pub async fn handle_site_chgadmin_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    _session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    if args.len() != 2 {
+        return respond_with_error(&writer, b"501 Usage: SITE CHGADMIN <user> <group>\r\n").await;
+    }
+    let (username, group) = (&args[0], &args[1]);
+
+    let result = state
+        .users()
+        .update(username, |user| {
+            let entry = user.groups.iter_mut().find(|g| &g.name == group)?;
+            entry.gadmin = !entry.gadmin;
+            let gadmin = entry.gadmin;
+            if gadmin {
+                user.add_flag(GADMIN);
+            } else if !user.groups.iter().any(|g| g.gadmin) {
+                user.remove_flag(GADMIN);
+            }
+            Some(gadmin)
+        })
+        .await;
+
+    let response = match result {
+        Ok(Some(true)) => {
+            info!("{} is now group admin of {}", username, group);
+            format!("200 {} is now group admin of {}.\r\n", username, group)
+        }
+        Ok(Some(false)) => {
+            info!("{} is no longer group admin of {}", username, group);
+            format!(
+                "200 {} is no longer group admin of {}.\r\n",
+                username, group
+            )
+        }
+        Ok(None) => format!("550 {} is not a member of {}.\r\n", username, group),
+        Err(e) => {
+            warn!("SITE CHGADMIN failed for {}: {}", username, e);
+            format!("{}\r\n", e.to_ftp_response())
+        }
+    };
+    respond_with_success(&writer, response.as_bytes()).await
+}
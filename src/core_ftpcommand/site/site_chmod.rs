@@ -1,23 +1,26 @@
 // Commande SITE CHMOD - Changement de permissions
 // Inspiré de glFTPd
 
-use crate::core_quota::manager::QuotaManager;
+use crate::core_ftpcommand::site::helper::{
+    resolve_modifiable_path, respond_with_error, respond_with_success,
+};
+use crate::state::ServerState;
 use crate::{session::Session, Config};
-use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use log::{info, warn};
 use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 /// Gère la commande SITE CHMOD
-/// Permet de changer les permissions des fichiers et répertoires
+/// Permet de changer les permissions des fichiers et répertoires que
+/// l'utilisateur peut supprimer, selon les règles delete et deleteown
 
 pub async fn handle_site_chmod_command(
     writer: Arc<Mutex<TcpStream>>,
     config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     args: Vec<String>,
-    _quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE CHMOD command with args: {:?}", args);
 
@@ -41,25 +44,19 @@ pub async fn handle_site_chmod_command(
         }
     };
 
-    let session = session.lock().await;
-    let base_path = session.base_path.clone();
-
-    // Construire le chemin complet en toute sécurité
-    let file_path = base_path.join(file_path_str);
-
-    // Vérifier que le chemin est dans la zone autorisée
-    if !file_path.starts_with(&base_path) {
-        warn!("Path traversal attempt: {:?}", file_path);
-        respond_with_error(&writer, b"550 Path is outside of the allowed area.\r\n").await?;
-        return Ok(());
-    }
-
-    // Vérifier que le fichier existe
-    if !file_path.exists() {
-        warn!("File not found: {:?}", file_path);
-        respond_with_error(&writer, b"550 File not found.\r\n").await?;
-        return Ok(());
-    }
+    // Résoudre le chemin dans la zone de l'utilisateur et vérifier ses droits
+    let resolved = {
+        let session = session.lock().await;
+        resolve_modifiable_path(&config, &session, &state, file_path_str)
+    };
+    let file_path = match resolved {
+        Ok(file_path) => file_path,
+        Err(response) => {
+            warn!("SITE CHMOD refused on {}", file_path_str);
+            respond_with_error(&writer, response).await?;
+            return Ok(());
+        }
+    };
 
     // Changer les permissions
     #[cfg(unix)]
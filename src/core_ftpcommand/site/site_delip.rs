@@ -1,22 +1,20 @@
 use crate::constants::SITE_DELIP_HELP_PATH;
 use crate::constants::{MAX_DELIP_IPS, MIN_DELIP_ARGS};
 use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::core_ftpcommand::site::site_group::can_manage_username;
 use crate::helpers::send_file_to_client;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use log::{info, warn};
-use std::{
-    fs::{self, OpenOptions},
-    io::{Read, Write},
-    path::PathBuf,
-    sync::Arc,
-};
+use std::sync::Arc;
 use tokio::{net::TcpStream, sync::Mutex};
 
 pub async fn handle_site_delip_command(
     writer: Arc<Mutex<TcpStream>>,
     config: Arc<Config>,
-    _session: Arc<Mutex<Session>>,
+    session: Arc<Mutex<Session>>,
     args: Vec<String>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     if args.len() < MIN_DELIP_ARGS {
         warn!("Insufficient arguments for SITE DELIP: {:?}", args);
@@ -28,6 +26,19 @@ pub async fn handle_site_delip_command(
     let username = &args[0];
     let del_ips = &args[1..];
 
+    if !can_manage_username(&state, &session, username).await {
+        warn!(
+            "SITE DELIP {} refused, not in a group the caller administers",
+            username
+        );
+        respond_with_error(
+            &writer,
+            b"550 Permission denied, not one of your users.\r\n",
+        )
+        .await?;
+        return Ok(());
+    }
+
     if del_ips.len() > MAX_DELIP_IPS {
         warn!("Too many IPs for SITE DELIP: {:?}", args);
         send_file_to_client(&writer, &config.server.chroot_dir, SITE_DELIP_HELP_PATH).await?;
@@ -35,49 +46,28 @@ pub async fn handle_site_delip_command(
         return Ok(());
     }
 
-    let user_file_path = PathBuf::from(&config.server.chroot_dir)
-        .join("ftp-data/users")
-        .join(format!("{}.user", username));
-
-    if !user_file_path.exists() {
-        respond_with_error(&writer, b"550 User not found.\r\n").await?;
-        return Ok(());
-    }
-
-    let mut user_data = String::new();
-    {
-        let mut file = fs::File::open(&user_file_path)?;
-        file.read_to_string(&mut user_data)?;
-    }
-
-    let mut updated_user_data = String::new();
-    let mut ips_removed = 0;
-
-    for line in user_data.lines() {
-        let line_trimmed = line.trim();
-        if line_trimmed.starts_with("IP ") {
-            let ip = &line_trimmed[3..];
-            if del_ips.contains(&ip.to_string()) || del_ips.contains(&line_trimmed[3..].to_string())
-            {
-                ips_removed += 1;
-                continue;
-            }
+    let removed = state
+        .users()
+        .update(username, |user| {
+            let before = user.ips.len();
+            user.ips.retain(|ip| !del_ips.contains(ip));
+            before - user.ips.len()
+        })
+        .await;
+    match removed {
+        Ok(0) => {
+            respond_with_error(&writer, b"550 No matching IPs found.\r\n").await?;
+            return Ok(());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            warn!("SITE DELIP failed for {}: {}", username, e);
+            let response = format!("{}\r\n", e.to_ftp_response());
+            respond_with_error(&writer, response.as_bytes()).await?;
+            return Ok(());
         }
-        updated_user_data.push_str(line);
-        updated_user_data.push('\n');
-    }
-
-    if ips_removed == 0 {
-        respond_with_error(&writer, b"550 No matching IPs found.\r\n").await?;
-        return Ok(());
     }
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(&user_file_path)?;
-    file.write_all(updated_user_data.as_bytes())?;
-
     info!("IPs removed for user {}: {:?}", username, del_ips);
     respond_with_success(&writer, b"200 IP(s) removed successfully.\r\n").await?;
     Ok(())
@@ -1,26 +1,25 @@
 use crate::constants::{DELETED, MIN_DELUSER_ARGS, SITE_DELUSER_HELP_PATH};
 use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::core_ftpcommand::site::site_group::can_manage_username;
 use crate::helpers::send_file_to_client;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use log::{info, warn};
-use std::{
-    fs::{self, OpenOptions},
-    io::{Read, Write},
-    path::PathBuf,
-    sync::Arc,
-};
+use std::sync::Arc;
 use tokio::{net::TcpStream, sync::Mutex};
 
 /// Handles the SITE DELUSER command.
 ///
 /// This command marks a user as deleted by adding a deleted flag to their user file.
+/// Group admins may only delete the members of the groups they administer.
 ///
 /// # Arguments
 ///
 /// * `writer` - The TCP stream writer to send responses.
 /// * `config` - The server configuration.
-/// * `session` - The current FTP session (not used in this command).
+/// * `session` - The current FTP session, whose user must manage the account.
 /// * `args` - The command arguments.
+/// * `state` - The shared server state, holding the user store.
 ///
 /// # Returns
 ///
@@ -29,8 +28,9 @@ use tokio::{net::TcpStream, sync::Mutex};
 pub async fn handle_site_deluser_command(
     writer: Arc<Mutex<TcpStream>>,
     config: Arc<Config>,
-    _session: Arc<Mutex<Session>>,
+    session: Arc<Mutex<Session>>,
     args: Vec<String>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     if args.len() < MIN_DELUSER_ARGS {
         warn!("Insufficient arguments for SITE DELUSER: {:?}", args);
@@ -42,59 +42,30 @@ pub async fn handle_site_deluser_command(
 
     let username = &args[0];
 
-    let user_file_path = PathBuf::from(&config.server.chroot_dir)
-        .join("ftp-data/users")
-        .join(format!("{}.user", username));
-
-    if !user_file_path.exists() {
-        respond_with_error(&writer, b"550 User not found.\r\n").await?;
+    if !can_manage_username(&state, &session, username).await {
+        warn!(
+            "SITE DELUSER {} refused, not in a group the caller administers",
+            username
+        );
+        respond_with_error(
+            &writer,
+            b"550 Permission denied, not one of your users.\r\n",
+        )
+        .await?;
         return Ok(());
     }
 
-    let mut user_data = String::new();
+    if let Err(e) = state
+        .users()
+        .update(username, |user| user.add_flag(DELETED))
+        .await
     {
-        let mut file = fs::File::open(&user_file_path)?;
-        file.read_to_string(&mut user_data)?;
-    }
-
-    let mut updated_user_data = String::new();
-    let mut flag_found = false;
-
-    for line in user_data.lines() {
-        let line_trimmed = line.trim();
-        if line_trimmed.starts_with("FLAGS ") {
-            let flags: Vec<&str> = line_trimmed[6..].split(',').collect();
-            if flags.contains(&DELETED.to_string().as_str()) {
-                flag_found = true;
-                updated_user_data.push_str(line);
-                updated_user_data.push('\n');
-                continue;
-            } else {
-                updated_user_data.push_str("FLAGS ");
-                updated_user_data.push_str(&flags.join(","));
-                updated_user_data.push(',');
-                updated_user_data.push_str(&DELETED.to_string());
-                updated_user_data.push('\n');
-                flag_found = true;
-                continue;
-            }
-        }
-        updated_user_data.push_str(line);
-        updated_user_data.push('\n');
-    }
-
-    if !flag_found {
-        updated_user_data.push_str("FLAGS ");
-        updated_user_data.push_str(&DELETED.to_string());
-        updated_user_data.push('\n');
+        warn!("SITE DELUSER failed for {}: {}", username, e);
+        let response = format!("{}\r\n", e.to_ftp_response());
+        respond_with_error(&writer, response.as_bytes()).await?;
+        return Ok(());
     }
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(&user_file_path)?;
-    file.write_all(updated_user_data.as_bytes())?;
-
     info!("User {} marked as deleted.", username);
     respond_with_success(&writer, b"200 User marked as deleted.\r\n").await?;
     Ok(())
@@ -0,0 +1,88 @@
+// Commande SITE DIRQUOTA - quotas de répertoire, réservée aux siteops
+
+use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::core_quota::dir_quota::DirQuota;
+use crate::core_quota::user_file_parser::parse_size;
+use crate::state::ServerState;
+use crate::{session::Session, Config};
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Gère la commande SITE DIRQUOTA [<chemin> <taille> [fichiers]]
+/// Sans argument, liste les quotas de répertoire et leur usage. Sinon pose la
+/// taille maximale (0 = illimitée) et le nombre maximal de fichiers et de
+/// répertoires de l'arborescence ; une limite à 0 sans nombre de fichiers la
+/// retire.
+pub async fn handle_site_dirquota_command(
+    writer: Arc<Mutex<TcpStream>>,
+    config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    if args.is_empty() {
+        let mut response = String::from("200-Directory quotas:\r\n");
+        let list = state.dir_quotas().list();
+        if list.is_empty() {
+            response.push_str(" No directory quota.\r\n");
+        }
+        for (quota, usage) in &list {
+            response.push_str(&format!(
+                " {}: {}\r\n",
+                quota.path,
+                quota.format_usage(usage)
+            ));
+        }
+        response.push_str("200 Command successful.\r\n");
+        return respond_with_success(&writer, response.as_bytes()).await;
+    }
+
+    if !(2..=3).contains(&args.len()) {
+        return respond_with_error(
+            &writer,
+            b"501 Usage: SITE DIRQUOTA [<path> <size> [files]]\r\n",
+        )
+        .await;
+    }
+    let Ok(max_bytes) = parse_size(&args[1]) else {
+        return respond_with_error(&writer, b"501 Invalid size.\r\n").await;
+    };
+    let max_files = match args.get(2).map(|files| files.parse::<u64>()) {
+        Some(Ok(files)) => Some(files),
+        Some(Err(_)) => return respond_with_error(&writer, b"501 Invalid file count.\r\n").await,
+        None => None,
+    };
+
+    // Les chemins sont comptés depuis la racine du site
+    let site_root = config.site_root();
+    let canonical_root = site_root
+        .canonicalize()
+        .unwrap_or_else(|_| site_root.clone());
+    let real_path = session.lock().await.real_path(&args[0]);
+    let Ok(site_path) = real_path.strip_prefix(&canonical_root) else {
+        return respond_with_error(&writer, b"550 Path is outside of the site.\r\n").await;
+    };
+    let quota = DirQuota::new(&site_path.to_string_lossy(), max_bytes, max_files);
+
+    let response = match state.dir_quotas().set(&site_root, quota.clone()).await {
+        Ok(Some(usage)) => {
+            info!("Directory quota of {} set: {:?}", quota.path, quota);
+            format!(
+                "200 Directory quota of {} set: {}.\r\n",
+                quota.path,
+                quota.format_usage(&usage)
+            )
+        }
+        Ok(None) => {
+            info!("Directory quota of {} removed", quota.path);
+            format!("200 Directory quota of {} removed.\r\n", quota.path)
+        }
+        Err(e) => {
+            warn!("Failed to set the directory quota of {}: {}", quota.path, e);
+            format!("{}\r\n", e.to_ftp_response())
+        }
+    };
+    respond_with_success(&writer, response.as_bytes()).await
+}
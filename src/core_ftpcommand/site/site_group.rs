@@ -1,60 +1,489 @@
-// Commande SITE GROUP - Gestion des groupes d'utilisateurs
+// Commandes SITE GROUP, GROUPS, GINFO, GRPADD, GRPDEL et GRPCHANGE - Gestion des groupes
 // Inspiré de glFTPd
 
-use crate::core_quota::manager::QuotaManager;
+use crate::constants::{DELETED, GADMIN, SITEOP, USEREDIT, USERS};
+use crate::core_ftpcommand::site::helper::{
+    is_valid_group_name, respond_with_error, respond_with_success,
+};
+use crate::core_groups::error::GroupError;
+use crate::core_groups::group::{Group, GroupPermission};
+use crate::core_quota::ratio::UserRatio;
+use crate::core_quota::user_file_parser::parse_size;
+use crate::core_users::userfile::UserFile;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
-use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use log::{info, warn};
 use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
-/// Gère la commande SITE GROUP
-/// Permet d'afficher et de modifier les informations de groupe d'un utilisateur
+/// Vérifie que l'utilisateur peut gérer un groupe : les siteops et les
+/// co-siteops (USEREDIT) gèrent tous les groupes, les GADMIN seulement ceux
+/// dont ils sont administrateurs dans leur userfile.
+pub async fn can_manage_group(state: &ServerState, session: &Session, group: &str) -> bool {
+    if session.has_flag(SITEOP) || session.has_flag(USEREDIT) {
+        return true;
+    }
+    let Some(username) = session.username.as_deref() else {
+        return false;
+    };
+    if !session.has_flag(GADMIN) {
+        return false;
+    }
+    match state.users().get(username).await {
+        Ok(Some(user)) => user.is_gadmin_of(group),
+        _ => false,
+    }
+}
+
+/// Renvoie le premier groupe de `target` administré par l'utilisateur de la
+/// session, `None` s'il n'en administre aucun.
+pub async fn gadmin_group_of(
+    state: &ServerState,
+    session: &Session,
+    target: &UserFile,
+) -> Option<String> {
+    let username = session.username.as_deref()?;
+    if !session.has_flag(GADMIN) {
+        return None;
+    }
+    let admin = state.users().get(username).await.ok()??;
+    target
+        .groups
+        .iter()
+        .find(|group| admin.is_gadmin_of(&group.name))
+        .map(|group| group.name.clone())
+}
+
+/// Vérifie que l'utilisateur peut gérer le compte de `target` : les siteops et
+/// les co-siteops gèrent tous les comptes, les GADMIN seulement ceux des
+/// membres de leurs groupes, siteops exceptés.
+pub async fn can_manage_user(state: &ServerState, session: &Session, target: &UserFile) -> bool {
+    if session.has_flag(SITEOP) || session.has_flag(USEREDIT) {
+        return true;
+    }
+    !target.has_flag(SITEOP) && gadmin_group_of(state, session, target).await.is_some()
+}
+
+/// Comme `can_manage_user`, pour le compte `username`. Un compte inconnu est
+/// laissé à la commande, qui répondra qu'il n'existe pas.
+pub async fn can_manage_username(
+    state: &ServerState,
+    session: &Mutex<Session>,
+    username: &str,
+) -> bool {
+    match state.users().get(username).await {
+        Ok(Some(target)) => {
+            let session = session.lock().await;
+            can_manage_user(state, &session, &target).await
+        }
+        _ => true,
+    }
+}
+
+/// Renvoie l'occupation des slots d'un groupe : le nombre de ses membres et
+/// de ses membres leech (ratio 0), les utilisateurs supprimés ne comptant pas.
+pub async fn slot_usage(state: &ServerState, group: &Group) -> (usize, usize) {
+    let (mut members, mut leeches) = (0, 0);
+    for (username, _) in group_members(state, group).await {
+        match state.users().get(&username).await {
+            Ok(Some(user)) if user.has_flag(DELETED) => {}
+            Ok(Some(user)) => {
+                members += 1;
+                if user.ratio.first() == Some(&0) {
+                    leeches += 1;
+                }
+            }
+            _ => members += 1,
+        }
+    }
+    (members, leeches)
+}
+
+/// Vérifie les slots du groupe avant qu'un GADMIN y ajoute un membre
+/// (`new_member`) ou un leech. Les siteops et les co-siteops ne sont pas
+/// limités par les slots.
+pub async fn check_slots(
+    state: &ServerState,
+    session: &Session,
+    group_name: &str,
+    new_member: bool,
+    leech: bool,
+) -> Result<(), GroupError> {
+    if session.has_flag(SITEOP) || session.has_flag(USEREDIT) {
+        return Ok(());
+    }
+    let group = state
+        .groups()
+        .get_group(group_name)
+        .ok_or_else(|| GroupError::GroupNotFound(group_name.to_string()))?;
+    let (members, leeches) = slot_usage(state, &group).await;
+
+    if new_member && !group.has_free_slot(members) {
+        return Err(GroupError::NoFreeSlot(group_name.to_string()));
+    }
+    if leech && !group.has_free_leech_slot(leeches) {
+        return Err(GroupError::NoFreeLeechSlot(group_name.to_string()));
+    }
+    Ok(())
+}
+
+/// Renvoie les membres d'un groupe, avec leur statut d'administrateur : ceux
+/// dont le userfile a une ligne GROUP pour ce groupe et ceux de groups.json.
+pub async fn group_members(state: &ServerState, group: &Group) -> Vec<(String, bool)> {
+    let users = state.users();
+    let mut members = Vec::new();
+
+    for username in users.usernames().await.unwrap_or_default() {
+        if let Ok(Some(user)) = users.get(&username).await {
+            if user.is_member_of(&group.name) {
+                members.push((username, user.is_gadmin_of(&group.name)));
+            }
+        }
+    }
+    for username in &group.users {
+        if !members.iter().any(|(name, _)| name == username) {
+            members.push((username.clone(), false));
+        }
+    }
+
+    members.sort();
+    members
+}
+
+/// Enregistre les groupes, met à jour les quotas et ratios des membres et
+/// construit la réponse d'une commande réussie.
+async fn save_groups(state: &ServerState, success: String) -> String {
+    match state.groups().save() {
+        Ok(()) => {
+            state.refresh_quota_settings().await;
+            success
+        }
+        Err(e) => {
+            warn!("Failed to save the groups: {}", e);
+            format!("{}\r\n", e.to_ftp_response())
+        }
+    }
+}
 
+fn format_slots(used: usize, slots: Option<u32>) -> String {
+    match slots {
+        Some(slots) => format!("{}/{}", used, slots),
+        None => format!("{}/unlimited", used),
+    }
+}
+
+/// Lit un nombre de slots, `unlimited` ou -1 les supprimant.
+fn parse_slots(value: &str) -> Option<Option<u32>> {
+    match value.to_ascii_lowercase().as_str() {
+        "unlimited" | "-1" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
+
+fn format_quota(quota: u64) -> String {
+    if quota == 0 {
+        "unlimited".to_string()
+    } else {
+        format!("{:.1} MB", quota as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Gère la commande SITE GROUP [groupe]
+/// Sans argument, affiche les groupes de l'utilisateur ; avec un groupe, se
+/// comporte comme SITE GINFO.
 pub async fn handle_site_group_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     args: Vec<String>,
-    _quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE GROUP command with args: {:?}", args);
 
-    let session = session.lock().await;
-    let username = session
-        .username
-        .clone()
-        .unwrap_or_else(|| "anonymous".to_string());
-
-    // Validation des arguments
     if args.len() > 1 {
         warn!("Too many arguments for SITE GROUP command");
-        respond_with_error(&writer, b"501 Syntax error in parameters or arguments.\r\n").await?;
-        respond_with_error(&writer, b"501 Usage: SITE GROUP [groupname]\r\n").await?;
-        return Ok(());
+        return respond_with_error(&writer, b"501 Usage: SITE GROUP [groupname]\r\n").await;
+    }
+    if !args.is_empty() {
+        return handle_site_ginfo_command(writer, config, session, args, state).await;
     }
 
-    // En-tête de la réponse
-    let response = if args.is_empty() {
-        format!("200-Group information for {}:\r\n", username)
-    } else {
-        format!("200-Group command processed for {}:\r\n", username)
+    let (username, user_groups) = {
+        let session = session.lock().await;
+        (
+            session.username.clone().unwrap_or_default(),
+            session.groups.clone(),
+        )
+    };
+    let admin_of = match state.users().get(&username).await {
+        Ok(Some(user)) => user
+            .groups
+            .iter()
+            .filter(|group| group.gadmin)
+            .map(|group| group.name.clone())
+            .collect(),
+        _ => Vec::new(),
     };
-    respond_with_success(&writer, response.as_bytes()).await?;
+    let mut groups: Vec<String> = state
+        .groups()
+        .get_user_groups(&username)
+        .unwrap_or_default();
+    for group in user_groups {
+        if !groups.contains(&group) {
+            groups.push(group);
+        }
+    }
+    groups.sort();
 
-    // Ajouter des informations de groupe (à implémenter)
-    let group_info = if args.is_empty() {
-        "  Group: users (default)\r\n"
-    } else {
-        format!("  Group: {} (requested)\r\n", args[0])
+    let mut response = format!("200- Groups of {}:\r\n", username);
+    for group in &groups {
+        let marker = if admin_of.contains(group) {
+            " (group admin)"
+        } else {
+            ""
+        };
+        response.push_str(&format!("200-   {}{}\r\n", group, marker));
+    }
+    response.push_str(&format!("200 {} group(s).\r\n", groups.len()));
+
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE GROUPS
+/// Liste les groupes du site avec leur nombre de membres.
+pub async fn handle_site_groups_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    _session: Arc<Mutex<Session>>,
+    _args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let groups = state.groups().list_groups();
+
+    let mut response = String::from("200- Group            Members  Description\r\n");
+    for group in &groups {
+        let members = group_members(&state, group).await.len();
+        response.push_str(&format!(
+            "200- {:<16} {:<8} {}\r\n",
+            group.name, members, group.description
+        ));
+    }
+    response.push_str(&format!("200 {} group(s).\r\n", groups.len()));
+
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE GINFO <groupe>
+/// Affiche la description, le quota, le ratio, les permissions et les membres
+/// d'un groupe. Les GADMIN ne voient que les groupes qu'ils administrent.
+pub async fn handle_site_ginfo_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    if args.len() != 1 {
+        return respond_with_error(&writer, b"501 Usage: SITE GINFO <group>\r\n").await;
+    }
+    let name = &args[0];
+
+    let allowed = {
+        let session = session.lock().await;
+        session.has_flag(USERS)
+            || session.groups.contains(name)
+            || can_manage_group(&state, &session, name).await
     };
-    respond_with_success(&writer, group_info.as_bytes()).await?;
+    if !allowed {
+        warn!("SITE GINFO {} refused", name);
+        return respond_with_error(&writer, b"550 Permission denied, not your group.\r\n").await;
+    }
 
-    // Note sur l'implémentation future
-    respond_with_success(&writer, b"  Note: Full group management will be implemented in future versions\r\n").await?;
+    let Some(group) = state.groups().get_group(name) else {
+        let response = format!(
+            "{}\r\n",
+            GroupError::GroupNotFound(name.clone()).to_ftp_response()
+        );
+        return respond_with_error(&writer, response.as_bytes()).await;
+    };
 
-    respond_with_success(&writer, b"200 GROUP command successful.\r\n").await?;
+    let permissions: Vec<&str> = GroupPermission::ALL
+        .iter()
+        .filter(|permission| group.permissions.has_permission(permission))
+        .map(|permission| permission.name())
+        .collect();
+    let members = group_members(&state, &group).await;
+    let (used, leeches) = slot_usage(&state, &group).await;
 
-    Ok(())
+    let mut response = format!("200- Group: {}\r\n", group.name);
+    response.push_str(&format!("200- Description: {}\r\n", group.description));
+    response.push_str(&format!(
+        "200- Quota: {}  Ratio: {}  Total quota: {}\r\n",
+        format_quota(group.quota),
+        group.ratio,
+        format_quota(group.total_quota)
+    ));
+    response.push_str(&format!(
+        "200- Slots: {}  Leech slots: {}\r\n",
+        format_slots(used, group.slots),
+        format_slots(leeches, group.leech_slots)
+    ));
+    response.push_str(&format!("200- Permissions: {}\r\n", permissions.join(" ")));
+    response.push_str(&format!(
+        "200- Members ({}, * = group admin):\r\n",
+        members.len()
+    ));
+    for (member, gadmin) in &members {
+        let marker = if *gadmin { "*" } else { " " };
+        response.push_str(&format!("200-   {}{}\r\n", marker, member));
+    }
+    response.push_str("200 End of GINFO.\r\n");
+
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE GRPADD <groupe> [description]
+pub async fn handle_site_grpadd_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    _session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let Some(name) = args.first() else {
+        return respond_with_error(&writer, b"501 Usage: SITE GRPADD <group> [description]\r\n")
+            .await;
+    };
+    if !is_valid_group_name(name) {
+        return respond_with_error(&writer, b"501 Invalid group name.\r\n").await;
+    }
+    let description = args[1..].join(" ");
+
+    let response = match state.groups().create_group(name, &description) {
+        Ok(()) => {
+            info!("Group {} added", name);
+            save_groups(&state, format!("200 Group {} added.\r\n", name)).await
+        }
+        Err(e) => {
+            warn!("SITE GRPADD failed for {}: {}", name, e);
+            format!("{}\r\n", e.to_ftp_response())
+        }
+    };
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE GRPDEL <groupe>
+/// Seuls les groupes sans membres peuvent être supprimés.
+pub async fn handle_site_grpdel_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    _session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    if args.len() != 1 {
+        return respond_with_error(&writer, b"501 Usage: SITE GRPDEL <group>\r\n").await;
+    }
+    let name = &args[0];
+
+    let result = match state.groups().get_group(name) {
+        Some(group) if !group_members(&state, &group).await.is_empty() => {
+            Err(GroupError::GroupNotEmpty(name.clone()))
+        }
+        _ => state.groups().delete_group(name),
+    };
+    let response = match result {
+        Ok(()) => {
+            info!("Group {} deleted", name);
+            save_groups(&state, format!("200 Group {} deleted.\r\n", name)).await
+        }
+        Err(e) => {
+            warn!("SITE GRPDEL failed for {}: {}", name, e);
+            format!("{}\r\n", e.to_ftp_response())
+        }
+    };
+    respond_with_success(&writer, response.as_bytes()).await
+}
+
+/// Gère la commande SITE GRPCHANGE <groupe> <champ> <valeur>
+/// Les champs sont `description`, `quota` (par membre, taille, 0 = illimité),
+/// `totalquota` (pour tous les membres dont c'est le groupe principal), `ratio`
+/// (upload:download), `slots` et `leechslots` (nombre ou `unlimited`) et les
+/// permissions (`upload on`, `delete off`...).
+pub async fn handle_site_grpchange_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    _session: Arc<Mutex<Session>>,
+    args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    if args.len() < 3 {
+        return respond_with_error(
+            &writer,
+            b"501 Usage: SITE GRPCHANGE <group> <description|quota|totalquota|ratio|slots|leechslots|permission> <value>\r\n",
+        )
+        .await;
+    }
+    let (name, field, value) = (&args[0], args[1].to_ascii_lowercase(), args[2..].join(" "));
+    let groups = state.groups();
+
+    let result = match field.as_str() {
+        "description" => groups.set_group_description(name, &value),
+        "quota" => match parse_size(&value) {
+            Ok(quota) => groups.set_group_quota(name, quota),
+            Err(_) => return respond_with_error(&writer, b"501 Invalid quota.\r\n").await,
+        },
+        "totalquota" => match parse_size(&value) {
+            Ok(quota) => groups.set_group_total_quota(name, quota),
+            Err(_) => return respond_with_error(&writer, b"501 Invalid quota.\r\n").await,
+        },
+        "slots" | "leechslots" => {
+            let Some(slots) = parse_slots(&value) else {
+                return respond_with_error(&writer, b"501 Invalid number of slots.\r\n").await;
+            };
+            match groups.get_group(name) {
+                Some(group) if field == "slots" => {
+                    groups.set_group_slots(name, slots, group.leech_slots)
+                }
+                Some(group) => groups.set_group_slots(name, group.slots, slots),
+                None => Err(GroupError::GroupNotFound(name.clone())),
+            }
+        }
+        "ratio" => match UserRatio::new(name, &value) {
+            Ok(_) => groups.set_group_ratio(name, &value),
+            Err(_) => return respond_with_error(&writer, b"501 Invalid ratio.\r\n").await,
+        },
+        _ => {
+            let enabled = match value.to_ascii_lowercase().as_str() {
+                "on" | "yes" | "1" => true,
+                "off" | "no" | "0" => false,
+                _ => {
+                    return respond_with_error(&writer, b"501 Permissions are on or off.\r\n").await
+                }
+            };
+            match GroupPermission::from_name(&field) {
+                Some(permission) => groups.set_group_permission(name, &permission, enabled),
+                None => {
+                    return respond_with_error(&writer, b"501 Unknown group setting.\r\n").await
+                }
+            }
+        }
+    };
+
+    let response = match result {
+        Ok(()) => {
+            info!("Group {}: {} set to {}", name, field, value);
+            save_groups(
+                &state,
+                format!("200 Group {}: {} set to {}.\r\n", name, field, value),
+            )
+            .await
+        }
+        Err(e) => {
+            warn!("SITE GRPCHANGE failed for {}: {}", name, e);
+            format!("{}\r\n", e.to_ftp_response())
+        }
+    };
+    respond_with_success(&writer, response.as_bytes()).await
 }
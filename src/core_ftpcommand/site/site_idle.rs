@@ -1,57 +1,111 @@
 // Commande SITE IDLE - Gestion de l'inactivité
 // Inspiré de glFTPd
 
+use crate::constants::MIN_IDLE_TIMEOUT;
+use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use crate::core_quota::manager::QuotaManager;
 use crate::{session::Session, Config};
-use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use log::info;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 /// Gère la commande SITE IDLE
-/// Affiche le temps d'inactivité de la session actuelle
-
+/// Sans argument, affiche le temps d'inactivité et le délai de déconnexion de la session.
+/// Avec un argument, modifie le délai de déconnexion de la session (en secondes).
 pub async fn handle_site_idle_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
-    _args: Vec<String>,
+    args: Vec<String>,
     _quota_manager: Option<Arc<QuotaManager>>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE IDLE command");
 
-    let session = session.lock().await;
+    let mut session = session.lock().await;
     let username = session
         .username
         .clone()
         .unwrap_or_else(|| "anonymous".to_string());
 
-    // Pour l'instant, nous simulons le temps d'inactivité
-    // Dans une implémentation complète, nous suivrions le dernier timestamp d'activité
-    let start_time = SystemTime::now();
-    let since_epoch = start_time
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
+    if let Some(value) = args.first() {
+        let requested: u64 = match value.parse() {
+            Ok(secs) => secs,
+            Err(_) => {
+                respond_with_error(&writer, b"501 Usage: SITE IDLE [seconds]\r\n").await?;
+                return Ok(());
+            }
+        };
 
-    // Simuler un temps d'inactivité (en secondes)
-    let idle_time = since_epoch.as_secs() % 300; // 0-300 secondes pour la démo
+        // 0 désactive le délai : réservé aux utilisateurs IDLER ou EXEMPT
+        if requested == 0 {
+            if !session.is_idle_exempt() {
+                respond_with_error(&writer, b"550 You are not allowed to idle forever.\r\n")
+                    .await?;
+                return Ok(());
+            }
+        } else if requested < MIN_IDLE_TIMEOUT {
+            respond_with_error(
+                &writer,
+                format!("550 Idle time must be at least {} seconds.\r\n", MIN_IDLE_TIMEOUT)
+                    .as_bytes(),
+            )
+            .await?;
+            return Ok(());
+        } else if !session.is_idle_exempt() {
+            // Un utilisateur normal ne peut pas dépasser le délai du serveur
+            if let Some(max) = config.server.idle_timeout_duration() {
+                if requested > max.as_secs() {
+                    respond_with_error(
+                        &writer,
+                        format!(
+                            "550 Idle time cannot exceed {} seconds.\r\n",
+                            max.as_secs()
+                        )
+                        .as_bytes(),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        session.idle_timeout = Some(requested);
+        info!("Idle timeout of {} set to {} seconds", username, requested);
+        respond_with_success(
+            &writer,
+            format!("200 Idle time set to {} seconds.\r\n", requested).as_bytes(),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Temps d'inactivité avant la commande SITE IDLE elle-même
+    let idle_time = session.last_idle.as_secs();
+    let timeout = session.effective_idle_timeout(&config);
 
     // En-tête de la réponse
     respond_with_success(&writer, b"200-IDLE command:\r\n").await?;
 
     // Afficher le temps d'inactivité
     respond_with_success(&writer, format!("  User: {}\r\n", username).as_bytes()).await?;
-    respond_with_success(&writer, format!("  Idle time: {} seconds\r\n", idle_time).as_bytes()).await?;
-
-    // Statut
-    if idle_time > 180 {
-        respond_with_success(&writer, b"  Status: About to timeout\r\n").await?;
-    } else if idle_time > 120 {
-        respond_with_success(&writer, b"  Status: Idle\r\n").await?;
-    } else {
-        respond_with_success(&writer, b"  Status: Active\r\n").await?;
+    respond_with_success(
+        &writer,
+        format!("  Idle time: {} seconds\r\n", idle_time).as_bytes(),
+    )
+    .await?;
+
+    match timeout {
+        Some(timeout) => {
+            respond_with_success(
+                &writer,
+                format!("  Idle timeout: {} seconds\r\n", timeout.as_secs()).as_bytes(),
+            )
+            .await?;
+        }
+        None => {
+            respond_with_success(&writer, b"  Idle timeout: none\r\n").await?;
+        }
     }
 
     respond_with_success(&writer, b"200 IDLE command successful.\r\n").await?;
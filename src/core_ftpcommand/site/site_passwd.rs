@@ -0,0 +1,91 @@
+// Commandes SITE PASSWD et SITE CHPASS - Changement de mot de passe
+// Inspiré de glFTPd
+
+use crate::core_auth::password::change_password;
+use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::state::ServerState;
+use crate::{session::Session, Config};
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Gère la commande SITE PASSWD <nouveau mot de passe>
+/// Permet à un utilisateur connecté de changer son propre mot de passe. Le mot
+/// de passe est le reste de la ligne, espaces compris.
+pub async fn handle_site_passwd_command(
+    writer: Arc<Mutex<TcpStream>>,
+    config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    password: String,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let username = {
+        let session = session.lock().await;
+        match &session.username {
+            Some(username) if session.is_authenticated && !session.is_anonymous => username.clone(),
+            _ => {
+                return respond_with_error(&writer, b"530 Not logged in.\r\n").await;
+            }
+        }
+    };
+
+    if password.is_empty() {
+        return respond_with_error(&writer, b"501 Usage: SITE PASSWD <new password>\r\n").await;
+    }
+
+    match change_password(&state, &config, &username, &password, false).await {
+        Ok(()) => {
+            session.lock().await.must_change_password = false;
+            respond_with_success(&writer, b"200 Password changed.\r\n").await
+        }
+        Err(e) => {
+            warn!("SITE PASSWD failed for {}: {}", username, e);
+            let response = format!("{}\r\n", e.to_ftp_response());
+            respond_with_error(&writer, response.as_bytes()).await
+        }
+    }
+}
+
+/// Gère la commande SITE CHPASS <utilisateur> <nouveau mot de passe>
+/// Réservée aux siteops, peut obliger l'utilisateur à changer son mot de passe
+/// à la prochaine connexion (force_change_on_chpass). Le mot de passe est le
+/// reste de la ligne, espaces compris.
+pub async fn handle_site_chpass_command(
+    writer: Arc<Mutex<TcpStream>>,
+    config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    args: String,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let siteop = session.lock().await.username.clone().unwrap_or_default();
+
+    let Some((username, password)) = args
+        .split_once(' ')
+        .filter(|(username, password)| !username.is_empty() && !password.is_empty())
+    else {
+        return respond_with_error(&writer, b"501 Usage: SITE CHPASS <user> <new password>\r\n")
+            .await;
+    };
+
+    let must_change = config.password_policy.force_change_on_chpass();
+    match change_password(&state, &config, username, password, must_change).await {
+        Ok(()) => {
+            info!("{} changed the password of {}", siteop, username);
+            let response = if must_change {
+                format!(
+                    "200 Password of {} changed, it must be changed on next login.\r\n",
+                    username
+                )
+            } else {
+                format!("200 Password of {} changed.\r\n", username)
+            };
+            respond_with_success(&writer, response.as_bytes()).await
+        }
+        Err(e) => {
+            warn!("SITE CHPASS failed for {}: {}", username, e);
+            let response = format!("{}\r\n", e.to_ftp_response());
+            respond_with_error(&writer, response.as_bytes()).await
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // Commande SITE QUOTA - inspiré de glFTPd
 
+use crate::constants::SITEOP;
 use crate::core_quota::manager::QuotaManager;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use log::{info, warn};
@@ -9,7 +11,8 @@ use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 /// Gère la commande SITE QUOTA
-/// Affiche les informations de quota pour l'utilisateur actuel
+/// Affiche les informations de quota pour l'utilisateur actuel.
+/// SITE QUOTA RESCAN <utilisateur> recalcule l'espace utilisé (siteops).
 
 pub async fn handle_site_quota_command(
     writer: Arc<Mutex<TcpStream>>,
@@ -17,13 +20,21 @@ pub async fn handle_site_quota_command(
     session: Arc<Mutex<Session>>,
     args: Vec<String>,
     quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE QUOTA command");
 
+    if args
+        .first()
+        .is_some_and(|arg| arg.eq_ignore_ascii_case("RESCAN"))
+    {
+        return handle_site_quota_rescan(writer, session, &args[1..], quota_manager, state).await;
+    }
+
     // Validation des arguments - cette commande ne prend pas d'arguments
     if !args.is_empty() {
         warn!("SITE QUOTA command does not accept arguments");
-        respond_with_error(&writer, b"501 SITE QUOTA does not accept arguments.\r\n").await?;
+        respond_with_error(&writer, b"501 Usage: SITE QUOTA [RESCAN <user>]\r\n").await?;
         return Ok(());
     }
 
@@ -38,11 +49,20 @@ pub async fn handle_site_quota_command(
         match quota_mgr.get_quota_info(&username, base_dir).await {
             Ok(quota_info) => {
                 let response = format!("200-Quota information for {}:\r\n", username);
-                let mut writer = writer.lock().await;
-                writer.write_all(response.as_bytes()).await?;
+                respond_with_success(&writer, response.as_bytes()).await?;
 
                 let info_response = format!(" {}\r\n", quota_info);
-                writer.write_all(info_response.as_bytes()).await?;
+                respond_with_success(&writer, info_response.as_bytes()).await?;
+
+                if let Some(warning) = quota_mgr.soft_quota_warning(&username).await {
+                    let warning_response = format!(" {}\r\n", warning);
+                    respond_with_success(&writer, warning_response.as_bytes()).await?;
+                }
+
+                if let Some(group_info) = quota_mgr.get_group_quota_info(&username).await {
+                    let group_response = format!(" {}\r\n", group_info);
+                    respond_with_success(&writer, group_response.as_bytes()).await?;
+                }
 
                 respond_with_success(&writer, b"200 Quota command successful.\r\n").await?;
                 info!("Sent quota info for user {}: {}", username, quota_info);
@@ -58,3 +78,68 @@ pub async fn handle_site_quota_command(
 
     Ok(())
 }
+
+/// Gère la commande SITE QUOTA RESCAN <utilisateur>
+/// Recalcule l'espace utilisé par l'utilisateur à partir de la taille sur le
+/// disque des fichiers qu'il a envoyés sous le répertoire de base de son quota.
+async fn handle_site_quota_rescan(
+    writer: Arc<Mutex<TcpStream>>,
+    session: Arc<Mutex<Session>>,
+    args: &[String],
+    quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let base_dir = {
+        let session = session.lock().await;
+        if !session.has_flag(SITEOP) {
+            return respond_with_error(
+                &writer,
+                b"550 Permission denied, SITE QUOTA RESCAN requires the SITEOP flag.\r\n",
+            )
+            .await;
+        }
+        session.base_path.clone()
+    };
+    let [username] = args else {
+        return respond_with_error(&writer, b"501 Usage: SITE QUOTA RESCAN <user>\r\n").await;
+    };
+    let Some(quota_mgr) = quota_manager else {
+        return respond_with_error(&writer, b"550 Quota system is not enabled.\r\n").await;
+    };
+    if !matches!(state.users().get(username).await, Ok(Some(_))) {
+        return respond_with_error(&writer, b"550 User does not exist.\r\n").await;
+    }
+
+    let result = match quota_mgr.get_or_create_user_quota(username, base_dir).await {
+        Ok(quota) => {
+            let used_bytes = state
+                .owners()
+                .usage_below(&quota.base_dir)
+                .await
+                .remove(username.as_str())
+                .unwrap_or(0);
+            quota_mgr
+                .set_used_bytes(username, quota.base_dir, used_bytes)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(quota) => {
+            info!(
+                "Quota of {} rescanned: {} bytes used",
+                username, quota.used_bytes
+            );
+            let response = format!(
+                "200 Quota of {} rescanned: {:.2}MB used.\r\n",
+                username,
+                quota.used_bytes as f64 / (1024.0 * 1024.0)
+            );
+            respond_with_success(&writer, response.as_bytes()).await
+        }
+        Err(e) => {
+            warn!("Failed to rescan the quota of {}: {}", username, e);
+            respond_with_error(&writer, b"550 Failed to rescan the quota.\r\n").await
+        }
+    }
+}
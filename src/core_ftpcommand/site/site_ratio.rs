@@ -2,6 +2,7 @@
 
 use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use crate::core_quota::manager::QuotaManager;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 use log::{info, warn};
 use std::sync::Arc;
@@ -9,7 +10,8 @@ use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 /// Gère la commande SITE RATIO
-/// Affiche les informations de ratio pour l'utilisateur actuel
+/// Affiche les informations de ratio pour l'utilisateur actuel, avec ses
+/// crédits tirés du userfile
 
 pub async fn handle_site_ratio_command(
     writer: Arc<Mutex<TcpStream>>,
@@ -17,6 +19,7 @@ pub async fn handle_site_ratio_command(
     session: Arc<Mutex<Session>>,
     args: Vec<String>,
     quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE RATIO command");
 
@@ -33,27 +36,47 @@ pub async fn handle_site_ratio_command(
         .clone()
         .unwrap_or_else(|| "anonymous".to_string());
 
-    if let Some(quota_mgr) = quota_manager {
-        match quota_mgr.get_ratio_info(&username).await {
-            Ok(ratio_info) => {
-                let response = format!("200-Ratio information for {}:\r\n", username);
-                let mut writer = writer.lock().await;
-                writer.write_all(response.as_bytes()).await?;
-
-                let info_response = format!(" {}\r\n", ratio_info);
-                writer.write_all(info_response.as_bytes()).await?;
-
-                respond_with_success(&writer, b"200 Ratio command successful.\r\n").await?;
-                info!("Sent ratio info for user {}: {}", username, ratio_info);
-            }
+    let ratio = state.user_ratio(&username).await;
+    let ratio_info = match quota_manager {
+        Some(quota_mgr) => match quota_mgr.get_ratio_info(&username).await {
+            Ok(ratio_info) => ratio_info,
             Err(e) => {
                 warn!("Failed to get ratio info for user {}: {}", username, e);
-                respond_with_error(&writer, b"550 Failed to retrieve ratio information.\r\n").await?;
+                respond_with_error(&writer, b"550 Failed to retrieve ratio information.\r\n")
+                    .await?;
+                return Ok(());
             }
-        }
-    } else {
-        respond_with_error(&writer, b"550 Quota system is not enabled.\r\n").await?;
+        },
+        // Sans section [quota], les octets transférés ne sont pas suivis
+        None => match &ratio {
+            Some(ratio) => format!("Ratio: {}", ratio.configured_ratio_string()),
+            None => "Unlimited".to_string(),
+        },
+    };
+
+    let response = format!("200-Ratio information for {}:\r\n", username);
+    respond_with_success(&writer, response.as_bytes()).await?;
+
+    let info_response = format!(" {}\r\n", ratio_info);
+    respond_with_success(&writer, info_response.as_bytes()).await?;
+
+    // Un leech n'a pas besoin de crédits
+    let leech = ratio.as_ref().is_none_or(|ratio| ratio.is_unlimited);
+    let credits = match state.users().get(&username).await {
+        Ok(Some(_)) if leech => Some("Unlimited (leech)".to_string()),
+        Ok(Some(user)) => Some(format!(
+            "{:.2}MB",
+            user.credits_bytes() as f64 / (1024.0 * 1024.0)
+        )),
+        _ => None,
+    };
+    if let Some(credits) = credits {
+        let credits_response = format!(" Credits: {}\r\n", credits);
+        respond_with_success(&writer, credits_response.as_bytes()).await?;
     }
 
+    respond_with_success(&writer, b"200 Ratio command successful.\r\n").await?;
+    info!("Sent ratio info for user {}: {}", username, ratio_info);
+
     Ok(())
 }
@@ -0,0 +1,43 @@
+// Commande SITE REHASH - Rechargement de la configuration
+// Inspiré de glFTPd
+
+use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::state::ServerState;
+use crate::{session::Session, Config};
+use log::{error, info};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Gère la commande SITE REHASH
+/// Recharge le fichier de configuration, réservé aux siteops.
+/// Les sessions ouvertes gardent leur configuration, les nouvelles utilisent la nouvelle.
+pub async fn handle_site_rehash_command(
+    writer: Arc<Mutex<TcpStream>>,
+    _config: Arc<Config>,
+    session: Arc<Mutex<Session>>,
+    _args: Vec<String>,
+    state: Arc<ServerState>,
+) -> Result<(), std::io::Error> {
+    let username = session.lock().await.username.clone().unwrap_or_default();
+
+    info!("SITE REHASH requested by {}", username);
+
+    match state.reload().await {
+        Ok(_) => {
+            respond_with_success(
+                &writer,
+                b"200 Configuration reloaded, it applies to new connections.\r\n",
+            )
+            .await
+        }
+        Err(e) => {
+            error!("Configuration reload failed, keeping the current one: {:#}", e);
+            respond_with_error(
+                &writer,
+                format!("550 Configuration reload failed: {}\r\n", e).as_bytes(),
+            )
+            .await
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
+use crate::session::LoginLimits;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
 
 use crate::constants::DELETED;
+use crate::core_users::UserFile;
 use std::{
     fs::{self},
     io::Read,
-    path::{Path, PathBuf},
+    path::PathBuf,
     sync::Arc,
 };
 use tokio::{net::TcpStream, sync::Mutex};
@@ -17,40 +20,33 @@ pub async fn handle_site_user_command(
     config: Arc<Config>,
     _session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     let args: Vec<&str> = arg.split_whitespace().collect();
     if args.len() < MIN_SITE_USER_ARGS {
-        list_all_users(writer, config).await
+        list_all_users(writer, state).await
     } else {
         let username = args[0].to_string();
-        show_user_info(writer, config, _session, &username).await
+        show_user_info(writer, config, _session, &username, state).await
     }
 }
 
 async fn list_all_users(
     writer: Arc<Mutex<TcpStream>>,
-    config: Arc<Config>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
-    let users_dir = PathBuf::from(&config.server.chroot_dir).join("ftp-data/users");
-
-    let mut users = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(users_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Ok(file_name) = entry.file_name().into_string() {
-                    if file_name.ends_with(".user") {
-                        let user_name = file_name.trim_end_matches(".user").to_string();
-                        if !is_user_deleted(&entry.path()) {
-                            users.push(user_name);
-                        }
-                    }
-                }
+    let users = state.users();
+
+    let mut active_users = Vec::new();
+    for username in users.usernames().await.unwrap_or_default() {
+        if let Ok(Some(user)) = users.get(&username).await {
+            if !user.has_flag(DELETED) {
+                active_users.push(username);
             }
         }
     }
 
-    let response = format!("200 Non-deleted users: {}\r\n", users.join(", "));
+    let response = format!("200 Non-deleted users: {}\r\n", active_users.join(", "));
     respond_with_success(&writer, response.as_bytes()).await
 }
 
@@ -59,24 +55,23 @@ async fn show_user_info(
     config: Arc<Config>,
     _session: Arc<Mutex<Session>>,
     username: &str,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
-    let user_file_path = PathBuf::from(&config.server.chroot_dir)
-        .join("ftp-data/users")
-        .join(format!("{}.user", username));
-
-    if !user_file_path.exists() {
-        respond_with_error(&writer, b"550 User not found.\r\n").await?;
-        return Ok(());
-    }
-
-    let mut user_data = String::new();
-    {
-        let mut file = fs::File::open(&user_file_path)?;
-        file.read_to_string(&mut user_data)?;
-    }
+    let user = match state.users().get(username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            respond_with_error(&writer, b"550 User not found.\r\n").await?;
+            return Ok(());
+        }
+        Err(e) => {
+            let response = format!("{}\r\n", e.to_ftp_response());
+            respond_with_error(&writer, response.as_bytes()).await?;
+            return Ok(());
+        }
+    };
 
-    // Parse user file
-    let user_info = parse_user_data(&user_data);
+    let mut user_info = user_info_of(&user);
+    user_info.current_logins = state.sessions().logins_of(username);
 
     // Load and replace statline template
     let user_info_template = load_user_info_template(config.clone()).await?;
@@ -85,48 +80,65 @@ async fn show_user_info(
     respond_with_success(&writer, cleaned_info.as_bytes()).await
 }
 
-fn parse_user_data(user_data: &str) -> UserInfo {
-    let mut user_info = UserInfo::default();
-
-    for line in user_data.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 2 {
-            continue;
+fn user_info_of(user: &UserFile) -> UserInfo {
+    let extra = |key: &str| user.get(key).unwrap_or_default().to_string();
+    // GENERAL: WKLY_ALLOTMENT, IDLE_TIME, MAX_DLSPEED, MAX_ULSPEED (KB/s, 0 = unlimited)
+    let speed = |speed: i64| {
+        if speed > 0 {
+            format!("{:.1} KB/s", speed as f64)
+        } else {
+            "Unlimited".to_string()
         }
-        let key = parts[0];
-        let value = parts[1..].join(" ");
-
-        match key {
-            "USER" => user_info.username = value,
-            "ADDED" => user_info.created = value,
-            "ADDEDBY" => user_info.added_by = value,
-            "EXPIRES" => user_info.expires = value,
-            "LAST" => user_info.last_seen = value,
-            "FLAGS" => user_info.flags = value,
-            "CREDITS" => user_info.credits = value,
-            "LOGINS" => {
-                let logins: Vec<&str> = value.split_whitespace().collect();
-                if logins.len() >= 2 {
-                    user_info.total_logins = logins[0].parse().unwrap_or_default();
-                    user_info.current_logins = logins[1].parse().unwrap_or_default();
-                }
-            }
-            "MAXLOGINS" => user_info.max_logins = value,
-            "MAXIP" => user_info.from_same_ip = value,
-            "MAXUP" => user_info.max_sim_uploads = value,
-            "MAXDN" => user_info.max_sim_downloads = value,
-            "MAXUPSP" => user_info.max_upload_speed = value,
-            "MAXDNSP" => user_info.max_download_speed = value,
-            "NUKE" => user_info.times_nuked = value,
-            "BYTESNUKED" => user_info.bytes_nuked = value,
-            "WKLYALLOT" => user_info.weekly_allotment = value,
-            "MSGS" => user_info.messages_waiting = value,
-            "TIMELIMIT" => user_info.time_limit = value,
-            "TIMEFRAME" => user_info.timeframe = value,
-            "TAGLINE" => user_info.tagline = value,
-            "GROUPS" => user_info.groups = value,
-            "PRIVGROUPS" => user_info.priv_groups = value,
-            _ => {}
+    };
+    let limits = user.login_limits();
+
+    let mut user_info = UserInfo {
+        username: user.username.clone(),
+        created: extra("ADDED"),
+        added_by: extra("ADDEDBY"),
+        expires: extra("EXPIRES"),
+        last_seen: extra("LAST"),
+        flags: user.flags.clone(),
+        credits: user
+            .credits
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" "),
+        total_logins: user.time.login_times as usize,
+        max_logins: LoginLimits::display(limits.max_logins),
+        from_same_ip: LoginLimits::display(limits.max_logins_per_ip),
+        max_sim_downloads: LoginLimits::display(limits.max_sim_downloads),
+        max_sim_uploads: LoginLimits::display(limits.max_sim_uploads),
+        max_download_speed: speed(user.general.max_download_speed),
+        max_upload_speed: speed(user.general.max_upload_speed),
+        times_nuked: user.nuke.times_nuked.to_string(),
+        bytes_nuked: user.nuke.megabytes_nuked.to_string(),
+        weekly_allotment: user.general.weekly_allotment.clone(),
+        messages_waiting: extra("MSGS"),
+        time_limit: user.time.time_limit.to_string(),
+        timeframe: extra("TIMEFRAME"),
+        tagline: user.tagline.clone(),
+        groups: user.group_names().join(" "),
+        priv_groups: extra("PRIVGROUPS"),
+        ..Default::default()
+    };
+
+    // Older userfiles keep some of the values on lines of their own
+    for (key, field) in [
+        ("MAXLOGINS", &mut user_info.max_logins),
+        ("MAXIP", &mut user_info.from_same_ip),
+        ("MAXUP", &mut user_info.max_sim_uploads),
+        ("MAXDN", &mut user_info.max_sim_downloads),
+        ("MAXUPSP", &mut user_info.max_upload_speed),
+        ("MAXDNSP", &mut user_info.max_download_speed),
+        ("BYTESNUKED", &mut user_info.bytes_nuked),
+        ("WKLYALLOT", &mut user_info.weekly_allotment),
+        ("TIMELIMIT", &mut user_info.time_limit),
+        ("GROUPS", &mut user_info.groups),
+    ] {
+        if let Some(value) = user.get(key) {
+            *field = value.to_string();
         }
     }
 
@@ -192,20 +204,6 @@ fn format_user_info(template: &str, user_info: &UserInfo) -> String {
         .replace("%[%-57s]IY", &user_info.priv_groups)
 }
 
-fn is_user_deleted(user_file_path: &Path) -> bool {
-    let mut user_data = String::new();
-    if let Ok(mut file) = fs::File::open(user_file_path) {
-        if let Ok(_) = file.read_to_string(&mut user_data) {
-            for line in user_data.lines() {
-                if line.starts_with("FLAGS") {
-                    return line.contains(DELETED.to_string().as_str());
-                }
-            }
-        }
-    }
-    false
-}
-
 // Helper function to load user info template
 async fn load_user_info_template(config: Arc<Config>) -> Result<String, std::io::Error> {
     let template_path = PathBuf::from(&config.server.chroot_dir).join("ftp-data/text/user.txt");
@@ -1,5 +1,7 @@
+use crate::core_ftpcommand::site::helper::resolve_modifiable_path;
 use crate::helpers::{sanitize_input, send_response};
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use chrono::NaiveDateTime;
@@ -11,9 +13,10 @@ use tokio::sync::Mutex;
 
 pub async fn handle_site_utime_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     arg: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE UTIME command with arguments: {}", arg);
 
@@ -32,30 +35,22 @@ pub async fn handle_site_utime_command(
     info!("Parsed arguments - file_path: {}, access_time: {}, modify_time: {}, create_time: {}, timezone: {}",
           file_path_arg, access_time_str, modify_time_str, create_time_str, _timezone);
 
-    // Sanitize the file path and remove any leading slashes
-    let sanitized_file_path = sanitize_input(file_path_arg)
-        .trim_start_matches('/')
-        .to_string();
+    let sanitized_file_path = sanitize_input(file_path_arg);
     info!("Sanitized file path: {}", sanitized_file_path);
 
-    let resolved_path = {
+    // Only the files the user may delete, or owns under a deleteown rule
+    let resolved = {
         let session = session.lock().await;
-        let base_path = session.base_path.clone();
-        let file_path = base_path.join(&sanitized_file_path);
-        file_path.canonicalize().unwrap_or_else(|_| file_path)
+        resolve_modifiable_path(&config, &session, &state, &sanitized_file_path)
+    };
+    let resolved_path = match resolved {
+        Ok(resolved_path) => resolved_path,
+        Err(response) => {
+            error!("SITE UTIME refused on {}", sanitized_file_path);
+            send_response(&writer, response).await?;
+            return Ok(());
+        }
     };
-
-    if !resolved_path.exists() {
-        error!("File not found: {}", resolved_path.display());
-        send_response(&writer, b"550 File not found.\r\n").await?;
-        return Ok(());
-    }
-
-    if !resolved_path.starts_with(&session.lock().await.base_path) {
-        error!("Path is outside of the allowed area: {:?}", resolved_path);
-        send_response(&writer, b"550 Path is outside of the allowed area.\r\n").await?;
-        return Ok(());
-    }
 
     info!("Updating file times for: {}", resolved_path.display());
 
@@ -1,50 +1,86 @@
 // Commande SITE WHO - Liste des utilisateurs connectés
 // Inspiré de glFTPd
 
-use crate::core_quota::manager::QuotaManager;
+use crate::constants::SITEOP;
+use crate::core_auth::acl::{self, AclUser};
+use crate::core_ftpcommand::site::helper::respond_with_success;
+use crate::state::ServerState;
 use crate::{session::Session, Config};
-use crate::core_ftpcommand::site::helper::{respond_with_error, respond_with_success};
 use log::info;
+use std::path::Path;
 use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 /// Gère la commande SITE WHO
-/// Affiche la liste des utilisateurs actuellement connectés
+/// Affiche la liste des connexions en cours, avec l'ident@ip et l'activité de chacune.
+/// Comme dans glFTPd, seuls les siteops voient l'ident@ip des autres sessions.
+/// Les sessions situées dans un chemin `hideinwho` sont masquées aux utilisateurs
+/// visés par la règle.
 
 pub async fn handle_site_who_command(
     writer: Arc<Mutex<TcpStream>>,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     _args: Vec<String>,
-    _quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Handling SITE WHO command");
 
-    let session = session.lock().await;
-    let username = session
-        .username
-        .clone()
-        .unwrap_or_else(|| "anonymous".to_string());
-
-    // Pour l'instant, nous retournons une réponse basique
-    // Cette commande sera étendue pour afficher les utilisateurs connectés
-    let mut writer = writer.lock().await;
+    let (own_id, siteop, sessions) = {
+        let session = session.lock().await;
+        let viewer = AclUser::from(&*session);
+        let sessions: Vec<_> = state
+            .sessions()
+            .list()
+            .into_iter()
+            .filter(|info| {
+                info.id == session.id
+                    || !acl::is_hidden_in_who(&config.acl, &viewer, Path::new(&info.current_dir))
+            })
+            .collect();
+        (session.id, session.has_flag(SITEOP), sessions)
+    };
 
     // En-tête de la réponse
-    writer.write_all(b"200-WHO command:\r\n").await?;
+    let mut response =
+        String::from("200- User             Ident@IP                       Activity\r\n");
 
-    // Afficher l'utilisateur actuel
-    writer
-        .write_all(format!("  User: {} (current session)\r\n", username).as_bytes())
-        .await?;
+    for info in &sessions {
+        let username = match (&info.username, info.logged_in) {
+            (Some(username), true) => username.clone(),
+            _ => "-".to_string(),
+        };
+        let ident_ip = if siteop || info.id == own_id {
+            format!(
+                "{}@{}",
+                info.ident.as_deref().unwrap_or("*"),
+                info.peer_addr.ip()
+            )
+        } else {
+            "*".to_string()
+        };
+        let activity = if !info.logged_in {
+            "Logging in".to_string()
+        } else if info.uploads > 0 {
+            format!("UL ({})", info.uploads)
+        } else if info.downloads > 0 {
+            format!("DL ({})", info.downloads)
+        } else {
+            "Idle".to_string()
+        };
+        let marker = if info.id == own_id { " *" } else { "" };
 
-    // Note sur les utilisateurs connectés (à implémenter)
-    writer
-        .write_all(b"  Note: Full user list will be implemented in future versions\r\n")
-        .await?;
+        response.push_str(&format!(
+            "200- {:<16} {:<30} {}{}\r\n",
+            username, ident_ip, activity, marker
+        ));
+    }
 
-    respond_with_success(&writer, b"200 WHO command successful.\r\n").await?;
+    response.push_str(&format!(
+        "200 {} connection(s), * = your session.\r\n",
+        sessions.len()
+    ));
 
-    Ok(())
+    respond_with_success(&writer, response.as_bytes()).await
 }
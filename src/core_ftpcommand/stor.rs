@@ -1,8 +1,13 @@
+use crate::core_auth::acl::{self, AclUser, PathPermission};
+use crate::core_network::throttle::Throttle;
+use crate::core_quota::dir_quota::DirUsage;
+use crate::core_quota::error::QuotaError;
 use crate::core_quota::manager::QuotaManager;
 use crate::helpers::pad_message;
 use crate::{
     helpers::{sanitize_input, send_response},
-    session::Session,
+    session::{LoginLimits, Session},
+    state::ServerState,
     Config,
 };
 use log::{error, info, warn};
@@ -13,9 +18,10 @@ use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
     sync::Mutex,
+    time::timeout,
 };
 
-use crate::constants::MESSAGE_LENGTH;
+use crate::constants::{EXEMPT, MESSAGE_LENGTH};
 
 /// Handles the STOR (Store File) FTP command.
 ///
@@ -30,6 +36,8 @@ use crate::constants::MESSAGE_LENGTH;
 /// * `session` - A shared, locked session containing the user's current state.
 /// * `arg` - The name of the file to be stored.
 /// * `data_stream` - The data connection for receiving the file data.
+/// * `quota_manager` - The quota manager, if quotas are enabled.
+/// * `state` - The shared server state, used to count running transfers.
 ///
 /// # Returns
 ///
@@ -41,6 +49,7 @@ pub async fn handle_stor_command(
     arg: String,
     data_stream: Option<Arc<Mutex<TcpStream>>>, // Change data_stream to Option
     quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
 ) -> io::Result<()> {
     if arg.trim().is_empty() {
         warn!("STOR command received with no arguments");
@@ -52,6 +61,10 @@ pub async fn handle_stor_command(
     info!("Received STOR command with argument: {}", sanitized_arg);
 
     // 0. Check quota before proceeding
+    let (allocated, allocation) = {
+        let mut session = session.lock().await;
+        (session.allocated.take(), session.allocation.take())
+    };
     if let Some(quota_mgr) = &quota_manager {
         let session = session.lock().await;
         let username = session
@@ -60,13 +73,16 @@ pub async fn handle_stor_command(
             .unwrap_or_else(|| "anonymous".to_string());
         let base_dir = session.base_path.clone();
 
-        // Estimate file size (we'll check again after transfer with actual size)
-        // For now, we use a reasonable estimate or check if quota allows any upload
-        match quota_mgr.check_upload(&username, base_dir, 0).await {
+        // The size announced with ALLO, if any; the quota is also enforced
+        // while receiving the file
+        match quota_mgr
+            .check_upload(&username, base_dir, allocated.unwrap_or(0))
+            .await
+        {
             Ok(_) => {}
             Err(e) => {
                 error!("Quota check failed for user {}: {}", username, e);
-                send_response(&writer, e.to_ftp_response().as_bytes()).await?;
+                send_response(&writer, format!("{}\r\n", e.to_ftp_response()).as_bytes()).await?;
                 return Ok(());
             }
         }
@@ -77,18 +93,104 @@ pub async fn handle_stor_command(
         let session = session.lock().await;
 
         // Ensure the file path is within the base path
-        let file_path = session.base_path.join(&sanitized_arg);
+        let file_path = session.real_path(&arg);
         if !file_path.starts_with(&session.base_path) {
             error!("Path is outside of the allowed area: {:?}", file_path);
             send_response(&writer, b"550 Path -is outside of the allowed area.\r\n").await?;
             return Ok(());
         }
+
+        // Overwriting a file takes the right to delete it
+        let site_path = session.site_path(&arg);
+        let user = AclUser::from(&*session);
+        let allowed = acl::is_allowed(&config.acl, PathPermission::Upload, &user, &site_path)
+            && (!file_path.exists() || {
+                let owner = state.owners().owner_of(&file_path);
+                acl::may_modify(
+                    &config.acl,
+                    PathPermission::Delete,
+                    &user,
+                    &site_path,
+                    owner.as_deref(),
+                )
+            });
+        if !allowed {
+            warn!("STOR refused by the upload rules: {:?}", file_path);
+            send_response(&writer, b"550 Permission denied.\r\n").await?;
+            return Ok(());
+        }
         file_path
     };
 
+    // Enforce the simultaneous uploads limit from the userfile
+    let (session_id, exempt, max_sim) = {
+        let session = session.lock().await;
+        (
+            session.id,
+            session.has_flag(EXEMPT),
+            session.login_limits.max_sim_uploads,
+        )
+    };
+    let _transfer = match state
+        .sessions()
+        .start_transfer(session_id, true, exempt, max_sim)
+    {
+        Some(guard) => guard,
+        None => {
+            warn!("Simultaneous uploads limit reached for session {}", session_id);
+            send_response(
+                &writer,
+                format!(
+                    "450 Too many simultaneous uploads (max {}).\r\n",
+                    LoginLimits::display(max_sim)
+                )
+                .as_bytes(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    // An overwritten file no longer counts against its uploader's quota
+    let existing_size = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() => Some(metadata.len()),
+        _ => None,
+    };
+    let overwritten = existing_size.and_then(|size| {
+        state
+            .owners()
+            .owner_of(&file_path)
+            .map(|owner| (owner, size))
+    });
+
+    // Directory quotas count the new file, an overwritten one makes room
+    let added = DirUsage {
+        bytes: allocated
+            .unwrap_or(0)
+            .saturating_sub(existing_size.unwrap_or(0)),
+        files: u64::from(existing_size.is_none()),
+    };
+    if let Err(e) = state.dir_quotas().check(&file_path, added) {
+        warn!("STOR of {:?} refused: {}", file_path, e);
+        send_response(&writer, format!("{}\r\n", e.to_ftp_response()).as_bytes()).await?;
+        return Ok(());
+    }
+
     // 2. Create File and Handle Errors
     let mut file = match File::create(&file_path).await {
-        Ok(f) => f,
+        Ok(f) => {
+            if let (Some(quota_mgr), Some((owner, size))) = (&quota_manager, &overwritten) {
+                if let Err(e) = quota_mgr.record_delete(owner, *size).await {
+                    error!("Failed to update quota for user {}: {}", owner, e);
+                }
+            }
+            if let Some(size) = existing_size {
+                state
+                    .dir_quotas()
+                    .record_remove(&file_path, DirUsage::file(size));
+            }
+            f
+        }
         Err(e) => {
             error!("Failed to create file: {:?}, error: {}", file_path, e);
             // More specific error handling based on the type of error
@@ -117,60 +219,220 @@ pub async fn handle_stor_command(
     .await?;
 
     // Lock the data stream
-    let data_stream = {
-        let mut session = session.lock().await;
-        session.data_stream.take().expect("Data stream is None")
+    let data_stream = match data_stream.or(session.lock().await.data_stream.take()) {
+        Some(stream) => stream,
+        None => {
+            error!("Data stream is None");
+            send_response(&writer, b"425 Can't open data connection.\r\n").await?;
+            return Ok(());
+        }
     };
 
+    // What the user may still upload, once the overwritten file was credited.
+    // The quota is reserved as the file comes in, so that parallel uploads
+    // cannot overshoot it together. ALLO may have reserved it already.
+    let (username, base_dir) = {
+        let session = session.lock().await;
+        let username = session
+            .username
+            .clone()
+            .unwrap_or_else(|| "anonymous".to_string());
+        (username, session.base_path.clone())
+    };
+    let mut reservation = allocation.or_else(|| {
+        quota_manager
+            .as_ref()
+            .map(|quota_mgr| quota_mgr.start_upload(&username))
+    });
+    let dir_allowance = state.dir_quotas().allowance(&file_path);
+    let mut received: u64 = 0;
+    let mut exceeded = false;
+    // Why the transfer ended before the end of the file, if it did
+    let mut aborted: Option<&[u8]> = None;
+    let mut write_error = None;
+
     let mut data_stream = data_stream.lock().await;
     let buffer_size = config.server.upload_buffer_size.unwrap_or(65536); // Use configured or default buffer size
     let mut buffer = vec![0; buffer_size];
+    let data_timeout = config.server.data_timeout_duration();
+    let throttle = {
+        let session = session.lock().await;
+        let site_path = file_path
+            .strip_prefix(&session.base_path)
+            .unwrap_or(&file_path);
+        Throttle::for_transfer(
+            &config,
+            &session,
+            site_path,
+            true,
+            state.bandwidth_bucket(true),
+        )
+    };
 
     loop {
-        // Read from file into buffer
-        let bytes_read = match data_stream.read(&mut buffer).await {
-            Ok(0) => break, // End of file
-            Ok(n) => n,
-            Err(e) => {
+        // Read from the data stream into buffer, aborting if the client stalls
+        let bytes_read = match timeout(data_timeout, data_stream.read(&mut buffer)).await {
+            Ok(Ok(0)) => break, // End of file
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => {
                 error!("Error reading from data stream: {}", e);
-                send_response(&writer, b"550 File read error.\r\n").await?;
-                return Ok(());
+                aborted = Some(b"550 File read error.\r\n");
+                break;
+            }
+            Err(_) => {
+                warn!(
+                    "Data transfer stalled for {} seconds, aborting: {:?}",
+                    data_timeout.as_secs(),
+                    file_path
+                );
+                aborted = Some(b"426 Data connection timed out; transfer aborted.\r\n");
+                break;
             }
         };
 
+        throttle.consume(bytes_read).await;
+
+        let wanted = received + bytes_read as u64;
+        if let (Some(quota_mgr), Some(held)) = (&quota_manager, reservation.as_mut()) {
+            if let Err(e) = quota_mgr
+                .reserve_upload(held, base_dir.clone(), wanted)
+                .await
+            {
+                error!("Failed to get the quota of user {}: {}", username, e);
+                reservation = None;
+            }
+        }
+        let allowance = match (
+            reservation.as_ref().and_then(|held| held.granted()),
+            dir_allowance,
+        ) {
+            (Some(allowance), Some(dir_allowance)) => Some(allowance.min(dir_allowance)),
+            (allowance, dir_allowance) => allowance.or(dir_allowance),
+        };
+
+        // Stop at the quota, so that a kept partial file still fits in it
+        let mut chunk = &buffer[..bytes_read];
+        if let Some(allowance) = allowance {
+            let left = allowance.saturating_sub(received);
+            if chunk.len() as u64 > left {
+                chunk = &chunk[..left as usize];
+                exceeded = true;
+            }
+        }
+
         // Write from buffer to the file
-        if let Err(e) = file.write_all(&buffer[..bytes_read]).await {
+        if let Err(e) = file.write_all(chunk).await {
             error!("Error writing to file: {}", e);
-            return Err(e);
+            write_error = Some(e);
+            break;
+        }
+        received += chunk.len() as u64;
+
+        if exceeded {
+            break;
         }
     }
+    if write_error.is_none() {
+        write_error = file.flush().await.err();
+    }
 
     // Shut down data stream when done
     if let Err(e) = data_stream.shutdown().await {
         error!("Error shutting down data stream: {}", e);
-        // Send error response to the client
-        send_response(&writer, b"426 Connection closed; transfer aborted.\r\n").await?;
-        return Ok(());
+        aborted = aborted.or(Some(b"426 Connection closed; transfer aborted.\r\n"));
     }
 
-    send_response(&writer, b"226 File transfer complete.\r\n").await?;
-    info!("File stored successfully: {:?}", file_path);
+    if let Some(response) = aborted {
+        send_response(&writer, response).await?;
+    } else if exceeded {
+        let response = match &quota_manager {
+            Some(quota_mgr) => quota_mgr
+                .check_upload(&username, base_dir, received + 1)
+                .await
+                .err(),
+            None => None,
+        }
+        .or_else(|| {
+            let received = DirUsage {
+                bytes: received + 1,
+                files: 0,
+            };
+            state.dir_quotas().check(&file_path, received).err()
+        })
+        .unwrap_or_else(|| QuotaError::QuotaExceeded(username.clone()))
+        .to_ftp_response();
+        warn!(
+            "Upload of {:?} by {} stopped by the quota after {} bytes",
+            file_path, username, received
+        );
+        send_response(&writer, format!("{}\r\n", response).as_bytes()).await?;
+    }
 
-    // Update quota after successful transfer
-    if let Some(quota_mgr) = &quota_manager {
-        let session = session.lock().await;
-        let username = session
-            .username
-            .clone()
-            .unwrap_or_else(|| "anonymous".to_string());
+    // A partial file is removed, or kept and counted as any other upload
+    let partial = exceeded || aborted.is_some() || write_error.is_some();
+    if partial && !config.keep_partial_uploads() {
+        drop(file);
+        if let Err(e) = tokio::fs::remove_file(&file_path).await {
+            error!("Failed to remove partial upload {:?}: {}", file_path, e);
+        }
+        return write_error.map_or(Ok(()), Err);
+    }
+
+    let uploader = session.lock().await.username.clone();
+    if let Some(uploader) = uploader {
+        state.owners().set_owner(&file_path, &uploader);
+    }
+
+    // Get the actual file size, the upload may be partial
+    let file_size = tokio::fs::metadata(&file_path)
+        .await
+        .map(|metadata| metadata.len())
+        .ok();
+
+    if let Some(file_size) = file_size {
+        state
+            .dir_quotas()
+            .record_add(&file_path, DirUsage::file(file_size));
+    }
+
+    let is_anonymous = session.lock().await.is_anonymous;
+
+    // Update quota after the transfer, partial or not
+    if let (Some(quota_mgr), Some(file_size)) = (&quota_manager, file_size) {
+        if let Err(e) = quota_mgr.record_upload(&username, file_size).await {
+            error!("Failed to update quota for user {}: {}", username, e);
+        }
+        drop(reservation.take());
+    }
+
+    // Uploads earn credits, only users with a userfile have some
+    if let Some(file_size) = file_size.filter(|_| !is_anonymous) {
+        let earned = match state.user_ratio(&username).await {
+            Some(ratio) => ratio.earned_credits(file_size),
+            None => 0,
+        };
+        if earned > 0 {
+            if let Some(credits) = state.add_credits(&username, earned as i64).await {
+                session.lock().await.credits = credits;
+            }
+        }
+    }
+
+    if let Some(e) = write_error {
+        return Err(e);
+    }
 
-        // Get the actual file size
-        if let Ok(metadata) = tokio::fs::metadata(&file_path).await {
-            let file_size = metadata.len();
-            if let Err(e) = quota_mgr.record_upload(&username, file_size).await {
-                error!("Failed to update quota for user {}: {}", username, e);
+    if !partial {
+        // Sent once the quota is updated, to warn about the soft quota
+        let mut response = String::new();
+        if let Some(quota_mgr) = &quota_manager {
+            if let Some(warning) = quota_mgr.soft_quota_warning(&username).await {
+                response.push_str(&format!("226-{}\r\n", warning));
             }
         }
+        response.push_str("226 File transfer complete.\r\n");
+        send_response(&writer, response.as_bytes()).await?;
+        info!("File stored successfully: {:?}", file_path);
     }
 
     Ok(())
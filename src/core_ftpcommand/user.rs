@@ -1,8 +1,11 @@
-use crate::core_auth::helper::load_passwd_file;
+use crate::core_auth::anonymous::is_anonymous_username;
+use crate::core_auth::mask::any_mask_matches;
+use crate::core_auth::AuthBackend;
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use colored::*;
-use log::{error, info};
+use log::{error, info, warn};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
@@ -15,8 +18,10 @@ use tokio::sync::Mutex;
 /// # Arguments
 ///
 /// * `writer` - A shared, locked TCP stream for writing responses to the client.
-/// * `_config` - A shared server configuration (not used in this command).
-/// * `_username` - The username provided by the client.
+/// * `config` - A shared server configuration.
+/// * `session` - The current session.
+/// * `username` - The username provided by the client.
+/// * `state` - The shared server state, holding the authentication backends.
 ///
 /// # Returns
 ///
@@ -26,23 +31,38 @@ pub async fn handle_user_command(
     config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     username: String,
+    state: Arc<ServerState>,
 ) -> Result<(), std::io::Error> {
     info!("Received USER command with username: {}", username);
 
-    let passwd_file_path = config.server.passwd_file.clone();
-    let passwd_map = load_passwd_file(&passwd_file_path).await;
-
-    {
-        let mut session = session.lock().await;
-        session.username = Some(username.clone());
+    if !start_login(&mut *session.lock().await, &username) {
+        warn!(
+            "USER {} refused, the session is already logged in",
+            username
+        );
+        let mut writer = writer.lock().await;
+        return writer
+            .write_all(b"503 You are already logged in.\r\n")
+            .await;
     }
 
-    let response: &[u8] = if username.to_lowercase() == "anonymous" {
-        info!("Anonymous login initiated for username: {}", username);
-        b"331 Anonymous login okay, send your complete email address as password.\r\n"
-    } else if passwd_map.contains_key(&username) {
-        info!("Username accepted: {}", username);
-        b"331 User name okay, need password.\r\n"
+    let response: &[u8] = if is_anonymous_username(&username) {
+        if config.anonymous.enabled() {
+            info!("Anonymous login initiated for username: {}", username);
+            b"331 Anonymous login okay, send your complete email address as password.\r\n"
+        } else {
+            session.lock().await.username = None;
+            b"530 Anonymous access is disabled.\r\n"
+        }
+    } else if user_exists(&state, &username).await {
+        if is_address_allowed(&config, &state, &session, &username).await {
+            info!("Username accepted: {}", username);
+            b"331 User name okay, need password.\r\n"
+        } else {
+            // Forget the user so that a following PASS is refused
+            session.lock().await.username = None;
+            b"530 Access denied from your address.\r\n"
+        }
     } else {
         info!("Username not found: {}", username);
         b"530 User not found.\r\n"
@@ -57,3 +77,92 @@ pub async fn handle_user_command(
     info!("Sent response to USER command, awaiting password.");
     Ok(())
 }
+
+/// Starts a login as `username`, unless the session is already logged in.
+///
+/// The session keeps acting as the account it logged in as: a second USER must
+/// not change whose password, quota or files the following commands use.
+fn start_login(session: &mut Session, username: &str) -> bool {
+    if session.is_authenticated {
+        return false;
+    }
+    session.username = Some(username.to_string());
+    true
+}
+
+/// Asks the authentication backends whether `username` exists.
+async fn user_exists(state: &ServerState, username: &str) -> bool {
+    match state.auth().lookup(username).await {
+        Ok(user) => user.is_some(),
+        Err(e) => {
+            error!("Could not look up user {}: {}", username, e);
+            false
+        }
+    }
+}
+
+/// Checks the client address against the `IP` masks of the user's userfile.
+///
+/// A user without any mask is refused, unless `allow_users_without_ip` is set
+/// in the `[auth]` section of the configuration.
+async fn is_address_allowed(
+    config: &Config,
+    state: &ServerState,
+    session: &Arc<Mutex<Session>>,
+    username: &str,
+) -> bool {
+    let (peer_addr, ident) = {
+        let session = session.lock().await;
+        (session.peer_addr, session.ident.clone())
+    };
+    let Some(peer_addr) = peer_addr else {
+        return false;
+    };
+
+    let masks = match state.users().get(username).await {
+        Ok(user) => user.map(|user| user.ips.clone()).unwrap_or_default(),
+        Err(e) => {
+            error!("Could not read the userfile of {}: {}", username, e);
+            Vec::new()
+        }
+    };
+
+    if masks.is_empty() {
+        if config.auth.allow_users_without_ip() {
+            return true;
+        }
+        warn!("User {} has no IP mask, refusing login", username);
+        return false;
+    }
+
+    if any_mask_matches(&masks, ident.as_deref(), peer_addr.ip()) {
+        true
+    } else {
+        warn!(
+            "User {} is not allowed to log in from {}@{}",
+            username,
+            ident.as_deref().unwrap_or("*"),
+            peer_addr.ip()
+        );
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_user_cannot_retarget_session() {
+        let mut session = Session::new(PathBuf::from("/"));
+        assert!(start_login(&mut session, "alice"));
+        assert!(start_login(&mut session, "bob"));
+        assert_eq!(session.username.as_deref(), Some("bob"));
+
+        // Once logged in, SITE PASSWD and the transfers keep acting as bob
+        session.is_authenticated = true;
+        assert!(!start_login(&mut session, "alice"));
+        assert_eq!(session.username.as_deref(), Some("bob"));
+    }
+}
@@ -0,0 +1,75 @@
+// Contrôle des commandes du système de fichiers par les permissions de groupe
+// Inspiré du système de groupes de glFTPd
+
+use crate::core_ftpcommand::ftpcommand::FtpCommand;
+use crate::core_groups::group::GroupPermission;
+use crate::core_groups::GroupManager;
+use crate::session::Session;
+
+/// Renvoie la permission de groupe demandée par une commande, `None` si la
+/// commande ne touche pas au système de fichiers.
+///
+/// Écraser un fichier existant avec STOR demande en plus la permission Write,
+/// vérifiée par `check_group_access`.
+pub fn required_permission(cmd: &FtpCommand, args: &[String]) -> Option<GroupPermission> {
+    match cmd {
+        FtpCommand::CWD | FtpCommand::CDUP | FtpCommand::SIZE | FtpCommand::MDTM => {
+            Some(GroupPermission::Read)
+        }
+        FtpCommand::LIST => Some(GroupPermission::List),
+        FtpCommand::RETR => Some(GroupPermission::Download),
+        FtpCommand::STOR => Some(GroupPermission::Upload),
+        FtpCommand::MKD => Some(GroupPermission::Mkdir),
+        FtpCommand::RMD | FtpCommand::DELE => Some(GroupPermission::Delete),
+        FtpCommand::RNFR | FtpCommand::RNTO => Some(GroupPermission::Rename),
+        FtpCommand::SITE
+            if args.first().is_some_and(|subcommand| {
+                ["CHMOD", "UTIME"]
+                    .iter()
+                    .any(|name| subcommand.eq_ignore_ascii_case(name))
+            }) =>
+        {
+            Some(GroupPermission::Write)
+        }
+        _ => None,
+    }
+}
+
+/// Vérifie qu'un des groupes de l'utilisateur accorde la permission demandée
+/// par la commande.
+///
+/// Les utilisateurs qui n'appartiennent à aucun groupe de `groups.json` ne sont
+/// pas concernés.
+///
+/// # Returns
+///
+/// La réponse à envoyer quand la commande est refusée.
+pub async fn check_group_access(
+    groups: &GroupManager,
+    session: &Session,
+    cmd: &FtpCommand,
+    args: &[String],
+) -> Result<(), &'static str> {
+    let Some(permission) = required_permission(cmd, args) else {
+        return Ok(());
+    };
+    let username = session.username.as_deref().unwrap_or_default();
+    let granted = |permission| {
+        groups
+            .user_has_permission(username, &session.groups, &permission)
+            .unwrap_or(true)
+    };
+
+    if !granted(permission) {
+        return Err("550 Permission denied by your group.\r\n");
+    }
+    if *cmd == FtpCommand::STOR
+        && tokio::fs::metadata(session.real_path(&args.join(" ")))
+            .await
+            .is_ok()
+        && !granted(GroupPermission::Write)
+    {
+        return Err("550 Permission denied by your group, the file exists.\r\n");
+    }
+    Ok(())
+}
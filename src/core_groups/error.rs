@@ -6,6 +6,18 @@ pub enum GroupError {
     #[error("Group not found: {0}")]
     GroupNotFound(String),
 
+    #[error("Group already exists: {0}")]
+    GroupExists(String),
+
+    #[error("Group still has members: {0}")]
+    GroupNotEmpty(String),
+
+    #[error("No free slot left in group: {0}")]
+    NoFreeSlot(String),
+
+    #[error("No free leech slot left in group: {0}")]
+    NoFreeLeechSlot(String),
+
     #[error("User not found: {0}")]
     UserNotFound(String),
 
@@ -26,6 +38,12 @@ impl GroupError {
     pub fn to_ftp_response(&self) -> String {
         match self {
             GroupError::GroupNotFound(_) => "550 Group not found.".to_string(),
+            GroupError::GroupExists(_) => "550 Group already exists.".to_string(),
+            GroupError::GroupNotEmpty(_) => "550 Group still has members.".to_string(),
+            GroupError::NoFreeSlot(_) => "550 No free slot left in the group.".to_string(),
+            GroupError::NoFreeLeechSlot(_) => {
+                "550 No free leech slot left in the group.".to_string()
+            }
             GroupError::UserNotFound(_) => "550 User not found.".to_string(),
             GroupError::PermissionDenied(_) => "550 Permission denied.".to_string(),
             _ => "451 Requested action aborted. Local error in processing.".to_string(),
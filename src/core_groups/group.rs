@@ -21,8 +21,23 @@ pub struct Group {
     /// Ratio du groupe (format "upload:download")
     pub ratio: String,
 
+    /// Espace total utilisable par les membres dont c'est le groupe principal
+    /// (en octets, 0 = illimité)
+    #[serde(default)]
+    pub total_quota: u64,
+
     /// Permissions du groupe
     pub permissions: GroupPermissions,
+
+    /// Nombre de membres que les GADMIN peuvent avoir dans le groupe
+    /// (None = illimité)
+    #[serde(default)]
+    pub slots: Option<u32>,
+
+    /// Nombre de membres leech (ratio 0) que les GADMIN peuvent avoir dans le
+    /// groupe (None = illimité)
+    #[serde(default)]
+    pub leech_slots: Option<u32>,
 }
 
 impl Group {
@@ -33,7 +48,10 @@ impl Group {
             users: HashSet::new(),
             quota: 0, // 0 = illimité
             ratio: "1:1".to_string(),
+            total_quota: 0,
             permissions: GroupPermissions::default(),
+            slots: None,
+            leech_slots: None,
         }
     }
 
@@ -61,6 +79,19 @@ impl Group {
     pub fn set_ratio(&mut self, ratio: &str) {
         self.ratio = ratio.to_string();
     }
+
+    /// Vérifie qu'il reste un slot pour un nouveau membre, le groupe en
+    /// comptant déjà `members`
+    pub fn has_free_slot(&self, members: usize) -> bool {
+        self.slots.is_none_or(|slots| members < slots as usize)
+    }
+
+    /// Vérifie qu'il reste un slot leech, le groupe comptant déjà `leeches`
+    /// membres leech
+    pub fn has_free_leech_slot(&self, leeches: usize) -> bool {
+        self.leech_slots
+            .is_none_or(|slots| leeches < slots as usize)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -143,6 +174,21 @@ impl GroupPermissions {
         }
     }
 
+    /// Accorde ou retire une permission
+    pub fn set_permission(&mut self, permission: &GroupPermission, enabled: bool) {
+        let flag = match permission {
+            GroupPermission::Read => &mut self.can_read,
+            GroupPermission::Write => &mut self.can_write,
+            GroupPermission::Mkdir => &mut self.can_mkdir,
+            GroupPermission::Delete => &mut self.can_delete,
+            GroupPermission::Rename => &mut self.can_rename,
+            GroupPermission::List => &mut self.can_list,
+            GroupPermission::Upload => &mut self.can_upload,
+            GroupPermission::Download => &mut self.can_download,
+        };
+        *flag = enabled;
+    }
+
     /// Définit toutes les permissions
     pub fn set_all_permissions(&mut self, enabled: bool) {
         self.can_read = enabled;
@@ -167,3 +213,37 @@ pub enum GroupPermission {
     Upload,
     Download,
 }
+
+impl GroupPermission {
+    pub const ALL: [GroupPermission; 8] = [
+        GroupPermission::Read,
+        GroupPermission::Write,
+        GroupPermission::Mkdir,
+        GroupPermission::Delete,
+        GroupPermission::Rename,
+        GroupPermission::List,
+        GroupPermission::Upload,
+        GroupPermission::Download,
+    ];
+
+    /// Nom de la permission, tel qu'utilisé par SITE GRPCHANGE et SITE GINFO
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupPermission::Read => "read",
+            GroupPermission::Write => "write",
+            GroupPermission::Mkdir => "mkdir",
+            GroupPermission::Delete => "delete",
+            GroupPermission::Rename => "rename",
+            GroupPermission::List => "list",
+            GroupPermission::Upload => "upload",
+            GroupPermission::Download => "download",
+        }
+    }
+
+    /// Retrouve une permission par son nom
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|permission| permission.name().eq_ignore_ascii_case(name))
+    }
+}
@@ -1,7 +1,10 @@
 // Gestionnaire de groupes pour rouilleftpd
 // Inspiré du système de groupes de glFTPd
 
-use crate::core_groups::{error::GroupError, group::Group};
+use crate::core_groups::{
+    error::GroupError,
+    group::{Group, GroupPermission},
+};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -86,15 +89,20 @@ impl GroupManager {
         groups.get(group_name).cloned()
     }
 
+    /// Liste les groupes, triés par nom
+    pub fn list_groups(&self) -> Vec<Group> {
+        let groups = self.groups.lock().unwrap();
+        let mut list: Vec<Group> = groups.values().cloned().collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
     /// Crée un nouveau groupe
     pub fn create_group(&self, name: &str, description: &str) -> Result<(), GroupError> {
         let mut groups = self.groups.lock().unwrap();
 
         if groups.contains_key(name) {
-            return Err(GroupError::InvalidGroupConfig(format!(
-                "Group {} already exists",
-                name
-            )));
+            return Err(GroupError::GroupExists(name.to_string()));
         }
 
         let group = Group::new(name, description);
@@ -119,6 +127,10 @@ impl GroupManager {
             )));
         }
 
+        if !groups[group_name].users.is_empty() {
+            return Err(GroupError::GroupNotEmpty(group_name.to_string()));
+        }
+
         groups.remove(group_name);
 
         Ok(())
@@ -206,6 +218,95 @@ impl GroupManager {
         Ok(())
     }
 
+    /// Définit le quota total d'un groupe (0 = illimité)
+    pub fn set_group_total_quota(&self, group_name: &str, quota: u64) -> Result<(), GroupError> {
+        let mut groups = self.groups.lock().unwrap();
+
+        let group = groups
+            .get_mut(group_name)
+            .ok_or_else(|| GroupError::GroupNotFound(group_name.to_string()))?;
+
+        group.total_quota = quota;
+
+        Ok(())
+    }
+
+    /// Définit les slots d'un groupe : le nombre de membres et de membres
+    /// leech que ses GADMIN peuvent avoir (None = illimité)
+    pub fn set_group_slots(
+        &self,
+        group_name: &str,
+        slots: Option<u32>,
+        leech_slots: Option<u32>,
+    ) -> Result<(), GroupError> {
+        let mut groups = self.groups.lock().unwrap();
+
+        let group = groups
+            .get_mut(group_name)
+            .ok_or_else(|| GroupError::GroupNotFound(group_name.to_string()))?;
+
+        group.slots = slots;
+        group.leech_slots = leech_slots;
+
+        Ok(())
+    }
+
+    /// Définit la description d'un groupe
+    pub fn set_group_description(
+        &self,
+        group_name: &str,
+        description: &str,
+    ) -> Result<(), GroupError> {
+        let mut groups = self.groups.lock().unwrap();
+
+        let group = groups
+            .get_mut(group_name)
+            .ok_or_else(|| GroupError::GroupNotFound(group_name.to_string()))?;
+
+        group.description = description.to_string();
+
+        Ok(())
+    }
+
+    /// Accorde ou retire une permission à un groupe
+    pub fn set_group_permission(
+        &self,
+        group_name: &str,
+        permission: &GroupPermission,
+        enabled: bool,
+    ) -> Result<(), GroupError> {
+        let mut groups = self.groups.lock().unwrap();
+
+        let group = groups
+            .get_mut(group_name)
+            .ok_or_else(|| GroupError::GroupNotFound(group_name.to_string()))?;
+
+        group.permissions.set_permission(permission, enabled);
+
+        Ok(())
+    }
+
+    /// Vérifie si l'un des groupes d'un utilisateur accorde une permission.
+    /// Les groupes sont ceux dont il est membre dans groups.json et ceux de
+    /// son userfile (`user_groups`).
+    /// Renvoie `None` si l'utilisateur n'appartient à aucun groupe connu.
+    pub fn user_has_permission(
+        &self,
+        username: &str,
+        user_groups: &[String],
+        permission: &GroupPermission,
+    ) -> Option<bool> {
+        let groups = self.groups.lock().unwrap();
+
+        let mut member_of = groups
+            .values()
+            .filter(|group| group.has_user(username) || user_groups.contains(&group.name))
+            .peekable();
+        member_of.peek()?;
+
+        Some(member_of.any(|group| group.permissions.has_permission(permission)))
+    }
+
     /// Vérifie les permissions d'un utilisateur
     pub fn check_permission(
         &self,
@@ -226,3 +327,64 @@ impl GroupManager {
         Ok(group.permissions.has_permission(permission))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_has_permission() {
+        let manager = GroupManager::new(PathBuf::from("/nonexistent/groups.json"));
+        manager.load().unwrap();
+        manager.add_user_to_group("alice", "users").unwrap();
+
+        let upload = GroupPermission::Upload;
+        let delete = GroupPermission::Delete;
+        assert_eq!(manager.user_has_permission("alice", &[], &delete), Some(true));
+        assert_eq!(
+            manager.user_has_permission("bob", &["guests".to_string()], &delete),
+            Some(false)
+        );
+        assert_eq!(
+            manager.user_has_permission("bob", &["guests".to_string()], &upload),
+            Some(true)
+        );
+        assert_eq!(
+            manager.user_has_permission("carol", &["unknown".to_string()], &delete),
+            None
+        );
+    }
+
+    #[test]
+    fn test_group_lifecycle() {
+        let manager = GroupManager::new(PathBuf::from("/nonexistent/groups.json"));
+        manager.load().unwrap();
+
+        manager.create_group("staff", "Staff").unwrap();
+        assert!(matches!(
+            manager.create_group("staff", ""),
+            Err(GroupError::GroupExists(_))
+        ));
+        manager
+            .set_group_permission("staff", &GroupPermission::Delete, false)
+            .unwrap();
+        let staff = manager.get_group("staff").unwrap();
+        assert!(!staff.permissions.has_permission(&GroupPermission::Delete));
+        assert!(staff.has_free_slot(100));
+
+        manager.set_group_slots("staff", Some(2), Some(0)).unwrap();
+        let staff = manager.get_group("staff").unwrap();
+        assert!(staff.has_free_slot(1));
+        assert!(!staff.has_free_slot(2));
+        assert!(!staff.has_free_leech_slot(0));
+
+        manager.add_user_to_group("alice", "staff").unwrap();
+        assert!(matches!(
+            manager.delete_group("staff"),
+            Err(GroupError::GroupNotEmpty(_))
+        ));
+        manager.remove_user_from_group("alice", "staff").unwrap();
+        manager.delete_group("staff").unwrap();
+        assert!(manager.get_group("staff").is_none());
+    }
+}
@@ -1,6 +1,7 @@
 // Module de gestion des groupes pour rouilleftpd
 // Inspiré du système de groupes de glFTPd
 
+pub mod access;
 pub mod error;
 pub mod group;
 pub mod group_manager;
@@ -0,0 +1,156 @@
+use log::debug;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpSocket;
+use tokio::time::timeout;
+
+/// Longest reply accepted from an identd, RFC 1413 allows 1000 characters.
+const MAX_IDENT_REPLY: u64 = 1000;
+
+/// Asks the identd of a client who owns its control connection (RFC 1413).
+///
+/// # Arguments
+///
+/// * `local` - The server end of the control connection.
+/// * `peer` - The client end of the control connection.
+/// * `ident_port` - The port of the identd, 113 outside of tests.
+/// * `limit` - Time allowed for the whole exchange.
+///
+/// # Returns
+///
+/// The user id reported by the identd, or `None` on error, timeout or an
+/// ERROR reply.
+pub async fn lookup_ident(
+    local: SocketAddr,
+    peer: SocketAddr,
+    ident_port: u16,
+    limit: Duration,
+) -> Option<String> {
+    match timeout(limit, query(local, peer, ident_port)).await {
+        Ok(Ok(reply)) => parse_ident_reply(&reply, peer.port(), local.port()),
+        Ok(Err(e)) => {
+            debug!("Ident lookup for {} failed: {}", peer, e);
+            None
+        }
+        Err(_) => {
+            debug!("Ident lookup for {} timed out", peer);
+            None
+        }
+    }
+}
+
+async fn query(local: SocketAddr, peer: SocketAddr, ident_port: u16) -> std::io::Result<String> {
+    // Connect from the address the client connected to, as identd expects
+    let socket = if peer.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.bind(SocketAddr::new(local.ip(), 0))?;
+    let mut stream = socket
+        .connect(SocketAddr::new(peer.ip(), ident_port))
+        .await?;
+
+    stream
+        .write_all(format!("{} , {}\r\n", peer.port(), local.port()).as_bytes())
+        .await?;
+
+    let mut reply = String::new();
+    BufReader::new(stream.take(MAX_IDENT_REPLY))
+        .read_line(&mut reply)
+        .await?;
+    Ok(reply)
+}
+
+/// Parses `<port> , <port> : USERID : <os>[,<charset>] : <user id>`.
+fn parse_ident_reply(reply: &str, peer_port: u16, local_port: u16) -> Option<String> {
+    let mut fields = reply.trim_end_matches(['\r', '\n']).splitn(4, ':');
+
+    let ports: Vec<u16> = fields
+        .next()?
+        .split(',')
+        .filter_map(|port| port.trim().parse().ok())
+        .collect();
+    if ports != [peer_port, local_port] {
+        return None;
+    }
+    if fields.next()?.trim() != "USERID" {
+        return None;
+    }
+    let _os = fields.next()?;
+
+    let user_id = fields.next()?.trim();
+    if user_id.is_empty() || user_id.contains('@') {
+        return None;
+    }
+    Some(user_id.chars().take(64).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Answers a single ident query with `reply`, echoing the requested ports.
+    async fn fake_identd(reply: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut query = String::new();
+            stream.read_line(&mut query).await.unwrap();
+            let answer = format!("{} : {}\r\n", query.trim(), reply);
+            stream.get_mut().write_all(answer.as_bytes()).await.unwrap();
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_lookup_ident() {
+        let local: SocketAddr = "127.0.0.1:21".parse().unwrap();
+        let peer: SocketAddr = "127.0.0.1:50123".parse().unwrap();
+        let limit = Duration::from_secs(2);
+
+        let port = fake_identd("USERID : UNIX : bob").await;
+        assert_eq!(
+            lookup_ident(local, peer, port, limit).await.as_deref(),
+            Some("bob")
+        );
+
+        let port = fake_identd("ERROR : NO-USER").await;
+        assert_eq!(lookup_ident(local, peer, port, limit).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_ident_timeout() {
+        // An identd that never answers must not hold the connection longer than the limit
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let local: SocketAddr = "127.0.0.1:21".parse().unwrap();
+        let peer: SocketAddr = "127.0.0.1:50123".parse().unwrap();
+
+        let started = std::time::Instant::now();
+        let ident = lookup_ident(local, peer, port, Duration::from_millis(200)).await;
+        assert_eq!(ident, None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        drop(listener);
+    }
+
+    #[test]
+    fn test_parse_ident_reply() {
+        assert_eq!(
+            parse_ident_reply("6193, 23 : USERID : UNIX : stjohns\r\n", 6193, 23).as_deref(),
+            Some("stjohns")
+        );
+        assert_eq!(
+            parse_ident_reply("6195, 23 : USERID : UNIX : stjohns\r\n", 6193, 23),
+            None
+        );
+        assert_eq!(
+            parse_ident_reply("6193, 23 : ERROR : NO-USER\r\n", 6193, 23),
+            None
+        );
+    }
+}
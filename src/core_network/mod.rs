@@ -1,4 +1,6 @@
 pub mod handlers;
+pub mod ident;
 pub mod network;
 pub mod pasv;
 pub mod port;
+pub mod throttle;
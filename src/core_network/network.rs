@@ -1,58 +1,153 @@
+use crate::constants::IDENT_PORT;
+use crate::core_auth::anonymous::check_anonymous_access;
 use crate::core_ftpcommand::ftpcommand::FtpCommand;
 use crate::core_ftpcommand::handlers::initialize_command_handlers;
+use crate::core_groups::access::check_group_access;
 use crate::core_log::logger::log_message;
-use crate::core_quota::manager::QuotaManager;
 use crate::helpers::load_banner;
 use crate::ipc::update_ipc;
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use crate::Ipc;
 use anyhow::Result;
-use log::{error, info};
+use log::{error, info, warn};
 
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
 
 use crate::helpers::send_response;
 
+use crate::core_network::ident::lookup_ident;
 use crate::core_network::pasv::accept_pasv_connection;
 use crate::core_network::pasv::setup_pasv_listener;
 
+/// Accepts control connections until a shutdown is requested.
+///
+/// Once `shutdown` flips to `true` the listener is closed and idle sessions are told
+/// to go away. Sessions still transferring get the configured grace period to finish
+/// before their tasks are aborted.
 pub async fn start_server(
     port: u16,
-    config: Arc<Config>,
+    state: Arc<ServerState>,
     ipc: Arc<Ipc>,
-    quota_manager: Option<Arc<QuotaManager>>,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     info!("Server listening on port {}", port);
 
-    let base_path = PathBuf::from(&config.server.chroot_dir)
-        .join(config.server.min_homedir.trim_start_matches('/'))
-        .canonicalize()
-        .unwrap();
+    let mut connections = JoinSet::new();
 
     loop {
-        let (socket, addr) = listener.accept().await?;
+        let (socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = shutdown_requested(&mut shutdown) => break,
+        };
         info!("New connection from {:?}", addr);
 
-        let config = Arc::clone(&config);
-        let session = Arc::new(Mutex::new(Session::new(base_path.clone())));
+        // Each connection keeps the configuration it was accepted with
+        let config = state.config();
+
+        if let Some(ban) = state.bans().ban_of(addr.ip()) {
+            info!("Refusing banned IP {}: {}", addr.ip(), ban.reason);
+            connections.spawn(async move {
+                let mut socket = socket;
+                socket
+                    .write_all(b"421 Your IP is temporarily banned.\r\n")
+                    .await
+                    .ok();
+            });
+            continue;
+        }
+
+        let Some(session_id) = state
+            .sessions()
+            .register(addr, config.limits.max_connections_per_ip)
+        else {
+            warn!("Too many connections from {}, refusing", addr.ip());
+            let message = format!("421 {}\r\n", config.limits.ip_limit_message());
+            connections.spawn(async move {
+                let mut socket = socket;
+                socket.write_all(message.as_bytes()).await.ok();
+            });
+            continue;
+        };
+
+        let base_path = match PathBuf::from(&config.server.chroot_dir)
+            .join(config.server.min_homedir.trim_start_matches('/'))
+            .canonicalize()
+        {
+            Ok(base_path) => base_path,
+            Err(e) => {
+                error!("Invalid home directory, dropping connection: {}", e);
+                state.sessions().unregister(session_id);
+                continue;
+            }
+        };
+        let mut session = Session::new(base_path);
+        session.id = session_id;
+        session.peer_addr = Some(addr);
+        let session = Arc::new(Mutex::new(session));
         let ipc_clone = ipc.clone();
-        let quota_manager_clone = quota_manager.clone();
+        let state_clone = Arc::clone(&state);
+        let shutdown_clone = shutdown.clone();
 
-        tokio::spawn(async move {
-            if let Err(e) =
-                handle_connection(socket, config, session, ipc_clone, quota_manager_clone).await
+        connections.spawn(async move {
+            if let Err(e) = handle_connection(
+                socket,
+                config,
+                session,
+                ipc_clone,
+                Arc::clone(&state_clone),
+                shutdown_clone,
+            )
+            .await
             {
                 error!("Connection error: {:?}", e);
             }
+            state_clone.sessions().unregister(session_id);
             info!("Connection closed for {:?}", addr);
         });
     }
+
+    // Stop accepting new connections, then let running transfers drain.
+    drop(listener);
+    let grace_period = state.config().server.shutdown_grace_period_duration();
+    info!(
+        "Shutting down, waiting up to {} seconds for {} connection(s) to finish",
+        grace_period.as_secs(),
+        connections.len()
+    );
+
+    let drained = timeout(grace_period, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+
+    if drained.is_err() {
+        warn!(
+            "Grace period expired, aborting {} connection(s)",
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+
+    Ok(())
+}
+
+/// Resolves once a shutdown has been requested.
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        // The sender is gone without asking for a shutdown: never resolve.
+        std::future::pending::<()>().await;
+    }
 }
 
 pub async fn handle_connection(
@@ -60,10 +155,26 @@ pub async fn handle_connection(
     config: Arc<Config>,
     session: Arc<Mutex<Session>>,
     ipc: Arc<Ipc>,
-    quota_manager: Option<Arc<QuotaManager>>,
+    state: Arc<ServerState>,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let banner_path = "ftp-data/text/banner.txt";
-    let banner_text = load_banner(banner_path)?;
+    let banner_text = load_banner(config.server.banner_path())?;
+    let quota_manager = state.quota_manager();
+
+    if config.auth.ident_lookup() {
+        if let (Ok(local), Ok(peer)) = (socket.local_addr(), socket.peer_addr()) {
+            let ident =
+                lookup_ident(local, peer, IDENT_PORT, config.auth.ident_timeout()).await;
+            info!(
+                "Connection from {}@{}",
+                ident.as_deref().unwrap_or("*"),
+                peer.ip()
+            );
+            let mut session = session.lock().await;
+            state.sessions().set_ident(session.id, ident.clone());
+            session.ident = ident;
+        }
+    }
 
     let socket = Arc::new(Mutex::new(socket));
     {
@@ -80,10 +191,44 @@ pub async fn handle_connection(
 
     loop {
         buffer.clear();
+        let idle_timeout = {
+            let session = session.lock().await;
+            session.effective_idle_timeout(&config)
+        };
         {
             let mut locked_socket = socket.lock().await;
             let mut reader = BufReader::new(&mut *locked_socket);
-            let n = reader.read_line(&mut buffer).await?;
+            let read = tokio::select! {
+                read = read_command(&mut reader, &mut buffer, idle_timeout) => Some(read),
+                _ = shutdown_requested(&mut shutdown) => None,
+            };
+
+            let n = match read {
+                Some(Some(result)) => result?,
+                Some(None) => {
+                    let idle_timeout = idle_timeout.unwrap_or_default();
+                    info!("Idle timeout reached, closing control connection.");
+                    locked_socket
+                        .write_all(
+                            format!(
+                                "421 Idle timeout ({} seconds) reached, closing control connection.\r\n",
+                                idle_timeout.as_secs()
+                            )
+                            .as_bytes(),
+                        )
+                        .await
+                        .ok();
+                    break;
+                }
+                None => {
+                    info!("Server shutting down, closing control connection.");
+                    locked_socket
+                        .write_all(b"421 Server is shutting down, closing control connection.\r\n")
+                        .await
+                        .ok();
+                    break;
+                }
+            };
             drop(locked_socket);
 
             if n == 0 {
@@ -91,9 +236,10 @@ pub async fn handle_connection(
                 break;
             }
         }
+        session.lock().await.touch();
 
         let command = buffer.trim();
-        info!("Received command: {}", command);
+        info!("Received command: {}", loggable_command(command));
 
         let parts: Vec<String> = command.split_whitespace().map(String::from).collect();
 
@@ -141,6 +287,11 @@ pub async fn handle_connection(
         };
 
         let args = parts[1..].to_vec();
+        // The argument as sent, so that passwords and file names keep their spaces
+        let arg = command
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, arg)| arg.trim_start())
+            .to_string();
 
         // Update IPC with the command and username
         let username = {
@@ -149,6 +300,50 @@ pub async fn handle_connection(
         };
         update_ipc(Arc::clone(&ipc), &username, &cmd_str, 0.0, 0.0);
 
+        if !session.lock().await.is_authenticated && !allowed_before_login(&cmd) {
+            send_response(&socket, b"530 Please login with USER and PASS.\r\n").await?;
+            continue;
+        }
+
+        if session.lock().await.must_change_password
+            && !allowed_before_password_change(&cmd, &args)
+        {
+            send_response(
+                &socket,
+                b"530 Your password must be changed first, use SITE PASSWD <new password>.\r\n",
+            )
+            .await?;
+            continue;
+        }
+
+        let anonymous_refusal = {
+            let session = session.lock().await;
+            if session.is_anonymous {
+                check_anonymous_access(&config.anonymous, &session, &cmd, &args)
+                    .await
+                    .err()
+            } else {
+                None
+            }
+        };
+        if let Some(response) = anonymous_refusal {
+            warn!("Refused {} to anonymous user {}", cmd_str, username);
+            send_response(&socket, response.as_bytes()).await?;
+            continue;
+        }
+
+        let group_refusal = {
+            let session = session.lock().await;
+            check_group_access(state.groups(), &session, &cmd, &args)
+                .await
+                .err()
+        };
+        if let Some(response) = group_refusal {
+            warn!("Refused {} to {} by group permissions", cmd_str, username);
+            send_response(&socket, response.as_bytes()).await?;
+            continue;
+        }
+
         if let Some(handler) = handlers.get(&cmd) {
             if cmd == FtpCommand::PASV {
                 let (listener, pasv_response) =
@@ -159,8 +354,9 @@ pub async fn handle_connection(
                 // Accept the connection asynchronously
                 let socket_clone = Arc::clone(&socket);
                 let session_clone = Arc::clone(&session);
+                let accept_timeout = config.server.pasv_accept_timeout_duration();
                 tokio::spawn(async move {
-                    match accept_pasv_connection(listener).await {
+                    match accept_pasv_connection(listener, accept_timeout).await {
                         Ok(stream) => {
                             let mut session = session_clone.lock().await;
                             session.data_stream = Some(Arc::new(Mutex::new(stream)));
@@ -182,9 +378,10 @@ pub async fn handle_connection(
                     Arc::clone(&socket),
                     Arc::clone(&config),
                     Arc::clone(&session),
-                    args.join(" "),
+                    arg.clone(),
                     data_stream.clone(),
                     quota_manager.clone(),
+                    Arc::clone(&state),
                 )
                 .await
             } else {
@@ -193,9 +390,10 @@ pub async fn handle_connection(
                     Arc::clone(&socket),
                     Arc::clone(&config),
                     Arc::clone(&session),
-                    args.join(" "),
+                    arg.clone(),
                     None,
                     quota_manager.clone(),
+                    Arc::clone(&state),
                 )
                 .await
             };
@@ -205,7 +403,115 @@ pub async fn handle_connection(
                 log_message(&format!("Error handling command {}: {:?}", cmd_str, e));
                 data_stream = None; // Reset data_stream after use
             }
+
+            if session.lock().await.close_requested {
+                info!("Closing control connection on request of the {} handler", cmd_str);
+                break;
+            }
         }
     }
     Ok(())
 }
+
+/// Reads the next command line, giving up after `idle_timeout`.
+///
+/// Returns `None` when the timeout elapsed before a full line was received.
+async fn read_command(
+    reader: &mut BufReader<&mut TcpStream>,
+    buffer: &mut String,
+    idle_timeout: Option<Duration>,
+) -> Option<std::io::Result<usize>> {
+    match idle_timeout {
+        Some(idle_timeout) => timeout(idle_timeout, reader.read_line(buffer)).await.ok(),
+        None => Some(reader.read_line(buffer).await),
+    }
+}
+
+/// Commands a client may send before logging in.
+fn allowed_before_login(cmd: &FtpCommand) -> bool {
+    matches!(
+        cmd,
+        FtpCommand::USER
+            | FtpCommand::PASS
+            | FtpCommand::QUIT
+            | FtpCommand::NOOP
+            | FtpCommand::SYST
+            | FtpCommand::FEAT
+    )
+}
+
+/// Commands a user may still send while a password change is pending.
+fn allowed_before_password_change(cmd: &FtpCommand, args: &[String]) -> bool {
+    match cmd {
+        FtpCommand::USER
+        | FtpCommand::PASS
+        | FtpCommand::QUIT
+        | FtpCommand::NOOP
+        | FtpCommand::SYST
+        | FtpCommand::FEAT => true,
+        FtpCommand::SITE => args
+            .first()
+            .is_some_and(|subcommand| subcommand.eq_ignore_ascii_case("PASSWD")),
+        _ => false,
+    }
+}
+
+/// Hides the passwords of SITE PASSWD, SITE CHPASS, SITE ADDUSER and SITE GADDUSER
+/// from the logs.
+fn loggable_command(command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        [site, sub, ..]
+            if site.eq_ignore_ascii_case("SITE") && sub.eq_ignore_ascii_case("PASSWD") =>
+        {
+            format!("{} {} ********", site, sub)
+        }
+        [site, sub, user, ..]
+            if site.eq_ignore_ascii_case("SITE") && sub.eq_ignore_ascii_case("CHPASS") =>
+        {
+            format!("{} {} {} ********", site, sub, user)
+        }
+        [site, sub, user, _, ips @ ..]
+            if site.eq_ignore_ascii_case("SITE") && sub.eq_ignore_ascii_case("ADDUSER") =>
+        {
+            format!("{} {} {} ******** {}", site, sub, user, ips.join(" "))
+                .trim_end()
+                .to_string()
+        }
+        [site, sub, group, user, _, ips @ ..]
+            if site.eq_ignore_ascii_case("SITE") && sub.eq_ignore_ascii_case("GADDUSER") =>
+        {
+            format!("{} {} {} {} ******** {}", site, sub, group, user, ips.join(" "))
+                .trim_end()
+                .to_string()
+        }
+        _ => command.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loggable_command_hides_passwords() {
+        assert_eq!(loggable_command("SITE PASSWD s3cret"), "SITE PASSWD ********");
+        assert_eq!(
+            loggable_command("site chpass bob s3cret"),
+            "site chpass bob ********"
+        );
+        assert_eq!(
+            loggable_command("SITE ADDUSER bob s3cret *@127.0.0.1"),
+            "SITE ADDUSER bob ******** *@127.0.0.1"
+        );
+        assert_eq!(
+            loggable_command("SITE GADDUSER staff bob s3cret"),
+            "SITE GADDUSER staff bob ********"
+        );
+        assert_eq!(
+            loggable_command("SITE GADDUSER staff bob s3cret *@10.0.0.1 *@10.0.0.2"),
+            "SITE GADDUSER staff bob ******** *@10.0.0.1 *@10.0.0.2"
+        );
+        assert_eq!(loggable_command("SITE WHO"), "SITE WHO");
+    }
+}
@@ -3,6 +3,7 @@ use anyhow::Result;
 use log::{debug, error, info};
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
@@ -33,10 +34,11 @@ pub async fn handle_pasv_command(
     // Clone writer and session to move into the spawned task
     let writer_clone = Arc::clone(&writer);
     let session_clone = Arc::clone(&session);
+    let accept_timeout = config.server.pasv_accept_timeout_duration();
 
     // Accept the incoming connection in a separate task
     tokio::spawn(async move {
-        match accept_pasv_connection(listener).await {
+        match accept_pasv_connection(listener, accept_timeout).await {
             Ok(data_stream) => {
                 debug!("Data connection accepted for PASV mode.");
                 let mut session = session_clone.lock().await;
@@ -87,8 +89,19 @@ pub async fn setup_pasv_listener(pasv_ip: IpAddr) -> Result<(TcpListener, String
 }
 
 /// Accepts the incoming connection on the passive listener.
-pub async fn accept_pasv_connection(listener: TcpListener) -> Result<TcpStream, std::io::Error> {
-    let (data_stream, addr) = listener.accept().await?;
+/// Fails with `TimedOut` if the client does not connect within `accept_timeout`.
+pub async fn accept_pasv_connection(
+    listener: TcpListener,
+    accept_timeout: Duration,
+) -> Result<TcpStream, std::io::Error> {
+    let (data_stream, addr) = tokio::time::timeout(accept_timeout, listener.accept())
+        .await
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "timed out waiting for the PASV data connection",
+            )
+        })??;
     debug!("Accepted data connection from: {}", addr);
     Ok(data_stream)
 }
@@ -0,0 +1,221 @@
+use crate::session::Session;
+use crate::Config;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// A token bucket limiting a flow of bytes to a given rate.
+///
+/// The bucket holds at most one second worth of tokens. Taking more tokens than
+/// available puts the bucket in debt, and the caller sleeps until the debt is
+/// paid back, so a bucket can be shared by several transfers.
+pub struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    rate: Option<u64>, // Bytes per second, None = unlimited
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: Option<u64>) -> Self {
+        Self {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate.unwrap_or(0) as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Changes the rate of the bucket, `None` removing the limit.
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = rate;
+        state.tokens = state.tokens.min(rate.unwrap_or(0) as f64);
+        state.last_refill = Instant::now();
+    }
+
+    /// Takes `bytes` tokens and returns how long the caller must wait before
+    /// sending them.
+    fn reserve(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let Some(rate) = state.rate else {
+            return Duration::ZERO;
+        };
+        let rate = rate as f64;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * rate).min(rate);
+        state.last_refill = now;
+
+        state.tokens -= bytes as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+
+    /// Waits until `bytes` may be transferred.
+    pub async fn consume(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// The rate limits applying to a single transfer.
+///
+/// The user, group and section limits are per transfer and folded into one
+/// bucket with the lowest rate; the server-wide bucket is shared by all sessions.
+pub struct Throttle {
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl Throttle {
+    /// Builds the throttle for a transfer of `path` (relative to the site root).
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the session.
+    /// * `session` - The session starting the transfer.
+    /// * `path` - The transferred file, relative to the site root.
+    /// * `upload` - `true` for STOR, `false` for RETR.
+    /// * `global` - The server-wide bucket for this direction.
+    pub fn for_transfer(
+        config: &Config,
+        session: &Session,
+        path: &Path,
+        upload: bool,
+        global: Arc<TokenBucket>,
+    ) -> Self {
+        let mut limits = Vec::new();
+
+        // Per-user limit, from the GENERAL line of the userfile
+        limits.push(if upload {
+            session.max_upload_speed
+        } else {
+            session.max_download_speed
+        });
+
+        if let Some(throttle) = &config.throttle {
+            for group in &session.groups {
+                if let Some(limit) = throttle.groups.get(group) {
+                    limits.push(limit.for_direction(upload));
+                }
+            }
+
+            let path = Path::new("/").join(path);
+            for section in &throttle.sections {
+                if path.starts_with(&section.path) {
+                    limits.push(section.limit.for_direction(upload));
+                }
+            }
+        }
+
+        let mut buckets = vec![global];
+        if let Some(rate) = limits.into_iter().flatten().min() {
+            buckets.push(Arc::new(TokenBucket::new(Some(rate * 1024))));
+        }
+
+        Self { buckets }
+    }
+
+    /// Waits until `bytes` may be transferred under every limit.
+    pub async fn consume(&self, bytes: usize) {
+        for bucket in &self.buckets {
+            bucket.consume(bytes).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn assert_wait(wait: Duration, secs: f64) {
+        assert!(
+            (wait.as_secs_f64() - secs).abs() < 0.05,
+            "waited {:?} instead of {}s",
+            wait,
+            secs
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_rate() {
+        // A second worth of bytes goes at once, the rest at the rate
+        let bucket = TokenBucket::new(Some(1000));
+        assert_eq!(bucket.reserve(1000), Duration::ZERO);
+        assert_wait(bucket.reserve(500), 0.5);
+        assert_wait(bucket.reserve(500), 1.0);
+
+        // Without a rate nothing waits, until one is set
+        let bucket = TokenBucket::new(None);
+        assert_eq!(bucket.reserve(1_000_000), Duration::ZERO);
+        bucket.set_rate(Some(100));
+        assert_wait(bucket.reserve(50), 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_consume() {
+        let bucket = TokenBucket::new(Some(10_000));
+        let started = Instant::now();
+        bucket.consume(10_000).await;
+        bucket.consume(2_000).await;
+        let elapsed = started.elapsed().as_secs_f64();
+        assert!((0.15..0.5).contains(&elapsed), "took {}s", elapsed);
+    }
+
+    #[test]
+    fn test_throttle_shares_the_global_bucket() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            listen_port = 2121
+            pasv_address = "127.0.0.1"
+            ipc_key = "0x0000BEEF"
+            chroot_dir = "/tmp"
+            min_homedir = "site"
+            passwd_file = "/tmp/passwd"
+
+            [throttle.groups.leechers]
+            max_download_speed = 500
+
+            [[throttle.sections]]
+            path = "/mp3"
+            max_download_speed = 2048
+            "#,
+        )
+        .unwrap();
+        let global = Arc::new(TokenBucket::new(Some(1000)));
+        let mut session = Session::new(PathBuf::from("/"));
+        session.groups = vec!["leechers".to_string()];
+        session.max_download_speed = Some(1000);
+
+        // The lowest of the user, group and section limits, in KB/s
+        let path = Path::new("mp3/song.mp3");
+        let first = Throttle::for_transfer(&config, &session, path, false, global.clone());
+        assert_eq!(first.buckets.len(), 2);
+        assert_eq!(
+            first.buckets[1].state.lock().unwrap().rate,
+            Some(500 * 1024)
+        );
+        let upload = Throttle::for_transfer(&config, &session, path, true, global.clone());
+        assert_eq!(upload.buckets.len(), 1);
+
+        // Parallel transfers draw from the same server-wide bucket
+        let second = Throttle::for_transfer(&config, &session, path, false, global.clone());
+        assert!(Arc::ptr_eq(&first.buckets[0], &global));
+        assert!(Arc::ptr_eq(&second.buckets[0], &global));
+        assert_eq!(first.buckets[0].reserve(1000), Duration::ZERO);
+        assert_wait(second.buckets[0].reserve(1000), 1.0);
+    }
+}
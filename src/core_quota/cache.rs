@@ -185,6 +185,7 @@ impl QuotaCache {
         &self,
         username: &str,
         base_dir: PathBuf,
+        default_quota: u64,
     ) -> Result<UserQuota, QuotaError> {
         {
             let quotas = self.user_quotas.read().await;
@@ -194,7 +195,7 @@ impl QuotaCache {
         }
 
         // Si le quota n'existe pas, créer un nouveau quota
-        let quota = UserQuota::new(username, 10737418240, base_dir); // 10GB par défaut
+        let quota = UserQuota::new(username, default_quota, base_dir);
 
         {
             let mut quotas = self.user_quotas.write().await;
@@ -208,7 +209,11 @@ impl QuotaCache {
     }
 
     /// Obtient ou crée un ratio pour un utilisateur
-    pub async fn get_or_create_user_ratio(&self, username: &str) -> Result<UserRatio, QuotaError> {
+    pub async fn get_or_create_user_ratio(
+        &self,
+        username: &str,
+        default_ratio: &str,
+    ) -> Result<UserRatio, QuotaError> {
         {
             let ratios = self.user_ratios.read().await;
             if let Some(ratio) = ratios.get(username) {
@@ -217,7 +222,7 @@ impl QuotaCache {
         }
 
         // Si le ratio n'existe pas, créer un nouveau ratio
-        let ratio = UserRatio::new(username, "1:1")?;
+        let ratio = UserRatio::new(username, default_ratio)?;
 
         {
             let mut ratios = self.user_ratios.write().await;
@@ -291,6 +296,11 @@ impl QuotaCache {
         self.user_quotas.read().await.clone()
     }
 
+    /// Obtient le quota d'un utilisateur, s'il en a un
+    pub async fn get_user_quota(&self, username: &str) -> Option<UserQuota> {
+        self.user_quotas.read().await.get(username).cloned()
+    }
+
     /// Obtient les ratios
     pub async fn get_ratios(&self) -> HashMap<String, UserRatio> {
         self.user_ratios.read().await.clone()
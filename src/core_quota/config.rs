@@ -1,5 +1,6 @@
 // Configuration des quotas et ratios - inspiré de glFTPd
 
+use crate::constants::DEFAULT_GRACE_PERIOD;
 use crate::core_quota::error::QuotaError;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -28,6 +29,18 @@ pub struct QuotaConfig {
 
     /// Activation du système de ratio
     pub enable_ratio: bool,
+
+    /// Quota souple par défaut (en octets, 0 = aucun)
+    #[serde(default)]
+    pub default_soft_quota: u64,
+
+    /// Durée du délai de grâce après un dépassement du quota souple (en secondes)
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+}
+
+fn default_grace_period() -> u64 {
+    DEFAULT_GRACE_PERIOD
 }
 
 impl Default for QuotaConfig {
@@ -40,26 +53,38 @@ impl Default for QuotaConfig {
             stats_storage_file: PathBuf::from("data/transfer_stats.json"),
             enable_quota: true,
             enable_ratio: true,
+            default_soft_quota: 0,
+            grace_period: DEFAULT_GRACE_PERIOD,
         }
     }
 }
 
 /// Configuration des quotas par groupe
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Un utilisateur sans quota ni ratio propre prend ceux de son groupe principal
+/// (la première ligne GROUP de son userfile). Le quota total d'un groupe limite
+/// la somme des octets utilisés par les utilisateurs dont c'est le groupe
+/// principal.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GroupQuotaConfig {
-    /// Quotas par groupe
+    /// Quotas par groupe, pour chacun de ses membres
     pub group_quotas: HashMap<String, u64>,
 
     /// Ratios par groupe
     pub group_ratios: HashMap<String, String>,
+
+    /// Quotas totaux par groupe (en octets, 0 = illimité)
+    #[serde(default)]
+    pub group_total_quotas: HashMap<String, u64>,
+
+    /// Groupe principal de chaque utilisateur
+    #[serde(default)]
+    pub primary_groups: HashMap<String, String>,
 }
 
 impl GroupQuotaConfig {
     pub fn new() -> Self {
-        Self {
-            group_quotas: HashMap::new(),
-            group_ratios: HashMap::new(),
-        }
+        Self::default()
     }
 
     /// Ajoute ou met à jour un quota de groupe
@@ -82,6 +107,38 @@ impl GroupQuotaConfig {
     pub fn get_group_ratio(&self, groupname: &str) -> Option<String> {
         self.group_ratios.get(groupname).cloned()
     }
+
+    /// Ajoute ou met à jour le quota total d'un groupe
+    pub fn set_group_total_quota(&mut self, groupname: &str, quota: u64) {
+        self.group_total_quotas.insert(groupname.to_string(), quota);
+    }
+
+    /// Obtient le quota total d'un groupe, `None` s'il est illimité
+    pub fn get_group_total_quota(&self, groupname: &str) -> Option<u64> {
+        self.group_total_quotas
+            .get(groupname)
+            .copied()
+            .filter(|quota| *quota > 0)
+    }
+
+    /// Définit le groupe principal d'un utilisateur
+    pub fn set_primary_group(&mut self, username: &str, groupname: &str) {
+        self.primary_groups
+            .insert(username.to_string(), groupname.to_string());
+    }
+
+    /// Obtient le groupe principal d'un utilisateur
+    pub fn primary_group(&self, username: &str) -> Option<&str> {
+        self.primary_groups.get(username).map(String::as_str)
+    }
+
+    /// Liste les utilisateurs dont `groupname` est le groupe principal
+    pub fn primary_members<'a>(&'a self, groupname: &'a str) -> impl Iterator<Item = &'a str> {
+        self.primary_groups
+            .iter()
+            .filter(move |(_, group)| *group == groupname)
+            .map(|(username, _)| username.as_str())
+    }
 }
 
 /// Configuration des quotas par utilisateur
@@ -92,6 +149,10 @@ pub struct UserQuotaConfig {
 
     /// Ratios spécifiques par utilisateur
     pub user_ratios: HashMap<String, String>,
+
+    /// Quotas souples spécifiques par utilisateur
+    #[serde(default)]
+    pub user_soft_quotas: HashMap<String, u64>,
 }
 
 impl UserQuotaConfig {
@@ -99,6 +160,7 @@ impl UserQuotaConfig {
         Self {
             user_quotas: HashMap::new(),
             user_ratios: HashMap::new(),
+            user_soft_quotas: HashMap::new(),
         }
     }
 
@@ -112,6 +174,16 @@ impl UserQuotaConfig {
         self.user_quotas.get(username).copied()
     }
 
+    /// Ajoute ou met à jour un quota souple utilisateur
+    pub fn set_user_soft_quota(&mut self, username: &str, quota: u64) {
+        self.user_soft_quotas.insert(username.to_string(), quota);
+    }
+
+    /// Obtient le quota souple pour un utilisateur
+    pub fn get_user_soft_quota(&self, username: &str) -> Option<u64> {
+        self.user_soft_quotas.get(username).copied()
+    }
+
     /// Ajoute ou met à jour un ratio utilisateur
     pub fn set_user_ratio(&mut self, username: &str, ratio: &str) {
         self.user_ratios
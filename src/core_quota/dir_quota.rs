@@ -0,0 +1,410 @@
+// Quotas de répertoire - limitent une arborescence du site, quel que soit
+// l'utilisateur qui y envoie des fichiers
+
+use crate::core_quota::error::QuotaError;
+use crate::helpers::write_file_atomic;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Limite d'une arborescence du site
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirQuota {
+    /// Chemin du site couvert, sous-répertoires compris (ex: /shared)
+    pub path: String,
+
+    /// Taille maximale en octets (0 = illimitée)
+    pub max_bytes: u64,
+
+    /// Nombre maximal de fichiers et de répertoires (None = illimité)
+    #[serde(default)]
+    pub max_files: Option<u64>,
+}
+
+impl DirQuota {
+    pub fn new(path: &str, max_bytes: u64, max_files: Option<u64>) -> Self {
+        Self {
+            path: format!("/{}", path.trim_matches('/')),
+            max_bytes,
+            max_files,
+        }
+    }
+
+    /// Une limite qui ne limite rien est retirée
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes == 0 && self.max_files.is_none()
+    }
+
+    /// Formate la limite et l'usage pour l'affichage
+    pub fn format_usage(&self, usage: &DirUsage) -> String {
+        let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let bytes = if self.max_bytes == 0 {
+            format!("{:.2}MB / Unlimited", mb(usage.bytes))
+        } else {
+            format!("{:.2}MB / {:.2}MB", mb(usage.bytes), mb(self.max_bytes))
+        };
+        match self.max_files {
+            Some(max_files) => format!("{}, {} / {} files", bytes, usage.files, max_files),
+            None => format!("{}, {} files", bytes, usage.files),
+        }
+    }
+}
+
+/// Espace occupé sous une arborescence
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirUsage {
+    pub bytes: u64,
+
+    /// Fichiers et répertoires
+    pub files: u64,
+}
+
+impl DirUsage {
+    /// Un fichier de `bytes` octets
+    pub fn file(bytes: u64) -> Self {
+        Self { bytes, files: 1 }
+    }
+
+    /// Mesure un fichier ou une arborescence sur le disque, sans suivre les
+    /// liens symboliques. Bloquant, voir `measure`.
+    pub fn scan(path: &Path) -> Self {
+        let mut usage = Self::default();
+        let Ok(metadata) = std::fs::symlink_metadata(path) else {
+            return usage;
+        };
+        if !metadata.is_dir() {
+            return Self::file(metadata.len());
+        }
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                usage.files += 1;
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    usage.bytes += metadata.len();
+                }
+            }
+        }
+        usage
+    }
+
+    /// Mesure `path` sans bloquer le runtime
+    pub async fn measure(path: PathBuf) -> Self {
+        tokio::task::spawn_blocking(move || Self::scan(&path))
+            .await
+            .unwrap_or_default()
+    }
+}
+
+/// Une limite suivie, avec son répertoire sur le disque
+struct TrackedDir {
+    quota: DirQuota,
+    root: PathBuf,
+    usage: DirUsage,
+}
+
+/// Quotas de répertoire et leur usage.
+///
+/// Les limites viennent de la configuration et de SITE DIRQUOTA, sauvegardées
+/// dans le fichier de stockage et prioritaires sur la configuration. L'usage
+/// est mesuré sur le disque au chargement, puis tenu à jour à chaque upload,
+/// suppression ou déplacement.
+pub struct DirQuotas {
+    tracked: Mutex<Vec<TrackedDir>>,
+    overrides: Mutex<Vec<DirQuota>>,
+    storage_file: PathBuf,
+    /// Une seule écriture du fichier à la fois
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl DirQuotas {
+    pub fn new(storage_file: PathBuf) -> Self {
+        Self {
+            tracked: Mutex::new(Vec::new()),
+            overrides: Mutex::new(Vec::new()),
+            storage_file,
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Charge les limites de la configuration et celles posées par SITE
+    /// DIRQUOTA, puis mesure l'usage de chaque arborescence
+    pub async fn load(&self, site_root: &Path, configured: &[DirQuota]) {
+        match tokio::fs::read_to_string(&self.storage_file).await {
+            Ok(content) => match serde_json::from_str::<Vec<DirQuota>>(&content) {
+                Ok(overrides) => *self.overrides.lock().unwrap() = overrides,
+                Err(e) => error!(
+                    "Failed to parse directory quota file {:?}: {}",
+                    self.storage_file, e
+                ),
+            },
+            Err(e) => info!(
+                "No directory quota file loaded from {:?}: {}",
+                self.storage_file, e
+            ),
+        }
+
+        let mut quotas: Vec<DirQuota> = configured
+            .iter()
+            .map(|quota| DirQuota::new(&quota.path, quota.max_bytes, quota.max_files))
+            .collect();
+        for quota in self.overrides.lock().unwrap().iter() {
+            quotas.retain(|q| q.path != quota.path);
+            quotas.push(quota.clone());
+        }
+        quotas.retain(|quota| !quota.is_unlimited());
+
+        let site_root = site_root
+            .canonicalize()
+            .unwrap_or_else(|_| site_root.to_path_buf());
+        let mut tracked = Vec::new();
+        for quota in quotas {
+            let root = site_root.join(quota.path.trim_start_matches('/'));
+            let usage = DirUsage::measure(root.clone()).await;
+            info!(
+                "Directory quota {}: {}",
+                quota.path,
+                quota.format_usage(&usage)
+            );
+            tracked.push(TrackedDir { quota, root, usage });
+        }
+        *self.tracked.lock().unwrap() = tracked;
+    }
+
+    async fn save(&self) -> Result<(), QuotaError> {
+        // Pris avant la copie, une écriture plus récente ne peut pas être écrasée
+        let _saving = self.save_lock.lock().await;
+        let content = serde_json::to_string_pretty(&*self.overrides.lock().unwrap())
+            .map_err(|e| QuotaError::QuotaWriteError(e.to_string()))?;
+        write_file_atomic(&self.storage_file, content.as_bytes())
+            .await
+            .map_err(|e| QuotaError::QuotaWriteError(e.to_string()))
+    }
+
+    /// Pose ou change une limite, retirée si elle ne limite rien. L'usage
+    /// d'une nouvelle arborescence est mesuré sur le disque.
+    pub async fn set(
+        &self,
+        site_root: &Path,
+        quota: DirQuota,
+    ) -> Result<Option<DirUsage>, QuotaError> {
+        {
+            let mut overrides = self.overrides.lock().unwrap();
+            overrides.retain(|q| q.path != quota.path);
+            overrides.push(quota.clone());
+        }
+        self.save().await?;
+
+        if quota.is_unlimited() {
+            self.tracked
+                .lock()
+                .unwrap()
+                .retain(|tracked| tracked.quota.path != quota.path);
+            return Ok(None);
+        }
+
+        let known = self
+            .tracked
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|tracked| tracked.quota.path == quota.path)
+            .map(|tracked| {
+                tracked.quota = quota.clone();
+                tracked.usage
+            });
+        if known.is_some() {
+            return Ok(known);
+        }
+
+        let site_root = site_root
+            .canonicalize()
+            .unwrap_or_else(|_| site_root.to_path_buf());
+        let root = site_root.join(quota.path.trim_start_matches('/'));
+        let usage = DirUsage::measure(root.clone()).await;
+        self.tracked
+            .lock()
+            .unwrap()
+            .push(TrackedDir { quota, root, usage });
+        Ok(Some(usage))
+    }
+
+    /// Liste les limites avec leur usage, triées par chemin
+    pub fn list(&self) -> Vec<(DirQuota, DirUsage)> {
+        let mut list: Vec<(DirQuota, DirUsage)> = self
+            .tracked
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tracked| (tracked.quota.clone(), tracked.usage))
+            .collect();
+        list.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+        list
+    }
+
+    /// Vérifie que `added` tient dans les limites couvrant `path`
+    pub fn check(&self, path: &Path, added: DirUsage) -> Result<(), QuotaError> {
+        let tracked = self.tracked.lock().unwrap();
+        for tracked in tracked
+            .iter()
+            .filter(|tracked| path.starts_with(&tracked.root))
+        {
+            Self::check_one(tracked, added)?;
+        }
+        Ok(())
+    }
+
+    fn check_one(tracked: &TrackedDir, added: DirUsage) -> Result<(), QuotaError> {
+        let quota = &tracked.quota;
+        if quota.max_bytes > 0 && tracked.usage.bytes + added.bytes > quota.max_bytes {
+            return Err(QuotaError::DirQuotaExceeded(quota.path.clone()));
+        }
+        if let Some(max_files) = quota.max_files {
+            if added.files > 0 && tracked.usage.files + added.files > max_files {
+                return Err(QuotaError::DirFileLimitReached(quota.path.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Octets qui peuvent encore être écrits sous `path`, None sans limite
+    pub fn allowance(&self, path: &Path) -> Option<u64> {
+        self.tracked
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tracked| tracked.quota.max_bytes > 0 && path.starts_with(&tracked.root))
+            .map(|tracked| tracked.quota.max_bytes.saturating_sub(tracked.usage.bytes))
+            .min()
+    }
+
+    /// Compte ce qui a été ajouté sous `path`
+    pub fn record_add(&self, path: &Path, added: DirUsage) {
+        for tracked in self.tracked.lock().unwrap().iter_mut() {
+            if path.starts_with(&tracked.root) {
+                tracked.usage.bytes += added.bytes;
+                tracked.usage.files += added.files;
+            }
+        }
+    }
+
+    /// Décompte ce qui a été supprimé sous `path`
+    pub fn record_remove(&self, path: &Path, removed: DirUsage) {
+        for tracked in self.tracked.lock().unwrap().iter_mut() {
+            if path.starts_with(&tracked.root) {
+                tracked.usage.bytes = tracked.usage.bytes.saturating_sub(removed.bytes);
+                tracked.usage.files = tracked.usage.files.saturating_sub(removed.files);
+            }
+        }
+    }
+
+    /// Vrai si un déplacement de `from` vers `to` entre dans une arborescence
+    /// limitée ou en sort
+    pub fn moves_across(&self, from: &Path, to: &Path) -> bool {
+        self.tracked
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|tracked| from.starts_with(&tracked.root) != to.starts_with(&tracked.root))
+    }
+
+    /// Vérifie qu'un déplacement tient dans les limites où il entre
+    pub fn check_move(&self, from: &Path, to: &Path, moved: DirUsage) -> Result<(), QuotaError> {
+        let tracked = self.tracked.lock().unwrap();
+        for tracked in tracked
+            .iter()
+            .filter(|tracked| to.starts_with(&tracked.root) && !from.starts_with(&tracked.root))
+        {
+            Self::check_one(tracked, moved)?;
+        }
+        Ok(())
+    }
+
+    /// Reporte un déplacement sur les arborescences quittées et rejointes
+    pub fn record_move(&self, from: &Path, to: &Path, moved: DirUsage) {
+        for tracked in self.tracked.lock().unwrap().iter_mut() {
+            match (
+                from.starts_with(&tracked.root),
+                to.starts_with(&tracked.root),
+            ) {
+                (true, false) => {
+                    tracked.usage.bytes = tracked.usage.bytes.saturating_sub(moved.bytes);
+                    tracked.usage.files = tracked.usage.files.saturating_sub(moved.files);
+                }
+                (false, true) => {
+                    tracked.usage.bytes += moved.bytes;
+                    tracked.usage.files += moved.files;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dir_quota_tracking() {
+        let site = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(site.path().join("shared/sub")).unwrap();
+        std::fs::write(site.path().join("shared/sub/a.bin"), [0u8; 600]).unwrap();
+
+        let dir_quotas = DirQuotas::new(site.path().join("dirquotas.json"));
+        let configured = [DirQuota::new("shared/", 1000, Some(3))];
+        dir_quotas.load(site.path(), &configured).await;
+        let root = site.path().canonicalize().unwrap();
+        let shared = root.join("shared");
+
+        // Mesure initiale : un répertoire et un fichier
+        let list = dir_quotas.list();
+        assert_eq!(list[0].0.path, "/shared");
+        assert_eq!(
+            list[0].1,
+            DirUsage {
+                bytes: 600,
+                files: 2
+            }
+        );
+        assert_eq!(dir_quotas.allowance(&shared.join("b.bin")), Some(400));
+        assert!(dir_quotas.allowance(&root.join("other.bin")).is_none());
+
+        assert!(dir_quotas
+            .check(&shared.join("b.bin"), DirUsage::file(400))
+            .is_ok());
+        assert!(matches!(
+            dir_quotas.check(&shared.join("b.bin"), DirUsage::file(401)),
+            Err(QuotaError::DirQuotaExceeded(_))
+        ));
+        dir_quotas.record_add(&shared.join("b.bin"), DirUsage::file(100));
+        assert!(matches!(
+            dir_quotas.check(&shared.join("c"), DirUsage { bytes: 0, files: 1 }),
+            Err(QuotaError::DirFileLimitReached(_))
+        ));
+
+        // Sortir un fichier de l'arborescence libère sa place
+        let (from, to) = (shared.join("b.bin"), root.join("b.bin"));
+        assert!(dir_quotas.moves_across(&from, &to));
+        dir_quotas.record_move(&from, &to, DirUsage::file(100));
+        assert!(dir_quotas
+            .check_move(&to, &from, DirUsage::file(400))
+            .is_ok());
+        dir_quotas.record_remove(&shared.join("sub/a.bin"), DirUsage::file(600));
+        assert_eq!(dir_quotas.list()[0].1, DirUsage { bytes: 0, files: 1 });
+
+        // Une limite posée par SITE DIRQUOTA remplace celle de la configuration
+        let quota = DirQuota::new("/shared", 0, None);
+        assert!(dir_quotas.set(site.path(), quota).await.unwrap().is_none());
+        dir_quotas.load(site.path(), &configured).await;
+        assert!(dir_quotas.list().is_empty());
+    }
+}
@@ -7,6 +7,18 @@ pub enum QuotaError {
     #[error("Quota exceeded for user {0}")]
     QuotaExceeded(String),
 
+    #[error("Quota exceeded for group {0}")]
+    GroupQuotaExceeded(String),
+
+    #[error("Soft quota grace period expired for user {0}")]
+    GracePeriodExpired(String),
+
+    #[error("Quota exceeded for directory {0}")]
+    DirQuotaExceeded(String),
+
+    #[error("File limit reached for directory {0}")]
+    DirFileLimitReached(String),
+
     #[error("Ratio limit reached for user {0}")]
     RatioLimitReached(String),
 
@@ -35,6 +47,20 @@ impl QuotaError {
             QuotaError::QuotaExceeded(_) => {
                 "552 Requested file action aborted. Exceeded storage allocation.".to_string()
             }
+            QuotaError::GroupQuotaExceeded(_) => {
+                "552 Requested file action aborted. Exceeded group storage allocation.".to_string()
+            }
+            QuotaError::GracePeriodExpired(_) => {
+                "552 Requested file action aborted. Soft quota exceeded and grace period expired."
+                    .to_string()
+            }
+            QuotaError::DirQuotaExceeded(_) => {
+                "552 Requested file action aborted. Exceeded directory storage allocation."
+                    .to_string()
+            }
+            QuotaError::DirFileLimitReached(_) => {
+                "552 Requested file action aborted. Too many files in the directory.".to_string()
+            }
             QuotaError::RatioLimitReached(_) => {
                 "552 Requested file action aborted. Ratio limit reached.".to_string()
             }
@@ -8,15 +8,20 @@ use crate::core_quota::{
     quota::UserQuota,
     ratio::UserRatio,
 };
-use std::path::PathBuf;
-use std::sync::Arc;
+use crate::core_users::UserStore;
+use arc_swap::ArcSwap;
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::{Mutex, RwLock};
 
 /// Gestionnaire principal des quotas et ratios
 #[derive(Clone)]
 pub struct QuotaManager {
-    /// Configuration globale
-    config: Arc<QuotaConfig>,
+    /// Configuration globale, remplaçable à chaud (SIGHUP, SITE REHASH)
+    config: Arc<ArcSwap<QuotaConfig>>,
 
     /// Configuration des groupes
     group_config: Arc<Mutex<GroupQuotaConfig>>,
@@ -26,6 +31,54 @@ pub struct QuotaManager {
 
     /// Cache des quotas et ratios
     cache: Arc<QuotaCache>,
+
+    /// Octets réservés par les uploads en cours, par utilisateur
+    reservations: Arc<StdMutex<HashMap<String, u64>>>,
+
+    /// Sérialise les réservations et l'enregistrement des uploads, pour que des
+    /// uploads parallèles ne dépassent pas ensemble le quota
+    upload_lock: Arc<Mutex<()>>,
+}
+
+/// Taille minimale d'une réservation, pour ne pas recalculer le quota restant à
+/// chaque bloc reçu
+const RESERVE_STEP: u64 = 1024 * 1024;
+
+/// Octets d'un upload en cours déjà réservés sur le quota de son utilisateur,
+/// rendus à la fin de l'upload
+#[derive(Debug)]
+pub struct UploadReservation {
+    reservations: Arc<StdMutex<HashMap<String, u64>>>,
+    username: String,
+    /// `None` si l'utilisateur n'a pas de limite
+    granted: Option<u64>,
+}
+
+impl UploadReservation {
+    /// Octets que l'upload peut écrire, `None` sans limite
+    pub fn granted(&self) -> Option<u64> {
+        self.granted
+    }
+
+    /// Rend les octets réservés
+    fn release(&mut self) {
+        let Some(granted) = self.granted.take().filter(|granted| *granted > 0) else {
+            return;
+        };
+        let mut reservations = self.reservations.lock().unwrap();
+        if let Some(reserved) = reservations.get_mut(&self.username) {
+            *reserved = reserved.saturating_sub(granted);
+            if *reserved == 0 {
+                reservations.remove(&self.username);
+            }
+        }
+    }
+}
+
+impl Drop for UploadReservation {
+    fn drop(&mut self) {
+        self.release();
+    }
 }
 
 impl QuotaManager {
@@ -42,35 +95,59 @@ impl QuotaManager {
         ));
 
         Self {
-            config: Arc::new(config),
+            config: Arc::new(ArcSwap::from_pointee(config)),
             group_config: Arc::new(Mutex::new(group_config)),
             user_config: Arc::new(Mutex::new(user_config)),
             cache,
+            reservations: Arc::new(StdMutex::new(HashMap::new())),
+            upload_lock: Arc::new(Mutex::new(())),
         }
     }
 
+    /// Remplace la configuration globale (quota et ratio par défaut, activation)
+    /// Les fichiers de stockage ne changent qu'au redémarrage
+    pub fn update_config(&self, config: QuotaConfig) {
+        self.config.store(Arc::new(config));
+    }
+
     /// Charge les données de quota depuis les fichiers
-    pub async fn load(&self) -> Result<(), QuotaError> {
+    pub async fn load(&self, users: &UserStore) -> Result<(), QuotaError> {
         // Charger les données dans le cache
         self.cache.load_all().await?;
 
         // Charger les configurations depuis les fichiers utilisateurs
-        let users_dir = PathBuf::from("ftp-data/users");
-        if let Ok(user_configs) =
-            crate::core_quota::user_file_parser::load_user_quota_configs(&users_dir)
-        {
-            let mut user_config = self.user_config.lock().await;
-            for (username, (quota, ratio)) in user_configs {
-                if let Some(q) = quota {
-                    user_config.set_user_quota(&username, q);
-                }
-                if let Some(r) = ratio {
-                    user_config.set_user_ratio(&username, &r);
+        self.load_user_configs(users).await;
+
+        Ok(())
+    }
+
+    /// Recharge les quotas et ratios propres à chaque utilisateur depuis les
+    /// fichiers utilisateurs
+    pub async fn load_user_configs(&self, users: &UserStore) {
+        match crate::core_quota::user_file_parser::load_user_quota_configs(users).await {
+            Ok(user_configs) => {
+                let mut user_config = UserQuotaConfig::new();
+                for (username, (quota, soft_quota, ratio)) in user_configs {
+                    if let Some(q) = quota {
+                        user_config.set_user_quota(&username, q);
+                    }
+                    if let Some(s) = soft_quota {
+                        user_config.set_user_soft_quota(&username, s);
+                    }
+                    if let Some(r) = ratio {
+                        user_config.set_user_ratio(&username, &r);
+                    }
                 }
+                *self.user_config.lock().await = user_config;
             }
+            Err(e) => warn!("Failed to load the user quotas: {}", e),
         }
+    }
 
-        Ok(())
+    /// Remplace la configuration des groupes : quotas, ratios et groupes
+    /// principaux des utilisateurs
+    pub async fn set_group_config(&self, group_config: GroupQuotaConfig) {
+        *self.group_config.lock().await = group_config;
     }
 
     /// Sauvegarde les données de quota dans les fichiers
@@ -78,40 +155,128 @@ impl QuotaManager {
         self.cache.save_all().await
     }
 
+    /// Sauvegarde les données modifiées et vide les écritures en attente sur le disque
+    /// À appeler avant l'arrêt du serveur
+    pub async fn flush(&self) -> Result<(), QuotaError> {
+        self.save().await?;
+        self.cache.force_save_all().await
+    }
+
+    /// Résout le quota d'un utilisateur : le sien, puis celui de son groupe
+    /// principal, puis le quota par défaut
+    async fn effective_quota(&self, username: &str) -> u64 {
+        if let Some(quota) = self.user_config.lock().await.get_user_quota(username) {
+            return quota;
+        }
+        let group_config = self.group_config.lock().await;
+        group_config
+            .primary_group(username)
+            .and_then(|group| group_config.get_group_quota(group))
+            .unwrap_or_else(|| self.config.load().default_quota)
+    }
+
+    /// Résout le ratio d'un utilisateur : le sien, puis celui de son groupe
+    /// principal, puis le ratio par défaut
+    async fn effective_ratio(&self, username: &str) -> String {
+        if let Some(ratio) = self.user_config.lock().await.get_user_ratio(username) {
+            return ratio;
+        }
+        let group_config = self.group_config.lock().await;
+        group_config
+            .primary_group(username)
+            .and_then(|group| group_config.get_group_ratio(group))
+            .unwrap_or_else(|| self.config.load().default_ratio.clone())
+    }
+
+    /// Résout le quota souple d'un utilisateur : le sien, puis celui par
+    /// défaut. Ignoré s'il n'est pas sous le quota strict `max_bytes`.
+    async fn effective_soft_quota(&self, username: &str, max_bytes: u64) -> u64 {
+        let soft_bytes = self
+            .user_config
+            .lock()
+            .await
+            .get_user_soft_quota(username)
+            .unwrap_or_else(|| self.config.load().default_soft_quota);
+        if max_bytes == 0 || soft_bytes < max_bytes {
+            soft_bytes
+        } else {
+            0
+        }
+    }
+
     /// Obtient ou crée un quota pour un utilisateur
     pub async fn get_or_create_user_quota(
         &self,
         username: &str,
         base_dir: PathBuf,
     ) -> Result<UserQuota, QuotaError> {
-        // Vérifier si l'utilisateur a un quota spécifique
-        if let Some(quota_bytes) = self.user_config.lock().await.get_user_quota(username) {
-            let quota = UserQuota::new(username, quota_bytes, base_dir);
+        let max_bytes = self.effective_quota(username).await;
+        let soft_bytes = self.effective_soft_quota(username, max_bytes).await;
+        let mut quota = self
+            .cache
+            .get_or_create_user_quota(username, base_dir, max_bytes)
+            .await?;
+
+        // Le quota a changé depuis : garder l'espace déjà utilisé
+        if quota.max_bytes != max_bytes || quota.soft_bytes != soft_bytes {
+            quota.max_bytes = max_bytes;
+            quota.is_unlimited = max_bytes == 0;
+            quota.soft_bytes = soft_bytes;
+            quota.update_grace(unix_now());
             self.cache
                 .update_user_quota(username, quota.clone())
                 .await?;
-            return Ok(quota);
         }
-
-        // Sinon, utiliser le quota par défaut
-        self.cache
-            .get_or_create_user_quota(username, base_dir)
-            .await
+        Ok(quota)
     }
 
     /// Obtient ou crée un ratio pour un utilisateur
     pub async fn get_or_create_user_ratio(&self, username: &str) -> Result<UserRatio, QuotaError> {
-        // Vérifier si l'utilisateur a un ratio spécifique
-        if let Some(ratio_str) = self.user_config.lock().await.get_user_ratio(username) {
-            let ratio = UserRatio::new(username, &ratio_str)?;
+        let ratio_str = self.effective_ratio(username).await;
+        let configured = UserRatio::new(username, &ratio_str)?;
+        let mut ratio = self
+            .cache
+            .get_or_create_user_ratio(username, &ratio_str)
+            .await?;
+
+        // Le ratio a changé depuis : garder les octets transférés
+        if ratio.configured_ratio_string() != configured.configured_ratio_string() {
+            ratio.upload_ratio = configured.upload_ratio;
+            ratio.download_ratio = configured.download_ratio;
+            ratio.is_unlimited = configured.is_unlimited;
             self.cache
                 .update_user_ratio(username, ratio.clone())
                 .await?;
-            return Ok(ratio);
         }
+        Ok(ratio)
+    }
+
+    /// Renvoie le groupe principal d'un utilisateur, les membres dont c'est le
+    /// groupe principal et le quota total du groupe. `None` si le groupe n'a pas
+    /// de quota total.
+    async fn group_members(&self, username: &str) -> Option<(String, Vec<String>, u64)> {
+        let group_config = self.group_config.lock().await;
+        let group = group_config.primary_group(username)?.to_string();
+        let max_bytes = group_config.get_group_total_quota(&group)?;
+        let members = group_config
+            .primary_members(&group)
+            .map(String::from)
+            .collect();
+        Some((group, members, max_bytes))
+    }
 
-        // Sinon, utiliser le ratio par défaut
-        self.cache.get_or_create_user_ratio(username).await
+    /// Renvoie le groupe principal d'un utilisateur, l'espace utilisé par les
+    /// membres dont c'est le groupe principal et le quota total du groupe.
+    /// `None` si le groupe n'a pas de quota total.
+    pub async fn group_usage(&self, username: &str) -> Option<(String, u64, u64)> {
+        let (group, members, max_bytes) = self.group_members(username).await?;
+        let quotas = self.cache.get_quotas().await;
+        let used_bytes = members
+            .iter()
+            .filter_map(|member| quotas.get(member))
+            .map(|quota| quota.used_bytes)
+            .sum();
+        Some((group, used_bytes, max_bytes))
     }
 
     /// Vérifie si un utilisateur peut uploader un fichier
@@ -121,44 +286,160 @@ impl QuotaManager {
         base_dir: PathBuf,
         file_size: u64,
     ) -> Result<(), QuotaError> {
-        if !self.config.enable_quota && !self.config.enable_ratio {
+        if !self.config.load().enable_quota && !self.config.load().enable_ratio {
             return Ok(());
         }
 
         // Vérification du quota
-        if self.config.enable_quota {
+        if self.config.load().enable_quota {
             let quota = self.get_or_create_user_quota(username, base_dir).await?;
             quota.check_quota(file_size)?;
+            quota.check_grace(file_size, unix_now(), self.config.load().grace_period)?;
+
+            // Puis le quota total du groupe principal
+            if let Some((group, used_bytes, max_bytes)) = self.group_usage(username).await {
+                if used_bytes + file_size > max_bytes {
+                    return Err(QuotaError::GroupQuotaExceeded(group));
+                }
+            }
         }
 
         Ok(())
     }
 
-    /// Vérifie si un utilisateur peut télécharger un fichier
-    pub async fn check_download(&self, username: &str, file_size: u64) -> Result<u64, QuotaError> {
-        if !self.config.enable_ratio {
-            return Ok(file_size);
+    /// Renvoie ce qu'un utilisateur peut encore envoyer avant de dépasser son
+    /// quota ou le quota total de son groupe principal, `None` sans limite.
+    /// Les octets réservés par les uploads en cours sont déjà comptés.
+    pub async fn upload_allowance(
+        &self,
+        username: &str,
+        base_dir: PathBuf,
+    ) -> Result<Option<u64>, QuotaError> {
+        if !self.config.load().enable_quota {
+            return Ok(None);
         }
 
-        let ratio = self.get_or_create_user_ratio(username).await?;
-        ratio.check_download(file_size)
+        let quota = self.get_or_create_user_quota(username, base_dir).await?;
+        let mut allowance =
+            (!quota.is_unlimited).then(|| quota.max_bytes.saturating_sub(quota.used_bytes));
+        // Délai de grâce écoulé : le quota souple est la limite
+        if let Some(ends) = quota.grace_ends(self.config.load().grace_period) {
+            if unix_now() >= ends {
+                let left = quota.soft_bytes.saturating_sub(quota.used_bytes);
+                allowance = Some(allowance.map_or(left, |allowance| allowance.min(left)));
+            }
+        }
+        let group = match self.group_members(username).await {
+            Some((_, members, max_bytes)) => {
+                let quotas = self.cache.get_quotas().await;
+                let used_bytes: u64 = members
+                    .iter()
+                    .filter_map(|member| quotas.get(member))
+                    .map(|quota| quota.used_bytes)
+                    .sum();
+                Some((members, max_bytes.saturating_sub(used_bytes)))
+            }
+            None => None,
+        };
+
+        let reservations = self.reservations.lock().unwrap();
+        let reserved = |user: &str| reservations.get(user).copied().unwrap_or(0);
+        let mut allowance = allowance.map(|allowance| allowance.saturating_sub(reserved(username)));
+        if let Some((members, left)) = group {
+            let reserved: u64 = members.iter().map(|member| reserved(member)).sum();
+            let left = left.saturating_sub(reserved);
+            allowance = Some(allowance.map_or(left, |allowance| allowance.min(left)));
+        }
+        Ok(allowance)
+    }
+
+    /// Commence la réservation du quota d'un upload, vide au départ
+    pub fn start_upload(&self, username: &str) -> UploadReservation {
+        UploadReservation {
+            reservations: Arc::clone(&self.reservations),
+            username: username.to_string(),
+            granted: Some(0),
+        }
+    }
+
+    /// Étend la réservation d'un upload à `wanted` octets, ou à tout ce qui
+    /// reste du quota s'il en reste moins
+    pub async fn reserve_upload(
+        &self,
+        reservation: &mut UploadReservation,
+        base_dir: PathBuf,
+        wanted: u64,
+    ) -> Result<(), QuotaError> {
+        let Some(granted) = reservation.granted.filter(|granted| *granted < wanted) else {
+            return Ok(());
+        };
+
+        let _lock = self.upload_lock.lock().await;
+        let allowance = self
+            .upload_allowance(&reservation.username, base_dir)
+            .await?;
+        match allowance {
+            Some(allowance) => {
+                let more = (wanted - granted).max(RESERVE_STEP).min(allowance);
+                if more > 0 {
+                    *self
+                        .reservations
+                        .lock()
+                        .unwrap()
+                        .entry(reservation.username.clone())
+                        .or_insert(0) += more;
+                    reservation.granted = Some(granted + more);
+                }
+            }
+            None => reservation.release(),
+        }
+        Ok(())
+    }
+
+    /// Réserve d'avance la taille annoncée par ALLO, refusée si ce qui reste du
+    /// quota, réservations des autres uploads comprises, ne la couvre pas
+    pub async fn reserve_allocation(
+        &self,
+        reservation: &mut UploadReservation,
+        base_dir: PathBuf,
+        size: u64,
+    ) -> Result<(), QuotaError> {
+        self.check_upload(&reservation.username, base_dir.clone(), size)
+            .await?;
+        self.reserve_upload(reservation, base_dir, size).await?;
+        match reservation.granted {
+            Some(granted) if granted < size => {
+                Err(QuotaError::QuotaExceeded(reservation.username.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Ratio d'un utilisateur pour ses crédits, `None` si les ratios sont
+    /// désactivés
+    pub async fn user_ratio(&self, username: &str) -> Result<Option<UserRatio>, QuotaError> {
+        if !self.config.load().enable_ratio {
+            return Ok(None);
+        }
+        self.get_or_create_user_ratio(username).await.map(Some)
     }
 
     /// Met à jour les statistiques après un upload
     pub async fn record_upload(&self, username: &str, bytes: u64) -> Result<(), QuotaError> {
-        // Mettre à jour le quota
-        if self.config.enable_quota {
+        // Mettre à jour le quota, déjà vérifié pendant l'upload
+        if self.config.load().enable_quota {
+            let _lock = self.upload_lock.lock().await;
             let mut quota = self
-                .cache
                 .get_or_create_user_quota(username, PathBuf::from("/"))
                 .await?;
-            quota.update_used_bytes(bytes)?;
+            quota.update_used_bytes(bytes);
+            quota.update_grace(unix_now());
             self.cache.update_user_quota(username, quota).await?;
         }
 
         // Mettre à jour le ratio
-        if self.config.enable_ratio {
-            let mut ratio = self.cache.get_or_create_user_ratio(username).await?;
+        if self.config.load().enable_ratio {
+            let mut ratio = self.get_or_create_user_ratio(username).await?;
             ratio.update_uploaded(bytes);
             self.cache.update_user_ratio(username, ratio).await?;
         }
@@ -169,12 +450,70 @@ impl QuotaManager {
         Ok(())
     }
 
+    /// Rend au quota d'un utilisateur les octets d'un de ses fichiers supprimé
+    /// ou écrasé
+    pub async fn record_delete(&self, username: &str, bytes: u64) -> Result<(), QuotaError> {
+        if !self.config.load().enable_quota {
+            return Ok(());
+        }
+        let Some(mut quota) = self.cache.get_user_quota(username).await else {
+            return Ok(());
+        };
+        quota.reduce_used_bytes(bytes);
+        quota.update_grace(unix_now());
+        self.cache.update_user_quota(username, quota).await
+    }
+
+    /// Reporte le déplacement de fichiers d'un utilisateur de `from` vers `to` :
+    /// les octets sont rendus s'ils sortent de l'arborescence de son quota et
+    /// repris s'ils y entrent
+    pub async fn record_move(
+        &self,
+        username: &str,
+        bytes: u64,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), QuotaError> {
+        if !self.config.load().enable_quota {
+            return Ok(());
+        }
+        let Some(mut quota) = self.cache.get_user_quota(username).await else {
+            return Ok(());
+        };
+        match (
+            from.starts_with(&quota.base_dir),
+            to.starts_with(&quota.base_dir),
+        ) {
+            (true, false) => quota.reduce_used_bytes(bytes),
+            (false, true) => quota.used_bytes += bytes,
+            _ => return Ok(()),
+        }
+        quota.update_grace(unix_now());
+        self.cache.update_user_quota(username, quota).await
+    }
+
+    /// Remplace l'espace utilisé par un utilisateur, recalculé depuis le disque
+    pub async fn set_used_bytes(
+        &self,
+        username: &str,
+        base_dir: PathBuf,
+        bytes: u64,
+    ) -> Result<UserQuota, QuotaError> {
+        let mut quota = self.get_or_create_user_quota(username, base_dir).await?;
+        quota.used_bytes = bytes;
+        quota.update_grace(unix_now());
+        self.cache
+            .update_user_quota(username, quota.clone())
+            .await?;
+        Ok(quota)
+    }
+
     /// Met à jour les statistiques après un download
     pub async fn record_download(&self, username: &str, bytes: u64) -> Result<(), QuotaError> {
-        // Vérifier et mettre à jour le ratio
-        if self.config.enable_ratio {
-            let mut ratio = self.cache.get_or_create_user_ratio(username).await?;
-            ratio.update_downloaded(bytes)?;
+        // Mettre à jour le ratio, les crédits ont été vérifiés avant le transfert
+        if self.config.load().enable_ratio {
+            let mut ratio = self.get_or_create_user_ratio(username).await?;
+            ratio.update_downloaded(bytes);
             self.cache.update_user_ratio(username, ratio).await?;
         }
 
@@ -194,6 +533,48 @@ impl QuotaManager {
         Ok(quota.format_quota())
     }
 
+    /// Avertit un utilisateur qui dépasse son quota souple, avec la fin du délai
+    /// de grâce. `None` s'il est sous son quota souple.
+    pub async fn soft_quota_warning(&self, username: &str) -> Option<String> {
+        if !self.config.load().enable_quota {
+            return None;
+        }
+        let quota = self.cache.get_user_quota(username).await?;
+        let ends = quota.grace_ends(self.config.load().grace_period)?;
+        let soft_mb = quota.soft_bytes as f64 / (1024.0 * 1024.0);
+
+        let now = unix_now();
+        if now >= ends {
+            return Some(format!(
+                "You are over your soft quota of {:.2}MB and your grace period has expired, free some space to upload again.",
+                soft_mb
+            ));
+        }
+        let left = ends - now;
+        Some(format!(
+            "You are over your soft quota of {:.2}MB, your grace period ends in {}d {}h {}m.",
+            soft_mb,
+            left / 86400,
+            left % 86400 / 3600,
+            left % 3600 / 60
+        ))
+    }
+
+    /// Obtient les informations du quota total du groupe principal d'un
+    /// utilisateur, `None` si ce groupe n'en a pas
+    pub async fn get_group_quota_info(&self, username: &str) -> Option<String> {
+        let (group, used_bytes, max_bytes) = self.group_usage(username).await?;
+        let used_mb = used_bytes as f64 / (1024.0 * 1024.0);
+        let max_mb = max_bytes as f64 / (1024.0 * 1024.0);
+        Some(format!(
+            "Group {}: {:.2}MB / {:.2}MB ({:.1}%)",
+            group,
+            used_mb,
+            max_mb,
+            used_bytes as f64 / max_bytes as f64 * 100.0
+        ))
+    }
+
     /// Obtient les informations de ratio pour un utilisateur
     pub async fn get_ratio_info(&self, username: &str) -> Result<String, QuotaError> {
         let ratio = self.get_or_create_user_ratio(username).await?;
@@ -207,3 +588,218 @@ impl QuotaManager {
         stats_manager.get_stats(username).map(|s| s.format_stats())
     }
 }
+
+/// Secondes écoulées depuis l'epoch Unix
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_group_quota_resolution() {
+        let config = QuotaConfig {
+            default_quota: 1000,
+            ..QuotaConfig::default()
+        };
+        let mut group_config = GroupQuotaConfig::new();
+        group_config.set_group_quota("staff", 600);
+        group_config.set_group_ratio("staff", "1:3");
+        group_config.set_group_total_quota("staff", 800);
+        group_config.set_primary_group("alice", "staff");
+        group_config.set_primary_group("bob", "staff");
+        let mut user_config = UserQuotaConfig::new();
+        user_config.set_user_quota("bob", 2000);
+
+        let manager = QuotaManager::new(config, group_config, user_config);
+        let base_dir = PathBuf::from("/");
+
+        // Utilisateur, puis groupe principal, puis défaut
+        let quota = |user| manager.get_or_create_user_quota(user, base_dir.clone());
+        assert_eq!(quota("bob").await.unwrap().max_bytes, 2000);
+        assert_eq!(quota("alice").await.unwrap().max_bytes, 600);
+        assert_eq!(quota("carol").await.unwrap().max_bytes, 1000);
+        let ratio = manager.get_or_create_user_ratio("alice").await.unwrap();
+        assert_eq!(ratio.configured_ratio_string(), "1:3");
+
+        // Le quota total du groupe compte les octets de tous ses membres
+        manager.record_upload("bob", 500).await.unwrap();
+        assert!(manager
+            .check_upload("alice", base_dir.clone(), 300)
+            .await
+            .is_ok());
+        assert!(matches!(
+            manager.check_upload("alice", base_dir.clone(), 301).await,
+            Err(QuotaError::GroupQuotaExceeded(_))
+        ));
+        assert!(manager.check_upload("carol", base_dir, 900).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_quota_credit() {
+        let manager = QuotaManager::new(
+            QuotaConfig::default(),
+            GroupQuotaConfig::new(),
+            UserQuotaConfig::new(),
+        );
+        let site = PathBuf::from("/site");
+        manager
+            .get_or_create_user_quota("alice", site.clone())
+            .await
+            .unwrap();
+        manager.record_upload("alice", 1000).await.unwrap();
+
+        manager.record_delete("alice", 400).await.unwrap();
+        let used = || async {
+            manager
+                .cache
+                .get_user_quota("alice")
+                .await
+                .unwrap()
+                .used_bytes
+        };
+        assert_eq!(used().await, 600);
+
+        // Seuls les déplacements hors de l'arborescence du quota comptent
+        let (inside, outside) = (site.join("a"), PathBuf::from("/archive/a"));
+        manager
+            .record_move("alice", 100, &inside, &site.join("b"))
+            .await
+            .unwrap();
+        assert_eq!(used().await, 600);
+        manager
+            .record_move("alice", 100, &inside, &outside)
+            .await
+            .unwrap();
+        assert_eq!(used().await, 500);
+        manager
+            .record_move("alice", 100, &outside, &inside)
+            .await
+            .unwrap();
+        assert_eq!(used().await, 600);
+
+        manager.record_delete("alice", 5000).await.unwrap();
+        assert_eq!(used().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_soft_quota_grace() {
+        let config = QuotaConfig {
+            default_quota: 1000,
+            default_soft_quota: 500,
+            grace_period: 3600,
+            ..QuotaConfig::default()
+        };
+        let manager = QuotaManager::new(config, GroupQuotaConfig::new(), UserQuotaConfig::new());
+        let base_dir = PathBuf::from("/");
+
+        // Au-delà du quota souple, le délai de grâce commence
+        manager.record_upload("alice", 600).await.unwrap();
+        assert!(manager.soft_quota_warning("alice").await.is_some());
+        assert!(manager
+            .check_upload("alice", base_dir.clone(), 300)
+            .await
+            .is_ok());
+
+        // Une fois écoulé, le quota souple s'applique
+        let mut quota = manager.cache.get_user_quota("alice").await.unwrap();
+        quota.grace_started = Some(unix_now() - 7200);
+        manager
+            .cache
+            .update_user_quota("alice", quota)
+            .await
+            .unwrap();
+        assert!(matches!(
+            manager.check_upload("alice", base_dir.clone(), 1).await,
+            Err(QuotaError::GracePeriodExpired(_))
+        ));
+        assert_eq!(
+            manager
+                .upload_allowance("alice", base_dir.clone())
+                .await
+                .unwrap(),
+            Some(0)
+        );
+
+        // Repasser sous le quota souple referme le délai
+        manager.record_delete("alice", 200).await.unwrap();
+        assert!(manager.soft_quota_warning("alice").await.is_none());
+        assert!(manager.check_upload("alice", base_dir, 100).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_upload_reservations() {
+        let config = QuotaConfig {
+            default_quota: 3 * RESERVE_STEP,
+            ..QuotaConfig::default()
+        };
+        let manager = QuotaManager::new(config, GroupQuotaConfig::new(), UserQuotaConfig::new());
+        let base_dir = PathBuf::from("/");
+
+        // Deux uploads parallèles se partagent ce qui reste du quota
+        let mut first = manager.start_upload("alice");
+        let mut second = manager.start_upload("alice");
+        manager
+            .reserve_upload(&mut first, base_dir.clone(), 2 * RESERVE_STEP)
+            .await
+            .unwrap();
+        manager
+            .reserve_upload(&mut second, base_dir.clone(), 2 * RESERVE_STEP)
+            .await
+            .unwrap();
+        assert_eq!(first.granted(), Some(2 * RESERVE_STEP));
+        assert_eq!(second.granted(), Some(RESERVE_STEP));
+
+        // L'upload terminé est compté, même au-delà du quota
+        manager
+            .record_upload("alice", 4 * RESERVE_STEP)
+            .await
+            .unwrap();
+        drop(first);
+        drop(second);
+        let quota = manager.cache.get_user_quota("alice").await.unwrap();
+        assert_eq!(quota.used_bytes, 4 * RESERVE_STEP);
+        assert_eq!(
+            manager.upload_allowance("alice", base_dir).await.unwrap(),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ratio_credits() {
+        let mut user_config = UserQuotaConfig::new();
+        user_config.set_user_ratio("alice", "1:3");
+        user_config.set_user_ratio("leech", "unlimited");
+        let manager =
+            QuotaManager::new(QuotaConfig::default(), GroupQuotaConfig::new(), user_config);
+
+        // Un upload rapporte octets × ratio, un téléchargement coûte ses octets
+        let alice = manager.user_ratio("alice").await.unwrap().unwrap();
+        assert_eq!(alice.earned_credits(1000), 3000);
+        assert!(alice.check_download(3000, 3000).is_ok());
+        assert!(matches!(
+            alice.check_download(3000, 3001),
+            Err(QuotaError::RatioLimitReached(_))
+        ));
+        assert_eq!(alice.configured_ratio_string(), "1:3");
+
+        // Un leech ne gagne ni ne consomme de crédits
+        let leech = manager.user_ratio("leech").await.unwrap().unwrap();
+        assert_eq!(leech.earned_credits(1000), 0);
+        assert!(leech.check_download(0, 1000).is_ok());
+        assert_eq!(leech.configured_ratio_string(), "Unlimited");
+
+        // Sans ratios, pas de crédits
+        let config = QuotaConfig {
+            enable_ratio: false,
+            ..QuotaConfig::default()
+        };
+        let manager = QuotaManager::new(config, GroupQuotaConfig::new(), UserQuotaConfig::new());
+        assert!(manager.user_ratio("alice").await.unwrap().is_none());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod cache; // Ajout du module de cache
 pub mod config;
+pub mod dir_quota;
 pub mod disk_io; // Ajout du module d'E/S disque optimisée
 pub mod error;
 pub mod manager; // Ajout du module de gestion des quotas
@@ -21,6 +21,16 @@ pub struct UserQuota {
 
     /// Indicateur si le quota est illimité
     pub is_unlimited: bool,
+
+    /// Quota souple en octets (0 = aucun) : le dépasser ouvre un délai de
+    /// grâce, après lequel il devient la limite
+    #[serde(default)]
+    pub soft_bytes: u64,
+
+    /// Début du délai de grâce (secondes depuis l'epoch Unix), tant que le
+    /// quota souple est dépassé
+    #[serde(default)]
+    pub grace_started: Option<u64>,
 }
 
 impl UserQuota {
@@ -33,6 +43,8 @@ impl UserQuota {
             used_bytes: 0,
             base_dir,
             is_unlimited,
+            soft_bytes: 0,
+            grace_started: None,
         }
     }
 
@@ -44,6 +56,8 @@ impl UserQuota {
             used_bytes: 0,
             base_dir,
             is_unlimited: true,
+            soft_bytes: 0,
+            grace_started: None,
         }
     }
 
@@ -60,11 +74,10 @@ impl UserQuota {
         }
     }
 
-    /// Met à jour les octets utilisés
-    pub fn update_used_bytes(&mut self, bytes: u64) -> Result<(), QuotaError> {
-        self.check_quota(bytes)?;
-        self.used_bytes += bytes;
-        Ok(())
+    /// Ajoute des octets utilisés, même au-delà du quota : il est vérifié avant
+    /// et pendant l'upload
+    pub fn update_used_bytes(&mut self, bytes: u64) {
+        self.used_bytes = self.used_bytes.saturating_add(bytes);
     }
 
     /// Réduit les octets utilisés (pour les suppressions)
@@ -76,6 +89,44 @@ impl UserQuota {
         }
     }
 
+    /// Vérifie si le quota souple est dépassé
+    pub fn is_over_soft(&self) -> bool {
+        !self.is_unlimited && self.soft_bytes > 0 && self.used_bytes > self.soft_bytes
+    }
+
+    /// Ouvre le délai de grâce quand le quota souple vient d'être dépassé, le
+    /// referme quand l'utilisateur repasse dessous
+    pub fn update_grace(&mut self, now: u64) {
+        if !self.is_over_soft() {
+            self.grace_started = None;
+        } else if self.grace_started.is_none() {
+            self.grace_started = Some(now);
+        }
+    }
+
+    /// Renvoie la fin du délai de grâce, si le quota souple est dépassé
+    pub fn grace_ends(&self, grace_period: u64) -> Option<u64> {
+        self.grace_started
+            .filter(|_| self.is_over_soft())
+            .map(|started| started.saturating_add(grace_period))
+    }
+
+    /// Vérifie un upload une fois le délai de grâce écoulé : le quota souple
+    /// s'applique alors comme un quota strict
+    pub fn check_grace(
+        &self,
+        additional_bytes: u64,
+        now: u64,
+        grace_period: u64,
+    ) -> Result<(), QuotaError> {
+        match self.grace_ends(grace_period) {
+            Some(ends) if now >= ends && self.used_bytes + additional_bytes > self.soft_bytes => {
+                Err(QuotaError::GracePeriodExpired(self.username.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Retourne le pourcentage d'utilisation
     pub fn usage_percentage(&self) -> f64 {
         if self.is_unlimited || self.max_bytes == 0 {
@@ -92,12 +143,17 @@ impl UserQuota {
         } else {
             let used_mb = self.used_bytes as f64 / (1024.0 * 1024.0);
             let max_mb = self.max_bytes as f64 / (1024.0 * 1024.0);
-            format!(
+            let mut quota = format!(
                 "{:.2}MB / {:.2}MB ({:.1}%)",
                 used_mb,
                 max_mb,
                 self.usage_percentage()
-            )
+            );
+            if self.soft_bytes > 0 {
+                let soft_mb = self.soft_bytes as f64 / (1024.0 * 1024.0);
+                quota.push_str(&format!(", soft limit {:.2}MB", soft_mb));
+            }
+            quota
         }
     }
 }
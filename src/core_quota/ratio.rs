@@ -71,35 +71,34 @@ impl UserRatio {
         }
     }
 
-    /// Vérifie si le ratio permet un téléchargement
-    /// Retourne le crédit disponible en octets
-    pub fn check_download(&self, download_bytes: u64) -> Result<u64, QuotaError> {
+    /// Crédits gagnés par un upload, comme glFTPd : octets × ratio.
+    /// Un leech (ratio illimité) n'en gagne pas puisqu'il n'en consomme pas.
+    pub fn earned_credits(&self, uploaded_bytes: u64) -> u64 {
         if self.is_unlimited {
-            return Ok(download_bytes);
+            return 0;
         }
+        let earned =
+            uploaded_bytes as u128 * self.download_ratio as u128 / self.upload_ratio.max(1) as u128;
+        earned.min(u64::MAX as u128) as u64
+    }
 
-        // Calcul du crédit disponible selon la formule glFTPd:
-        // crédit = (uploaded_bytes / upload_ratio) - downloaded_bytes
-        let available_credit = (self.uploaded_bytes as f64 / self.upload_ratio as f64) as u64;
-
-        if available_credit >= self.downloaded_bytes + download_bytes {
-            Ok(download_bytes)
-        } else {
-            let remaining = available_credit.saturating_sub(self.downloaded_bytes);
-            Err(QuotaError::RatioLimitReached(format!(
-                "{}: Need {} more upload credit for {} download",
-                self.username,
-                download_bytes.saturating_sub(remaining),
-                download_bytes
-            )))
+    /// Vérifie que les crédits de l'utilisateur, en octets, couvrent un
+    /// téléchargement. Un leech télécharge sans limite.
+    pub fn check_download(&self, credits: u64, download_bytes: u64) -> Result<(), QuotaError> {
+        if self.is_unlimited || credits >= download_bytes {
+            return Ok(());
         }
+        Err(QuotaError::RatioLimitReached(format!(
+            "{}: Need {} more bytes of credits for {} download",
+            self.username,
+            download_bytes - credits,
+            download_bytes
+        )))
     }
 
     /// Met à jour les octets téléchargés
-    pub fn update_downloaded(&mut self, bytes: u64) -> Result<(), QuotaError> {
-        self.check_download(bytes)?;
+    pub fn update_downloaded(&mut self, bytes: u64) {
         self.downloaded_bytes += bytes;
-        Ok(())
     }
 
     /// Met à jour les octets uploadés
@@ -2,67 +2,46 @@
 // Inspiré du format glFTPd
 
 use crate::core_quota::error::QuotaError;
+use crate::core_users::{UserFile, UserStore};
 use log::warn;
 use std::collections::HashMap;
-use std::path::Path;
 
-/// Parse un fichier utilisateur et extrait les informations de quota/ratio
-pub fn parse_user_file(file_path: &Path) -> Result<(Option<u64>, Option<String>), QuotaError> {
-    let content = std::fs::read_to_string(file_path)
-        .map_err(|e| QuotaError::QuotaReadError(e.to_string()))?;
-
-    let mut quota = None;
-    let mut ratio = None;
-
-    for line in content.lines() {
-        // Ignorer les commentaires et les lignes vides
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
+/// Extrait le quota et le ratio d'un fichier utilisateur
+///
+/// Le quota vient d'une ligne `QUOTA <taille> [souple]` (ex: `QUOTA 10GB 8GB`,
+/// `QUOTA unlimited`), le ratio de la ligne `RATIO` de glFTPd : `RATIO 3` donne
+/// 1:3, `RATIO 0` est illimité (leech) et `RATIO -1` garde le ratio du groupe
+/// principal ou, à défaut, le ratio par défaut.
+pub fn parse_user_file(user: &UserFile) -> Result<(Option<u64>, Option<String>), QuotaError> {
+    let quota = match user
+        .get("QUOTA")
+        .and_then(|value| value.split_whitespace().next())
+    {
+        Some(value) if value.eq_ignore_ascii_case("unlimited") => Some(0), // 0 signifie illimité
+        Some(value) => Some(parse_size(value)?),
+        None => None,
+    };
 
-        // Parser les lignes au format key=value
-        if let Some((key, value)) = line.split_once('=') {
-            let key = key.trim().to_lowercase();
-            let value = value.trim();
-
-            match key.as_str() {
-                "quota" => {
-                    if value.to_lowercase() == "unlimited" {
-                        quota = Some(0); // 0 signifie illimité
-                    } else {
-                        // Parser le quota en octets
-                        // Supporter les suffixes comme GB, MB, etc.
-                        quota = Some(parse_size(value)?);
-                    }
-                }
-                "ratio" => {
-                    if value.to_lowercase() == "unlimited" {
-                        ratio = Some("0:0".to_string()); // 0:0 signifie illimité
-                    } else {
-                        // Valider le format du ratio
-                        if value.contains(':') {
-                            ratio = Some(value.to_string());
-                        } else {
-                            return Err(QuotaError::InvalidRatioConfig(format!(
-                                "Invalid ratio format: {}",
-                                value
-                            )));
-                        }
-                    }
-                }
-                _ => {
-                    // Ignorer les autres clés
-                }
-            }
-        }
-    }
+    let ratio = match user.ratio.first() {
+        Some(0) => Some("0:0".to_string()), // 0:0 signifie illimité
+        Some(ratio) if *ratio > 0 => Some(format!("1:{}", ratio)),
+        _ => None,
+    };
 
     Ok((quota, ratio))
 }
 
+/// Extrait le quota souple d'un fichier utilisateur, la seconde taille de la
+/// ligne `QUOTA` (0 = aucun)
+pub fn parse_soft_quota(user: &UserFile) -> Result<Option<u64>, QuotaError> {
+    user.get("QUOTA")
+        .and_then(|value| value.split_whitespace().nth(1))
+        .map(parse_size)
+        .transpose()
+}
+
 /// Parse une taille avec suffixe (ex: 10GB, 5MB, etc.)
-fn parse_size(size_str: &str) -> Result<u64, QuotaError> {
+pub fn parse_size(size_str: &str) -> Result<u64, QuotaError> {
     let size_str = size_str.trim();
 
     // Extraire le nombre et le suffixe
@@ -99,32 +78,34 @@ fn parse_size(size_str: &str) -> Result<u64, QuotaError> {
     Ok(num * multiplier)
 }
 
-/// Charge les configurations de quota/ratio pour tous les utilisateurs
-pub fn load_user_quota_configs(
-    users_dir: &Path,
-) -> Result<HashMap<String, (Option<u64>, Option<String>)>, QuotaError> {
+/// Charge les configurations de quota/ratio pour tous les utilisateurs :
+/// quota, quota souple et ratio
+pub async fn load_user_quota_configs(
+    users: &UserStore,
+) -> Result<HashMap<String, (Option<u64>, Option<u64>, Option<String>)>, QuotaError> {
     let mut configs = HashMap::new();
 
-    if !users_dir.exists() {
-        return Ok(configs);
-    }
-
-    for entry in
-        std::fs::read_dir(users_dir).map_err(|e| QuotaError::QuotaReadError(e.to_string()))?
-    {
-        let entry = entry.map_err(|e| QuotaError::QuotaReadError(e.to_string()))?;
-        let path = entry.path();
-
-        if path.extension().and_then(|s| s.to_str()) == Some("user") {
-            if let Some(username) = path.file_stem().and_then(|s| s.to_str()) {
-                match parse_user_file(&path) {
-                    Ok((quota, ratio)) => {
-                        configs.insert(username.to_string(), (quota, ratio));
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse user file {}: {}", path.display(), e);
-                    }
-                }
+    let usernames = users
+        .usernames()
+        .await
+        .map_err(|e| QuotaError::QuotaReadError(e.to_string()))?;
+    for username in usernames {
+        let user = match users.get(&username).await {
+            Ok(Some(user)) => user,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Failed to read user file of {}: {}", username, e);
+                continue;
+            }
+        };
+        match parse_user_file(&user).and_then(|(quota, ratio)| {
+            parse_soft_quota(&user).map(|soft_quota| (quota, soft_quota, ratio))
+        }) {
+            Ok(config) => {
+                configs.insert(username, config);
+            }
+            Err(e) => {
+                warn!("Failed to parse user file of {}: {}", username, e);
             }
         }
     }
@@ -135,8 +116,6 @@ pub fn load_user_quota_configs(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
 
     #[test]
     fn test_parse_size() {
@@ -151,13 +130,19 @@ mod tests {
 
     #[test]
     fn test_parse_user_file() {
-        let mut file = NamedTempFile::new().unwrap();
-        writeln!(file, "username=testuser").unwrap();
-        writeln!(file, "quota=10GB").unwrap();
-        writeln!(file, "ratio=1:2").unwrap();
-
-        let (quota, ratio) = parse_user_file(file.path()).unwrap();
+        let user = UserFile::parse("testuser", "QUOTA 10GB 8GB\nRATIO 2 -1\n");
+        let (quota, ratio) = parse_user_file(&user).unwrap();
         assert_eq!(quota, Some(10 * 1024 * 1024 * 1024));
         assert_eq!(ratio, Some("1:2".to_string()));
+        assert_eq!(
+            parse_soft_quota(&user).unwrap(),
+            Some(8 * 1024 * 1024 * 1024)
+        );
+
+        let leech = UserFile::parse("leech", "RATIO 0\n");
+        assert_eq!(
+            parse_user_file(&leech).unwrap(),
+            (None, Some("0:0".to_string()))
+        );
     }
 }
@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UserError {
+    #[error("User not found: {0}")]
+    NotFound(String),
+
+    #[error("User already exists: {0}")]
+    AlreadyExists(String),
+
+    #[error("Failed to read userfile {0}: {1}")]
+    ReadError(String, std::io::Error),
+
+    #[error("Failed to write userfile {0}: {1}")]
+    WriteError(String, std::io::Error),
+}
+
+impl UserError {
+    pub fn to_ftp_response(&self) -> String {
+        match self {
+            UserError::NotFound(_) => "550 User not found.".to_string(),
+            UserError::AlreadyExists(_) => "550 User already exists.".to_string(),
+            _ => "451 Requested action aborted. Local error in processing.".to_string(),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+// The users of the site, stored in glftpd userfiles
+pub mod error;
+pub mod owners;
+pub mod store;
+pub mod userfile;
+
+pub use owners::FileOwners;
+pub use store::UserStore;
+pub use userfile::UserFile;
@@ -0,0 +1,144 @@
+use crate::helpers::write_file_atomic;
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Remembers who uploaded each file and created each directory, as glftpd does
+/// with the file owner. Used by the deleteown and renameown rules.
+///
+/// Entries are keyed on the path on disk. Changes only mark the owners dirty,
+/// `flush` persists them to the owner file, periodically and at shutdown.
+pub struct FileOwners {
+    owners: Mutex<HashMap<PathBuf, String>>,
+    owner_file: PathBuf,
+    /// Changed since the last save
+    dirty: AtomicBool,
+    /// Serializes the writes of the owner file
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileOwners {
+    pub fn new(owner_file: PathBuf) -> Self {
+        Self {
+            owners: Mutex::new(HashMap::new()),
+            owner_file,
+            dirty: AtomicBool::new(false),
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Loads the owners saved by a previous run.
+    pub async fn load(&self) {
+        let content = match tokio::fs::read_to_string(&self.owner_file).await {
+            Ok(content) => content,
+            Err(e) => {
+                info!("No owner file loaded from {:?}: {}", self.owner_file, e);
+                return;
+            }
+        };
+
+        match serde_json::from_str::<HashMap<PathBuf, String>>(&content) {
+            Ok(owners) => {
+                info!("Loaded the owners of {} file(s)", owners.len());
+                *self.owners.lock().unwrap() = owners;
+            }
+            Err(e) => error!("Failed to parse owner file {:?}: {}", self.owner_file, e),
+        }
+    }
+
+    /// Writes the owners to the owner file, if they changed since the last save.
+    pub async fn flush(&self) {
+        // Taken before the snapshot, so a later save never writes older owners
+        let _saving = self.save_lock.lock().await;
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let content = serde_json::to_string_pretty(&*self.owners.lock().unwrap());
+        let result = match content {
+            Ok(content) => write_file_atomic(&self.owner_file, content.as_bytes()).await,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        if let Err(e) = result {
+            error!("Failed to save owner file {:?}: {}", self.owner_file, e);
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns who owns the file or directory at `path`, if known.
+    pub fn owner_of(&self, path: &Path) -> Option<String> {
+        self.owners.lock().unwrap().get(path).cloned()
+    }
+
+    /// Returns the files and directories at or below `path` with their owners.
+    pub fn owned_below(&self, path: &Path) -> Vec<(PathBuf, String)> {
+        self.owners
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(owned, _)| owned.starts_with(path))
+            .map(|(owned, owner)| (owned.clone(), owner.clone()))
+            .collect()
+    }
+
+    /// Adds up the size on disk of the files at or below `path`, by owner.
+    pub async fn usage_below(&self, path: &Path) -> HashMap<String, u64> {
+        let mut usage = HashMap::new();
+        for (owned, owner) in self.owned_below(path) {
+            match tokio::fs::metadata(&owned).await {
+                Ok(metadata) if metadata.is_file() => {
+                    *usage.entry(owner).or_insert(0) += metadata.len();
+                }
+                _ => {}
+            }
+        }
+        usage
+    }
+
+    /// Records `username` as the owner of `path`.
+    pub fn set_owner(&self, path: &Path, username: &str) {
+        self.owners
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), username.to_string());
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Forgets the owner of `path` and of everything below it.
+    pub fn remove(&self, path: &Path) {
+        let removed = {
+            let mut owners = self.owners.lock().unwrap();
+            let before = owners.len();
+            owners.retain(|owned, _| !owned.starts_with(path));
+            owners.len() != before
+        };
+        if removed {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Moves the owners of `from`, and of everything below it, to `to`.
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let moved = {
+            let mut owners = self.owners.lock().unwrap();
+            let entries: Vec<(PathBuf, String)> = owners
+                .iter()
+                .filter(|(owned, _)| owned.starts_with(from))
+                .map(|(owned, owner)| (owned.clone(), owner.clone()))
+                .collect();
+            for (owned, owner) in &entries {
+                owners.remove(owned);
+                let moved_to = match owned.strip_prefix(from) {
+                    Ok(relative) if !relative.as_os_str().is_empty() => to.join(relative),
+                    _ => to.to_path_buf(),
+                };
+                owners.insert(moved_to, owner.clone());
+            }
+            !entries.is_empty()
+        };
+        if moved {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::core_users::error::UserError;
+use crate::core_users::userfile::UserFile;
+use crate::helpers::write_file_atomic;
+use crate::Config;
+use log::info;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Serializes the read-modify-write cycles of the userfiles.
+static USERFILE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Template of new userfiles, in the users directory.
+const DEFAULT_USERFILE: &str = "default";
+
+/// The userfiles of `ftp-data/users`, kept in memory until they change on disk.
+///
+/// Every reader of user data goes through the store. Writes replace the file
+/// atomically, so a crash never leaves a truncated userfile behind.
+pub struct UserStore {
+    users_dir: PathBuf,
+    cache: Mutex<HashMap<String, (SystemTime, Arc<UserFile>)>>,
+}
+
+impl UserStore {
+    pub fn new(users_dir: PathBuf) -> Self {
+        Self {
+            users_dir,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Opens the users directory of the configured chroot.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(PathBuf::from(&config.server.chroot_dir).join("ftp-data/users"))
+    }
+
+    fn path(&self, username: &str) -> PathBuf {
+        self.users_dir.join(format!("{}.user", username))
+    }
+
+    /// Rejects names that would point outside of the users directory.
+    fn is_valid_name(username: &str) -> bool {
+        !username.is_empty()
+            && !username.starts_with('.')
+            && !username.contains(['/', '\\'])
+            && username != DEFAULT_USERFILE
+    }
+
+    async fn read(&self, username: &str) -> Result<Option<(SystemTime, UserFile)>, UserError> {
+        let path = self.path(username);
+        let read_error = |e| UserError::ReadError(path.display().to_string(), e);
+
+        let modified = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata.modified().map_err(read_error)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(read_error(e)),
+        };
+        let content = tokio::fs::read_to_string(&path).await.map_err(read_error)?;
+        Ok(Some((modified, UserFile::parse(username, &content))))
+    }
+
+    async fn write(&self, user: UserFile) -> Result<Arc<UserFile>, UserError> {
+        let path = self.path(&user.username);
+        let write_error = |e| UserError::WriteError(path.display().to_string(), e);
+
+        write_file_atomic(&path, user.serialize().as_bytes())
+            .await
+            .map_err(write_error)?;
+        let modified = tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .map_err(write_error)?;
+
+        let user = Arc::new(user);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(user.username.clone(), (modified, Arc::clone(&user)));
+        Ok(user)
+    }
+
+    /// Returns the userfile of `username`, `None` if the user does not exist.
+    pub async fn get(&self, username: &str) -> Result<Option<Arc<UserFile>>, UserError> {
+        if !Self::is_valid_name(username) {
+            return Ok(None);
+        }
+
+        let modified = match tokio::fs::metadata(self.path(username)).await {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(_) => None,
+        };
+        if let (Some(modified), Some((cached_at, user))) =
+            (modified, self.cache.lock().unwrap().get(username))
+        {
+            if *cached_at == modified {
+                return Ok(Some(Arc::clone(user)));
+            }
+        }
+
+        match self.read(username).await? {
+            Some((modified, user)) => {
+                let user = Arc::new(user);
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .insert(username.to_string(), (modified, Arc::clone(&user)));
+                Ok(Some(user))
+            }
+            None => {
+                self.cache.lock().unwrap().remove(username);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns whether `username` has a userfile.
+    pub async fn exists(&self, username: &str) -> bool {
+        matches!(self.get(username).await, Ok(Some(_)))
+    }
+
+    /// Returns the names of every user, sorted.
+    pub async fn usernames(&self) -> Result<Vec<String>, UserError> {
+        let read_error = |e| UserError::ReadError(self.users_dir.display().to_string(), e);
+        let mut entries = tokio::fs::read_dir(&self.users_dir)
+            .await
+            .map_err(read_error)?;
+
+        let mut usernames = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(read_error)? {
+            let file_name = entry.file_name();
+            let Some(username) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".user"))
+            else {
+                continue;
+            };
+            if Self::is_valid_name(username) {
+                usernames.push(username.to_string());
+            }
+        }
+        usernames.sort();
+        Ok(usernames)
+    }
+
+    /// Returns the userfile new users start from, `default.user`.
+    pub async fn template(&self, username: &str) -> Result<UserFile, UserError> {
+        let path = self.path(DEFAULT_USERFILE);
+        let content = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| UserError::ReadError(path.display().to_string(), e))?;
+        Ok(UserFile::parse(username, &content))
+    }
+
+    /// Writes the userfile of a new user.
+    pub async fn create(&self, user: UserFile) -> Result<Arc<UserFile>, UserError> {
+        if !Self::is_valid_name(&user.username) {
+            return Err(UserError::NotFound(user.username));
+        }
+        let _lock = USERFILE_LOCK.lock().await;
+        if self.read(&user.username).await?.is_some() {
+            return Err(UserError::AlreadyExists(user.username));
+        }
+        info!("Creating userfile of {}", user.username);
+        self.write(user).await
+    }
+
+    /// Changes the userfile of `username` with `update` and writes it back.
+    ///
+    /// The file is read again from disk under a lock, so concurrent updates and
+    /// changes made by hand are not lost.
+    ///
+    /// # Returns
+    ///
+    /// What `update` returned.
+    pub async fn update<F, T>(&self, username: &str, update: F) -> Result<T, UserError>
+    where
+        F: FnOnce(&mut UserFile) -> T,
+    {
+        if !Self::is_valid_name(username) {
+            return Err(UserError::NotFound(username.to_string()));
+        }
+        let _lock = USERFILE_LOCK.lock().await;
+        let Some((_, mut user)) = self.read(username).await? else {
+            return Err(UserError::NotFound(username.to_string()));
+        };
+        let result = update(&mut user);
+        self.write(user).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DELETED;
+
+    #[tokio::test]
+    async fn test_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("default.user"),
+            "# template\nFLAGS 3\nTAGLINE No Tagline Set\nRATIO 3\n",
+        )
+        .unwrap();
+        let store = UserStore::new(dir.path().to_path_buf());
+
+        let mut user = store.template("alice").await.unwrap();
+        user.ips.push("*@127.0.0.1".to_string());
+        store.create(user.clone()).await.unwrap();
+        assert!(matches!(
+            store.create(user).await,
+            Err(UserError::AlreadyExists(_))
+        ));
+
+        store
+            .update("alice", |user| user.add_flag(DELETED))
+            .await
+            .unwrap();
+        let alice = store.get("alice").await.unwrap().unwrap();
+        assert_eq!(alice.flags, "36");
+        assert_eq!(alice.ips, vec!["*@127.0.0.1"]);
+        assert_eq!(alice.ratio, vec![3]);
+
+        assert_eq!(store.usernames().await.unwrap(), vec!["alice"]);
+        assert!(store.get("../default").await.unwrap().is_none());
+        assert!(matches!(
+            store.update("bob", |_| ()).await,
+            Err(UserError::NotFound(_))
+        ));
+    }
+}
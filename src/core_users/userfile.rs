@@ -0,0 +1,455 @@
+use crate::core_ftpcommand::site::helper::parse_flags;
+use crate::session::LoginLimits;
+use std::fmt::Write;
+
+/// `GENERAL <weekly allotment> <idle time> <max download speed> <max upload speed>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct General {
+    /// `section,kilobytes`, kept as written
+    pub weekly_allotment: String,
+    /// Seconds, -1 = the server default, 0 = never idle out
+    pub idle_time: i64,
+    /// KB/s, 0 = unlimited
+    pub max_download_speed: i64,
+    /// KB/s, 0 = unlimited
+    pub max_upload_speed: i64,
+}
+
+impl Default for General {
+    fn default() -> Self {
+        Self {
+            weekly_allotment: "0,0".to_string(),
+            idle_time: -1,
+            max_download_speed: 0,
+            max_upload_speed: 0,
+        }
+    }
+}
+
+/// `LOGINS <max logins> <max from same IP> <max sim downloads> <max sim uploads>`,
+/// 0 or less = unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Logins {
+    pub max_logins: i64,
+    pub max_logins_per_ip: i64,
+    pub max_sim_downloads: i64,
+    pub max_sim_uploads: i64,
+}
+
+/// One section of an `ALLUP` .. `MONTHDN` line: `<files> <kilobytes> <seconds>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub files: u64,
+    pub kilobytes: u64,
+    pub seconds: u64,
+}
+
+/// `TIME <login times> <last on> <time limit> <time on today>`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeInfo {
+    pub login_times: u64,
+    /// Unix timestamp of the last login
+    pub last_on: u64,
+    /// Minutes per day, 0 = unlimited
+    pub time_limit: i64,
+    pub time_on_today: u64,
+}
+
+/// `NUKE <last nuked> <times nuked> <total MB nuked>`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NukeInfo {
+    pub last_nuked: u64,
+    pub times_nuked: u64,
+    pub megabytes_nuked: u64,
+}
+
+/// A `GROUP <name> [gadmin]` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserGroup {
+    pub name: String,
+    /// The user is a group admin (GADMIN) of this group
+    pub gadmin: bool,
+}
+
+/// A glftpd userfile (`ftp-data/users/<user>.user`).
+///
+/// Lines the parser does not know, e.g. `ADDED` or `EXPIRES`, are kept in
+/// `extra` so that they survive a rewrite. Comments are dropped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserFile {
+    pub username: String,
+    pub general: General,
+    pub logins: Logins,
+    /// glftpd flag characters, see `parse_flags`
+    pub flags: String,
+    pub tagline: String,
+    /// Start-up directory
+    pub dir: String,
+    /// Upload/download ratio per section, 0 = leech
+    pub ratio: Vec<i64>,
+    /// Credits in kilobytes per section
+    pub credits: Vec<i64>,
+    pub allup: Vec<TransferStats>,
+    pub alldn: Vec<TransferStats>,
+    pub wkup: Vec<TransferStats>,
+    pub wkdn: Vec<TransferStats>,
+    pub dayup: Vec<TransferStats>,
+    pub daydn: Vec<TransferStats>,
+    pub monthup: Vec<TransferStats>,
+    pub monthdn: Vec<TransferStats>,
+    pub time: TimeInfo,
+    pub nuke: NukeInfo,
+    /// `ident@host` masks allowed to log in
+    pub ips: Vec<String>,
+    pub groups: Vec<UserGroup>,
+    /// Unknown lines as (key, rest of the line)
+    pub extra: Vec<(String, String)>,
+}
+
+fn number<T: std::str::FromStr + Default>(value: Option<&&str>) -> T {
+    value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default()
+}
+
+fn parse_stats(values: &[&str]) -> Vec<TransferStats> {
+    values
+        .chunks(3)
+        .map(|chunk| TransferStats {
+            files: number(chunk.first()),
+            kilobytes: number(chunk.get(1)),
+            seconds: number(chunk.get(2)),
+        })
+        .collect()
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the glftpd character of a flag value, the inverse of `parse_flags`.
+pub fn flag_char(flag: u8) -> Option<char> {
+    match flag {
+        1..=9 => Some((b'0' + flag) as char),
+        10..=35 => Some((b'A' + flag - 10) as char),
+        _ => None,
+    }
+}
+
+impl UserFile {
+    /// Parses the content of a userfile.
+    pub fn parse(username: &str, content: &str) -> Self {
+        let mut user = UserFile {
+            username: username.to_string(),
+            ..Default::default()
+        };
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let values: Vec<&str> = rest.split_whitespace().collect();
+
+            match key {
+                "GENERAL" => {
+                    user.general = General {
+                        weekly_allotment: values.first().unwrap_or(&"0,0").to_string(),
+                        idle_time: number(values.get(1)),
+                        max_download_speed: number(values.get(2)),
+                        max_upload_speed: number(values.get(3)),
+                    }
+                }
+                "LOGINS" => {
+                    user.logins = Logins {
+                        max_logins: number(values.first()),
+                        max_logins_per_ip: number(values.get(1)),
+                        max_sim_downloads: number(values.get(2)),
+                        max_sim_uploads: number(values.get(3)),
+                    }
+                }
+                "FLAGS" => user.flags = rest.to_string(),
+                "TAGLINE" => user.tagline = rest.to_string(),
+                "DIR" => user.dir = rest.to_string(),
+                "RATIO" => user.ratio = values.iter().map(|v| number(Some(v))).collect(),
+                "CREDITS" => user.credits = values.iter().map(|v| number(Some(v))).collect(),
+                "ALLUP" => user.allup = parse_stats(&values),
+                "ALLDN" => user.alldn = parse_stats(&values),
+                "WKUP" => user.wkup = parse_stats(&values),
+                "WKDN" => user.wkdn = parse_stats(&values),
+                "DAYUP" => user.dayup = parse_stats(&values),
+                "DAYDN" => user.daydn = parse_stats(&values),
+                "MONTHUP" => user.monthup = parse_stats(&values),
+                "MONTHDN" => user.monthdn = parse_stats(&values),
+                "TIME" => {
+                    user.time = TimeInfo {
+                        login_times: number(values.first()),
+                        last_on: number(values.get(1)),
+                        time_limit: number(values.get(2)),
+                        time_on_today: number(values.get(3)),
+                    }
+                }
+                "NUKE" => {
+                    user.nuke = NukeInfo {
+                        last_nuked: number(values.first()),
+                        times_nuked: number(values.get(1)),
+                        megabytes_nuked: number(values.get(2)),
+                    }
+                }
+                "IP" if !rest.is_empty() => user.ips.push(values[0].to_string()),
+                "GROUP" if !rest.is_empty() => user.groups.push(UserGroup {
+                    name: values[0].to_string(),
+                    gadmin: values.get(1) == Some(&"1"),
+                }),
+                // The name comes from the file name
+                "USER" => {}
+                _ => user.extra.push((key.to_string(), rest.to_string())),
+            }
+        }
+
+        // A missing stats line counts as zero in the first section
+        for stats in [
+            &mut user.allup,
+            &mut user.alldn,
+            &mut user.wkup,
+            &mut user.wkdn,
+            &mut user.dayup,
+            &mut user.daydn,
+            &mut user.monthup,
+            &mut user.monthdn,
+        ] {
+            if stats.is_empty() {
+                stats.push(TransferStats::default());
+            }
+        }
+
+        user
+    }
+
+    /// Formats the userfile, known fields first in the order of `default.user`.
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        let general = &self.general;
+        let logins = &self.logins;
+
+        // Writing to a String cannot fail
+        let _ = writeln!(out, "USER {}", self.username);
+        let _ = writeln!(
+            out,
+            "GENERAL {} {} {} {}",
+            general.weekly_allotment,
+            general.idle_time,
+            general.max_download_speed,
+            general.max_upload_speed
+        );
+        let _ = writeln!(
+            out,
+            "LOGINS {} {} {} {}",
+            logins.max_logins,
+            logins.max_logins_per_ip,
+            logins.max_sim_downloads,
+            logins.max_sim_uploads
+        );
+        let _ = writeln!(out, "FLAGS {}", self.flags);
+        let _ = writeln!(out, "TAGLINE {}", self.tagline);
+        let _ = writeln!(out, "DIR {}", self.dir);
+        if !self.ratio.is_empty() {
+            let _ = writeln!(out, "RATIO {}", join(&self.ratio));
+        }
+        if !self.credits.is_empty() {
+            let _ = writeln!(out, "CREDITS {}", join(&self.credits));
+        }
+        for (key, stats) in [
+            ("ALLUP", &self.allup),
+            ("ALLDN", &self.alldn),
+            ("WKUP", &self.wkup),
+            ("WKDN", &self.wkdn),
+            ("DAYUP", &self.dayup),
+            ("DAYDN", &self.daydn),
+            ("MONTHUP", &self.monthup),
+            ("MONTHDN", &self.monthdn),
+        ] {
+            let values: Vec<String> = stats
+                .iter()
+                .map(|s| format!("{} {} {}", s.files, s.kilobytes, s.seconds))
+                .collect();
+            let _ = writeln!(out, "{} {}", key, values.join(" "));
+        }
+        let _ = writeln!(
+            out,
+            "TIME {} {} {} {}",
+            self.time.login_times, self.time.last_on, self.time.time_limit, self.time.time_on_today
+        );
+        let _ = writeln!(
+            out,
+            "NUKE {} {} {}",
+            self.nuke.last_nuked, self.nuke.times_nuked, self.nuke.megabytes_nuked
+        );
+        for (key, value) in &self.extra {
+            let _ = writeln!(out, "{} {}", key, value);
+        }
+        for ip in &self.ips {
+            let _ = writeln!(out, "IP {}", ip);
+        }
+        for group in &self.groups {
+            let _ = writeln!(out, "GROUP {} {}", group.name, u8::from(group.gadmin));
+        }
+        out
+    }
+
+    /// Returns the value of the first unknown line with `key`, e.g. `ADDED`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.extra
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn flags(&self) -> Vec<u8> {
+        parse_flags(&self.flags)
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags().contains(&flag)
+    }
+
+    /// Adds a flag, returning `false` if the user already had it.
+    pub fn add_flag(&mut self, flag: u8) -> bool {
+        let mut flags = self.flags();
+        if flags.contains(&flag) {
+            return false;
+        }
+        flags.push(flag);
+        self.flags = flags.into_iter().filter_map(flag_char).collect();
+        true
+    }
+
+    /// Removes a flag, returning `false` if the user did not have it.
+    pub fn remove_flag(&mut self, flag: u8) -> bool {
+        let mut flags = self.flags();
+        if !flags.contains(&flag) {
+            return false;
+        }
+        flags.retain(|f| *f != flag);
+        self.flags = flags.into_iter().filter_map(flag_char).collect();
+        true
+    }
+
+    pub fn group_names(&self) -> Vec<String> {
+        self.groups.iter().map(|group| group.name.clone()).collect()
+    }
+
+    pub fn is_member_of(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g.name == group)
+    }
+
+    /// Returns whether the user is a group admin of `group`.
+    pub fn is_gadmin_of(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g.name == group && g.gadmin)
+    }
+
+    /// Returns the credits of the first section, in bytes.
+    pub fn credits_bytes(&self) -> u64 {
+        self.credits
+            .first()
+            .map_or(0, |kb| (*kb).max(0) as u64 * 1024)
+    }
+
+    /// Adds credits to the first section, or takes them when `bytes` is
+    /// negative, never going below 0. Credits are kept in kilobytes: gains
+    /// round down and costs round up, so small downloads are not free.
+    ///
+    /// # Returns
+    ///
+    /// The new credits, in bytes.
+    pub fn add_credits(&mut self, bytes: i64) -> u64 {
+        let kilobytes = if bytes < 0 {
+            -(bytes.unsigned_abs().div_ceil(1024) as i64)
+        } else {
+            bytes / 1024
+        };
+        match self.credits.first_mut() {
+            Some(first) => *first = first.saturating_add(kilobytes).max(0),
+            None => self.credits.push(kilobytes.max(0)),
+        }
+        self.credits_bytes()
+    }
+
+    /// Returns the limits of the LOGINS line.
+    pub fn login_limits(&self) -> LoginLimits {
+        LoginLimits::from_values(&[
+            self.logins.max_logins.to_string(),
+            self.logins.max_logins_per_ip.to_string(),
+            self.logins.max_sim_downloads.to_string(),
+            self.logins.max_sim_uploads.to_string(),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{DELETED, SITEOP};
+
+    const USERFILE: &str = "\
+## GENERAL: WKLY_ALLOTMENT, IDLE_TIME, MAX_DLSPEED, MAX_ULSPEED
+GENERAL 0,0 -1 120 60
+LOGINS 2 0 -1 -1
+FLAGS 13
+TAGLINE No Tagline Set
+DIR /
+ADDED 1700000000 glftpd
+RATIO 3 -1
+CREDITS 15000
+ALLUP 4 2048 12 1 10 2
+ALLDN 0 0 0
+TIME 5 923341886 0 0
+NUKE 0 1 700
+IP *@127.0.0.1
+GROUP siteops 1
+GROUP users
+";
+
+    #[test]
+    fn test_parse_userfile() {
+        let user = UserFile::parse("alice", USERFILE);
+        assert_eq!(user.general.max_download_speed, 120);
+        assert_eq!(user.general.idle_time, -1);
+        assert_eq!(user.logins.max_logins, 2);
+        assert_eq!(user.login_limits().max_sim_uploads, None);
+        assert_eq!(user.tagline, "No Tagline Set");
+        assert_eq!(user.ratio, vec![3, -1]);
+        assert_eq!(user.credits, vec![15000]);
+        assert_eq!(user.credits_bytes(), 15000 * 1024);
+        assert_eq!(user.allup.len(), 2);
+        assert_eq!(user.allup[0].kilobytes, 2048);
+        assert_eq!(user.time.last_on, 923341886);
+        assert_eq!(user.nuke.megabytes_nuked, 700);
+        assert_eq!(user.ips, vec!["*@127.0.0.1"]);
+        assert!(user.groups[0].gadmin && !user.groups[1].gadmin);
+        assert_eq!(user.get("ADDED"), Some("1700000000 glftpd"));
+        assert!(user.has_flag(SITEOP));
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let mut user = UserFile::parse("alice", USERFILE);
+        assert!(user.add_flag(DELETED));
+        assert!(!user.add_flag(DELETED));
+        assert_eq!(user.flags, "136");
+
+        assert_eq!(user.add_credits(-20000 * 1024), 0);
+        assert_eq!(user.add_credits(3 * 2048), 6 * 1024);
+        assert_eq!(user.add_credits(-1), 5 * 1024);
+        assert_eq!(user.add_credits(1023), 5 * 1024);
+
+        let reparsed = UserFile::parse("alice", &user.serialize());
+        assert_eq!(reparsed, user);
+    }
+}
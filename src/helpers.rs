@@ -10,6 +10,7 @@ use std::collections::HashMap;
 use std::fs;
 use std::io::Result as IoResult;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
@@ -92,6 +93,56 @@ pub fn log_config(config: &Config) {
         "  Download Buffer Size: {} KB",
         config.server.download_buffer_size.unwrap_or(256 * 1024) / 1024
     );
+    match config.server.idle_timeout_duration() {
+        Some(timeout) => info!("  Idle Timeout: {} seconds", timeout.as_secs()),
+        None => info!("  Idle Timeout: disabled"),
+    }
+    info!(
+        "  PASV Accept Timeout: {} seconds",
+        config.server.pasv_accept_timeout_duration().as_secs()
+    );
+    info!(
+        "  Data Timeout: {} seconds",
+        config.server.data_timeout_duration().as_secs()
+    );
+    info!(
+        "  Shutdown Grace Period: {} seconds",
+        config.server.shutdown_grace_period_duration().as_secs()
+    );
+}
+
+/// Counter making the temporary files of `write_file_atomic` unique.
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `content` to `path` atomically.
+///
+/// The data is written to a temporary file next to the target, synced, then
+/// renamed over it, so readers never see a half written file. Each call uses
+/// its own temporary file, so concurrent writers never mix their data; the
+/// last rename wins.
+pub async fn write_file_atomic(path: &Path, content: &[u8]) -> IoResult<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name"))?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&tmp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+    }
+    result
 }
 
 pub fn load_banner(path: &str) -> Result<String> {
@@ -222,6 +273,9 @@ pub async fn generate_and_send_statline(
     let session_lock = session.lock().await;
     let current_dir = session_lock.current_dir.clone();
     let path = PathBuf::from(current_dir);
+    let (credits, credits_unit) = session_lock.credits();
+    let credits = format!("{:.1}{}", credits, credits_unit);
+    let ratio = session_lock.ratio().to_string();
     drop(session_lock);
 
     match get_site_free_space(&path) {
@@ -236,8 +290,8 @@ pub async fn generate_and_send_statline(
     }
 
     replacements.insert("%[%s]b", "DEFAULT".to_string());
-    replacements.insert("%[%.1f]Ic%[%s]Y", format!("{:.1}MB", 14.6));
-    replacements.insert("%[%s]Ir", "Unlimited".to_string());
+    replacements.insert("%[%.1f]Ic%[%s]Y", credits);
+    replacements.insert("%[%s]Ir", ratio);
 
     let statline = cleanup_cookie_statline(&statline_template, &replacements);
 
@@ -9,10 +9,12 @@ mod core_log;
 mod core_network;
 mod core_quota;
 mod core_tls;
+mod core_users;
 mod helpers;
 mod ipc;
 mod server;
 mod session;
+mod state;
 mod users;
 mod watchdog;
 
@@ -93,7 +95,7 @@ async fn main() -> Result<()> {
 
     //watchdog::start_watchdog(ipc.clone(), args.verbose); // Pass the verbose flag to the watchdog
 
-    server::run(config, ipc).await?;
+    server::run(config, config_path.to_string(), ipc).await?;
 
     Ok(())
 }
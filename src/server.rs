@@ -1,16 +1,23 @@
+use crate::config::QuotaConfig;
+use crate::constants::{DEFAULT_GRACE_PERIOD, OWNER_FLUSH_INTERVAL};
 use crate::core_network::network;
 use crate::core_quota::config::{
     GroupQuotaConfig, QuotaConfig as CoreQuotaConfig, UserQuotaConfig,
 };
 use crate::core_quota::manager::QuotaManager;
+use crate::core_users::UserStore;
 use crate::helpers::log_config;
 use crate::ipc::Ipc;
 use crate::session::Session;
+use crate::state::ServerState;
 use crate::Config;
 use anyhow::Result;
 use log::{error, info};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
 
 /// Runs the FTP server with the provided configuration and IPC key.
 ///
@@ -20,12 +27,13 @@ use std::sync::Arc;
 /// # Arguments
 ///
 /// * `config` - The server configuration.
+/// * `config_path` - The configuration file, re-read on SIGHUP and SITE REHASH.
 /// * `ipc` - The IPC instance for inter-process communication.
 ///
 /// # Returns
 ///
 /// Result<(), anyhow::Error> indicating the success or failure of the operation.
-pub async fn run(config: Config, ipc: Arc<Ipc>) -> Result<()> {
+pub async fn run(config: Config, config_path: String, ipc: Arc<Ipc>) -> Result<()> {
     // Log each configuration option on a new line
     info!("Starting server with the following configuration:");
     log_config(&config);
@@ -36,35 +44,15 @@ pub async fn run(config: Config, ipc: Arc<Ipc>) -> Result<()> {
     // Initialize quota manager if configured
     let quota_manager = if let Some(quota_config) = &config.quota {
         info!("Initializing quota system");
-        let core_quota_config = CoreQuotaConfig {
-            default_quota: quota_config.default_quota.unwrap_or(10737418240),
-            default_ratio: quota_config
-                .default_ratio
-                .clone()
-                .unwrap_or_else(|| "1:1".to_string()),
-            quota_storage_file: quota_config
-                .quota_storage_file
-                .clone()
-                .unwrap_or_else(|| PathBuf::from("data/quotas.json")),
-            ratio_storage_file: quota_config
-                .ratio_storage_file
-                .clone()
-                .unwrap_or_else(|| PathBuf::from("data/ratios.json")),
-            stats_storage_file: quota_config
-                .stats_storage_file
-                .clone()
-                .unwrap_or_else(|| PathBuf::from("data/transfer_stats.json")),
-            enable_quota: quota_config.enable_quota.unwrap_or(true),
-            enable_ratio: quota_config.enable_ratio.unwrap_or(true),
-        };
+        let core_quota_config = core_quota_config(quota_config);
 
-        let group_config = GroupQuotaConfig::new();
+        let group_config = core_group_quota_config(quota_config);
         let user_config = UserQuotaConfig::new();
 
         let manager = QuotaManager::new(core_quota_config, group_config, user_config);
 
         // Load existing quota data
-        if let Err(e) = manager.load().await {
+        if let Err(e) = manager.load(&UserStore::from_config(&config)).await {
             error!("Failed to load quota data: {}", e);
         }
 
@@ -74,16 +62,74 @@ pub async fn run(config: Config, ipc: Arc<Ipc>) -> Result<()> {
         None
     };
 
+    // Request a shutdown on SIGTERM/SIGINT
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        if let Err(e) = wait_for_shutdown_signal().await {
+            error!("Failed to listen for shutdown signals: {}", e);
+            return;
+        }
+        info!("Shutdown signal received, no longer accepting connections.");
+        shutdown_tx.send(true).ok();
+    });
+
+    let listen_port = config.server.listen_port;
+    let state = Arc::new(ServerState::new(config, config_path, quota_manager.clone()));
+    state.bans().load().await;
+    state.owners().load().await;
+    if let Err(e) = state.groups().load() {
+        error!("Failed to load the groups: {}", e);
+    }
+    state.refresh_quota_settings().await;
+    let config = state.config();
+    state
+        .dir_quotas()
+        .load(&config.site_root(), config.dir_quotas())
+        .await;
+
+    // Reload the configuration on SIGHUP
+    let reload_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while sighup.recv().await.is_some() {
+            info!("Received SIGHUP");
+            if let Err(e) = reload_state.reload().await {
+                error!("Configuration reload failed, keeping the current one: {:#}", e);
+            }
+        }
+    });
+
+    // Write the file owners periodically rather than on every change
+    let flush_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(OWNER_FLUSH_INTERVAL));
+        loop {
+            interval.tick().await;
+            flush_state.owners().flush().await;
+        }
+    });
+
     // Start the FTP server
-    match network::start_server(
-        config.server.listen_port,
-        Arc::new(config),
-        ipc,
-        quota_manager,
-    )
-    .await
-    {
-        Ok(_) => info!("Server started successfully."),
+    let owners_state = Arc::clone(&state);
+    let result = network::start_server(listen_port, state, ipc, shutdown_rx).await;
+
+    // Flush dirty state, whether the server stopped cleanly or not
+    owners_state.owners().flush().await;
+    if let Some(quota_manager) = &quota_manager {
+        info!("Flushing quota data to disk");
+        if let Err(e) = quota_manager.flush().await {
+            error!("Failed to save quota data: {}", e);
+        }
+    }
+
+    match result {
+        Ok(_) => info!("Server stopped."),
         Err(e) => {
             error!("Failed to start server: {}", e);
             return Err(e);
@@ -93,6 +139,64 @@ pub async fn run(config: Config, ipc: Arc<Ipc>) -> Result<()> {
     Ok(())
 }
 
+/// Waits until the process receives SIGTERM or SIGINT.
+async fn wait_for_shutdown_signal() -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("Received SIGINT");
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the quota manager configuration from the `[quota]` section, filling in defaults.
+pub fn core_quota_config(quota_config: &QuotaConfig) -> CoreQuotaConfig {
+    CoreQuotaConfig {
+        default_quota: quota_config.default_quota.unwrap_or(10737418240),
+        default_ratio: quota_config
+            .default_ratio
+            .clone()
+            .unwrap_or_else(|| "1:1".to_string()),
+        quota_storage_file: quota_config
+            .quota_storage_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("data/quotas.json")),
+        ratio_storage_file: quota_config
+            .ratio_storage_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("data/ratios.json")),
+        stats_storage_file: quota_config
+            .stats_storage_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("data/transfer_stats.json")),
+        enable_quota: quota_config.enable_quota.unwrap_or(true),
+        enable_ratio: quota_config.enable_ratio.unwrap_or(true),
+        default_soft_quota: quota_config.default_soft_quota.unwrap_or(0),
+        grace_period: quota_config.grace_period.unwrap_or(DEFAULT_GRACE_PERIOD),
+    }
+}
+
+/// Builds the group quotas and ratios of the `[quota]` section. Primary groups
+/// come from the userfiles, see `ServerState::refresh_quota_settings`.
+pub fn core_group_quota_config(quota_config: &QuotaConfig) -> GroupQuotaConfig {
+    let mut group_config = GroupQuotaConfig::new();
+    for (group, quota) in &quota_config.group_quotas {
+        group_config.set_group_quota(group, *quota);
+    }
+    for (group, ratio) in &quota_config.group_ratios {
+        group_config.set_group_ratio(group, ratio);
+    }
+    for (group, quota) in &quota_config.group_total_quotas {
+        group_config.set_group_total_quota(group, *quota);
+    }
+    group_config
+}
+
 pub fn initialize_session(config: &Config) -> Session {
     let base_path = PathBuf::from(&config.server.chroot_dir)
         .join(config.server.min_homedir.trim_start_matches('/'))
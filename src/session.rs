@@ -1,11 +1,19 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Component, Path, PathBuf};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
 use sysinfo::{DiskExt, System, SystemExt};
 
+use crate::constants::{EXEMPT, IDLER};
+use crate::config::LimitsConfig;
+use crate::core_quota::manager::UploadReservation;
+use crate::Config;
+
 /// DEBUG - REMOVE ME ///
 ///
 use rand::Rng;
@@ -20,6 +28,24 @@ pub struct Session {
     pub base_path: PathBuf,       // chroot_dir + min_dir
     pub username: Option<String>, // Username for the session
     pub is_authenticated: bool,   // Indicates if the user is authenticated
+    pub flags: Vec<u8>,           // User flags loaded from the userfile at login
+    pub last_activity: Instant,   // When the last command was received
+    pub last_idle: Duration,      // Time spent idle before the last command
+    pub idle_timeout: Option<u64>, // Per-session idle timeout override (SITE IDLE), 0 = none
+    pub id: u64,                  // Id of the session in the SessionManager
+    pub is_anonymous: bool,       // Logged in as an anonymous user
+    pub close_requested: bool,    // Close the control connection after the current command
+    pub login_limits: LoginLimits, // LOGINS line of the userfile
+    pub max_download_speed: Option<u64>, // Per-user download speed limit in KB/s (GENERAL)
+    pub max_upload_speed: Option<u64>, // Per-user upload speed limit in KB/s (GENERAL)
+    pub groups: Vec<String>,      // Groups of the user, from the userfile
+    pub peer_addr: Option<SocketAddr>, // Address of the client
+    pub ident: Option<String>,         // User id reported by the client's identd
+    pub must_change_password: bool,    // Only SITE PASSWD is allowed until the password is changed
+    pub allocated: Option<u64>,        // Size announced with ALLO for the next upload
+    pub allocation: Option<UploadReservation>, // Quota reserved by ALLO for the next upload
+    pub credits: u64,                  // Credits of the user in bytes, from the userfile
+    pub ratio: String,                 // Ratio of the user as shown in the statline
 }
 
 impl Session {
@@ -33,7 +59,82 @@ impl Session {
             byte_size: None,        // Default byte size is None
             username: None,
             is_authenticated: false, // Initialize as FALSE
+            flags: Vec::new(),
+            last_activity: Instant::now(),
+            last_idle: Duration::ZERO,
+            idle_timeout: None,
+            id: 0,
+            is_anonymous: false,
+            close_requested: false,
+            login_limits: LoginLimits::default(),
+            max_download_speed: None,
+            max_upload_speed: None,
+            groups: Vec::new(),
+            peer_addr: None,
+            ident: None,
+            must_change_password: false,
+            allocated: None,
+            allocation: None,
+            credits: 0,
+            ratio: "Unlimited".to_string(),
+        }
+    }
+
+    /// Records activity on the control connection, resetting the idle timer.
+    pub fn touch(&mut self) {
+        self.last_idle = self.last_activity.elapsed();
+        self.last_activity = Instant::now();
+    }
+
+    /// Returns `true` if the logged in user has the given flag.
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Returns `true` if the user is allowed to idle forever (IDLER or EXEMPT flag).
+    pub fn is_idle_exempt(&self) -> bool {
+        self.has_flag(IDLER) || self.has_flag(EXEMPT)
+    }
+
+    /// Returns the idle timeout that applies to this session, or `None` if the
+    /// session may idle forever.
+    pub fn effective_idle_timeout(&self, config: &Config) -> Option<Duration> {
+        if self.is_idle_exempt() {
+            return None;
+        }
+
+        match self.idle_timeout {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => config.server.idle_timeout_duration(),
+        }
+    }
+
+    /// Resolves the path argument of a command to its location in the site, e.g.
+    /// `/incoming/file`. Relative paths start at the current directory and `..`
+    /// never goes above the root.
+    pub fn site_path(&self, arg: &str) -> PathBuf {
+        let mut path = PathBuf::from("/");
+        if !arg.starts_with('/') {
+            path.push(self.current_dir.trim_start_matches('/'));
+        }
+        for component in Path::new(arg).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::ParentDir => {
+                    path.pop();
+                }
+                _ => {}
+            }
         }
+        path
+    }
+
+    /// Returns where the path argument of a command lives on disk.
+    pub fn real_path(&self, arg: &str) -> PathBuf {
+        let site_path = self.site_path(arg);
+        self.base_path
+            .join(site_path.strip_prefix("/").unwrap_or(&site_path))
     }
 
     // Returns the average transfer rate.
@@ -79,12 +180,19 @@ impl Session {
         "DEFAULT" // Example value, replace with actual logic
     }
 
+    /// Returns the credits of the user in MB, or in GB past 1024 MB.
     pub fn credits(&self) -> (f64, &str) {
-        (14.6, "MB") // Example values, replace with actual logic
+        let credits_mb = self.credits as f64 / 1_048_576.0;
+        if credits_mb >= 1024.0 {
+            (credits_mb / 1024.0, "GB")
+        } else {
+            (credits_mb, "MB")
+        }
     }
 
+    /// Returns the ratio of the user, e.g. `1:3`, or `Unlimited` for leeches.
     pub fn ratio(&self) -> &str {
-        "Unlimited" // Example value, replace with actual logic
+        &self.ratio
     }
 
     pub fn get_disk_info(&self) -> Vec<(String, u64, u64)> {
@@ -101,32 +209,293 @@ impl Session {
     }
 }
 
+/// Login limit reached by a session trying to log in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginLimit {
+    SiteFull,
+    AnonymousFull,
+    TooManyLogins(usize),
+    TooManyFromIp(usize),
+}
+
+impl LoginLimit {
+    /// Returns the reply for this limit: the configured 421 message for the
+    /// server wide limits, a 530 for the per-account ones.
+    pub fn to_ftp_response(self, limits: &LimitsConfig) -> String {
+        match self {
+            LoginLimit::SiteFull => format!("421 {}\r\n", limits.site_full_message()),
+            LoginLimit::AnonymousFull => {
+                format!("421 {}\r\n", limits.anonymous_full_message())
+            }
+            LoginLimit::TooManyLogins(max) => format!(
+                "530 Sorry, your account is restricted to {} simultaneous login(s).\r\n",
+                max
+            ),
+            LoginLimit::TooManyFromIp(max) => format!(
+                "530 Sorry, your account is restricted to {} login(s) from the same IP.\r\n",
+                max
+            ),
+        }
+    }
+
+    /// Returns `true` if the control connection must be closed after the reply.
+    pub fn closes_connection(self) -> bool {
+        matches!(self, LoginLimit::SiteFull | LoginLimit::AnonymousFull)
+    }
+}
+
+/// Per-account limits from the LOGINS line of a userfile:
+/// `LOGINS <max logins> <max logins from same IP> <max sim downloads> <max sim uploads>`.
+///
+/// A value of 0 or less means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoginLimits {
+    pub max_logins: Option<usize>,
+    pub max_logins_per_ip: Option<usize>,
+    pub max_sim_downloads: Option<usize>,
+    pub max_sim_uploads: Option<usize>,
+}
+
+impl LoginLimits {
+    /// Parses the values following the LOGINS key.
+    pub fn from_values(values: &[String]) -> Self {
+        let limit = |index: usize| {
+            values
+                .get(index)
+                .and_then(|value| value.parse::<i64>().ok())
+                .filter(|value| *value > 0)
+                .map(|value| value as usize)
+        };
+
+        Self {
+            max_logins: limit(0),
+            max_logins_per_ip: limit(1),
+            max_sim_downloads: limit(2),
+            max_sim_uploads: limit(3),
+        }
+    }
+
+    /// Formats a limit for display, e.g. in SITE USER.
+    pub fn display(limit: Option<usize>) -> String {
+        limit.map_or_else(|| "Unlimited".to_string(), |max| max.to_string())
+    }
+}
+
+/// Public information about a connected session, as tracked by the `SessionManager`.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer_addr: SocketAddr,
+    pub ident: Option<String>,
+    pub username: Option<String>,
+    pub logged_in: bool,
+    pub anonymous: bool,
+    pub current_dir: String,
+    pub downloads: usize,
+    pub uploads: usize,
+}
+
+/// Registry of the live connections, used to enforce connection limits.
+///
+/// Entries are kept separately from the `Session` itself so that the registry can
+/// be queried without locking every session.
+#[derive(Default)]
 pub struct SessionManager {
-    sessions: HashMap<String, Session>,
-    system: System,
+    sessions: StdMutex<HashMap<u64, SessionInfo>>,
+    next_id: AtomicU64,
 }
 
 impl SessionManager {
     pub fn new() -> Self {
-        let system = System::new_all(); // Initialize with all available system information
-        SessionManager {
-            sessions: HashMap::new(),
-            system,
+        Self::default()
+    }
+
+    /// Registers a new connection and returns its id, or `None` if the source IP
+    /// already has `max_per_ip` connections open.
+    pub fn register(&self, peer_addr: SocketAddr, max_per_ip: Option<usize>) -> Option<u64> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        if let Some(max_per_ip) = max_per_ip {
+            let from_ip = sessions
+                .values()
+                .filter(|info| info.peer_addr.ip() == peer_addr.ip())
+                .count();
+            if from_ip >= max_per_ip {
+                return None;
+            }
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let info = SessionInfo {
+            id,
+            peer_addr,
+            ident: None,
+            username: None,
+            logged_in: false,
+            anonymous: false,
+            current_dir: String::from("/"),
+            downloads: 0,
+            uploads: 0,
+        };
+        sessions.insert(id, info);
+        Some(id)
+    }
+
+    /// Removes a closed connection from the registry.
+    pub fn unregister(&self, id: u64) {
+        self.sessions.lock().unwrap().remove(&id);
+    }
+
+    /// Records the ident reply of a connection.
+    pub fn set_ident(&self, id: u64, ident: Option<String>) {
+        if let Some(info) = self.sessions.lock().unwrap().get_mut(&id) {
+            info.ident = ident;
         }
     }
 
-    pub fn log_disk_info(&self) {
-        for disk in self.system.disks() {
-            println!("Disk {:?}: {:?}", disk.kind(), disk.name());
+    /// Records the directory a connection moved to.
+    pub fn set_current_dir(&self, id: u64, current_dir: &str) {
+        if let Some(info) = self.sessions.lock().unwrap().get_mut(&id) {
+            info.current_dir = current_dir.to_string();
         }
     }
-    pub fn get_session(&self, id: &str) -> Option<&Session> {
-        self.sessions.get(id)
+
+    /// Returns the connected sessions, oldest first.
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+        sessions.sort_by_key(|info| info.id);
+        sessions
+    }
+
+    /// Marks a connection as logged in, unless the login limits are reached.
+    ///
+    /// EXEMPT users may log in when the site is full, anonymous users are also
+    /// subject to the anonymous user limit. The per-account limits come from the
+    /// user's LOGINS line.
+    pub fn log_in(
+        &self,
+        id: u64,
+        username: &str,
+        anonymous: bool,
+        exempt: bool,
+        config: &Config,
+        account_limits: &LoginLimits,
+    ) -> Result<(), LoginLimit> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let peer_ip = match sessions.get(&id) {
+            Some(info) => info.peer_addr.ip(),
+            None => return Ok(()),
+        };
+        let others: Vec<&SessionInfo> = sessions
+            .values()
+            .filter(|info| info.logged_in && info.id != id)
+            .collect();
+        let users = others.len();
+        let anonymous_users = others.iter().filter(|info| info.anonymous).count();
+        let account_logins = others
+            .iter()
+            .filter(|info| info.username.as_deref() == Some(username));
+        let (logins, logins_from_ip) = account_logins.fold((0, 0), |(all, same_ip), info| {
+            (all + 1, same_ip + usize::from(info.peer_addr.ip() == peer_ip))
+        });
+
+        if let Some(max_logins) = account_limits.max_logins {
+            if logins >= max_logins {
+                return Err(LoginLimit::TooManyLogins(max_logins));
+            }
+        }
+        if let Some(max_logins_per_ip) = account_limits.max_logins_per_ip {
+            if logins_from_ip >= max_logins_per_ip {
+                return Err(LoginLimit::TooManyFromIp(max_logins_per_ip));
+            }
+        }
+
+        if let Some(max_users) = config.limits.max_users {
+            if users >= max_users && !exempt {
+                return Err(LoginLimit::SiteFull);
+            }
+        }
+        if let Some(max_anonymous_users) = config.max_anonymous_users() {
+            if anonymous && anonymous_users >= max_anonymous_users {
+                return Err(LoginLimit::AnonymousFull);
+            }
+        }
+
+        if let Some(info) = sessions.get_mut(&id) {
+            info.username = Some(username.to_string());
+            info.logged_in = true;
+            info.anonymous = anonymous;
+        }
+        Ok(())
     }
 
-    pub fn create_session(&mut self, id: String, base_path: PathBuf) -> &mut Session {
+    /// Returns the number of sessions currently logged in as `username`.
+    pub fn logins_of(&self, username: &str) -> usize {
         self.sessions
-            .entry(id.clone())
-            .or_insert_with(|| Session::new(base_path))
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|info| info.logged_in && info.username.as_deref() == Some(username))
+            .count()
+    }
+
+    /// Records the start of a transfer for the user of session `id`, unless the
+    /// user already runs `max` simultaneous transfers in that direction.
+    ///
+    /// EXEMPT users are not limited, as for logins. The returned guard ends the
+    /// transfer when dropped.
+    pub fn start_transfer(
+        self: &Arc<Self>,
+        id: u64,
+        upload: bool,
+        exempt: bool,
+        max: Option<usize>,
+    ) -> Option<TransferGuard> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let username = sessions.get(&id)?.username.clone();
+
+        if let Some(max) = max.filter(|_| !exempt) {
+            let running: usize = sessions
+                .values()
+                .filter(|info| info.username == username)
+                .map(|info| if upload { info.uploads } else { info.downloads })
+                .sum();
+            if running >= max {
+                return None;
+            }
+        }
+
+        let info = sessions.get_mut(&id)?;
+        if upload {
+            info.uploads += 1;
+        } else {
+            info.downloads += 1;
+        }
+
+        Some(TransferGuard {
+            sessions: Arc::clone(self),
+            id,
+            upload,
+        })
+    }
+}
+
+/// Keeps a transfer counted in the `SessionManager` until dropped.
+pub struct TransferGuard {
+    sessions: Arc<SessionManager>,
+    id: u64,
+    upload: bool,
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        if let Some(info) = self.sessions.sessions.lock().unwrap().get_mut(&self.id) {
+            if self.upload {
+                info.uploads = info.uploads.saturating_sub(1);
+            } else {
+                info.downloads = info.downloads.saturating_sub(1);
+            }
+        }
     }
 }
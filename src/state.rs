@@ -0,0 +1,323 @@
+use crate::core_auth::ban::BanManager;
+use crate::core_auth::AuthChain;
+use crate::core_groups::GroupManager;
+use crate::core_network::throttle::TokenBucket;
+use crate::core_quota::config::QuotaConfig;
+use crate::core_quota::dir_quota::DirQuotas;
+use crate::core_quota::error::QuotaError;
+use crate::core_quota::manager::QuotaManager;
+use crate::core_quota::ratio::UserRatio;
+use crate::core_quota::user_file_parser::parse_user_file;
+use crate::core_users::error::UserError;
+use crate::core_users::{FileOwners, UserStore};
+use crate::helpers::load_config;
+use crate::server::{core_group_quota_config, core_quota_config};
+use crate::session::SessionManager;
+use crate::Config;
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use std::sync::Arc;
+
+/// State shared by every connection of the server.
+///
+/// The configuration lives behind an `ArcSwap` so it can be replaced at runtime
+/// (SIGHUP, SITE REHASH). Connections take a snapshot when they are accepted and
+/// keep using it until they close, new connections get the latest configuration.
+pub struct ServerState {
+    config: ArcSwap<Config>,
+    config_path: String,
+    quota_manager: Option<Arc<QuotaManager>>,
+    sessions: Arc<SessionManager>,
+    download_bucket: Arc<TokenBucket>,
+    upload_bucket: Arc<TokenBucket>,
+    bans: BanManager,
+    auth: ArcSwap<AuthChain>,
+    users: ArcSwap<UserStore>,
+    owners: FileOwners,
+    groups: GroupManager,
+    dir_quotas: DirQuotas,
+}
+
+impl ServerState {
+    pub fn new(
+        config: Config,
+        config_path: String,
+        quota_manager: Option<Arc<QuotaManager>>,
+    ) -> Self {
+        let download_bucket = Arc::new(TokenBucket::new(config.global_speed_limit(false)));
+        let upload_bucket = Arc::new(TokenBucket::new(config.global_speed_limit(true)));
+
+        let bans = BanManager::new(config.bans.ban_file());
+        let auth = ArcSwap::from_pointee(AuthChain::from_config(&config));
+        let users = ArcSwap::from_pointee(UserStore::from_config(&config));
+        let owners = FileOwners::new(config.acl.owner_file());
+        let groups = GroupManager::new(config.groups.storage_file());
+        let dir_quotas = DirQuotas::new(config.dir_quota_file());
+
+        Self {
+            auth,
+            users,
+            owners,
+            groups,
+            dir_quotas,
+            bans,
+            download_bucket,
+            upload_bucket,
+            config: ArcSwap::from_pointee(config),
+            config_path,
+            quota_manager,
+            sessions: Arc::new(SessionManager::new()),
+        }
+    }
+
+    /// Returns the current configuration.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// Returns the quota manager, if the quota system is enabled.
+    pub fn quota_manager(&self) -> Option<Arc<QuotaManager>> {
+        self.quota_manager.clone()
+    }
+
+    /// Returns the registry of connected sessions.
+    pub fn sessions(&self) -> &Arc<SessionManager> {
+        &self.sessions
+    }
+
+    /// Returns the failed login and IP ban tracker.
+    pub fn bans(&self) -> &BanManager {
+        &self.bans
+    }
+
+    /// Returns the chain of authentication backends.
+    pub fn auth(&self) -> Arc<AuthChain> {
+        self.auth.load_full()
+    }
+
+    /// Returns the userfiles.
+    pub fn users(&self) -> Arc<UserStore> {
+        self.users.load_full()
+    }
+
+    /// Gives credits to a user, or takes them when `bytes` is negative.
+    ///
+    /// # Returns
+    ///
+    /// The new credits of the user in bytes, or `None` when they could not be
+    /// updated, e.g. for users without a userfile.
+    pub async fn add_credits(&self, username: &str, bytes: i64) -> Option<u64> {
+        match self
+            .users()
+            .update(username, |user| user.add_credits(bytes))
+            .await
+        {
+            Ok(credits) => Some(credits),
+            Err(e) => {
+                warn!("Failed to update the credits of {}: {}", username, e);
+                None
+            }
+        }
+    }
+
+    /// Takes the credits of a download of `bytes` before it starts, so that
+    /// parallel downloads cannot spend the same credits.
+    ///
+    /// # Returns
+    ///
+    /// The credits taken in bytes, what is not sent is given back with
+    /// [`Self::add_credits`]. 0 for leeches and users without a userfile, an
+    /// error when the credits do not cover the download.
+    pub async fn take_download_credits(
+        &self,
+        username: &str,
+        bytes: u64,
+    ) -> Result<u64, QuotaError> {
+        let Some(ratio) = self.user_ratio(username).await else {
+            return Ok(0);
+        };
+        if ratio.is_unlimited {
+            return Ok(0);
+        }
+        let users = self.users();
+        let taken = users.update(username, |user| {
+            ratio.check_download(user.credits_bytes(), bytes)?;
+            user.add_credits(-i64::try_from(bytes).unwrap_or(i64::MAX));
+            Ok(bytes.div_ceil(1024) * 1024)
+        });
+        match taken.await {
+            Ok(taken) => taken,
+            Err(UserError::NotFound(_)) => Ok(0),
+            Err(e) => {
+                warn!("Failed to update the credits of {}: {}", username, e);
+                Ok(0)
+            }
+        }
+    }
+
+    /// Returns the ratio of a user, `None` when ratios are disabled.
+    ///
+    /// With a `[quota]` section the quota manager resolves it, otherwise it comes
+    /// from the RATIO line of the userfile, or the default ratio for `RATIO -1`.
+    pub async fn user_ratio(&self, username: &str) -> Option<UserRatio> {
+        let result = match &self.quota_manager {
+            Some(quota_manager) => quota_manager.user_ratio(username).await,
+            None => match self.users().get(username).await {
+                Ok(Some(user)) => parse_user_file(&user).and_then(|(_, ratio)| {
+                    let ratio = ratio.unwrap_or_else(|| QuotaConfig::default().default_ratio);
+                    UserRatio::new(username, &ratio).map(Some)
+                }),
+                _ => Ok(None),
+            },
+        };
+        result.unwrap_or_else(|e| {
+            warn!("Failed to get the ratio of {}: {}", username, e);
+            None
+        })
+    }
+
+    /// Returns the ratio of a user for display: "1:3" or "Unlimited".
+    pub async fn ratio_display(&self, username: &str) -> String {
+        self.user_ratio(username).await.map_or_else(
+            || "Unlimited".to_string(),
+            |ratio| ratio.configured_ratio_string(),
+        )
+    }
+
+    /// Returns the owners of the uploaded files and created directories.
+    pub fn owners(&self) -> &FileOwners {
+        &self.owners
+    }
+
+    /// Returns the groups of the site.
+    pub fn groups(&self) -> &GroupManager {
+        &self.groups
+    }
+
+    /// Returns the directory quotas and their usage.
+    pub fn dir_quotas(&self) -> &DirQuotas {
+        &self.dir_quotas
+    }
+
+    /// Returns the server-wide bandwidth bucket shared by all transfers in one direction.
+    pub fn bandwidth_bucket(&self, upload: bool) -> Arc<TokenBucket> {
+        if upload {
+            Arc::clone(&self.upload_bucket)
+        } else {
+            Arc::clone(&self.download_bucket)
+        }
+    }
+
+    /// Rebuilds the group quotas and ratios and the user quotas and ratios of
+    /// the quota manager.
+    ///
+    /// Groups of groups.json override those of the `[quota]` section. The
+    /// primary group of a user is the first group of their userfile, or the
+    /// first group listing them in groups.json. Called at startup and whenever
+    /// groups or userfiles change.
+    pub async fn refresh_quota_settings(&self) {
+        let (Some(quota_manager), Some(quota_config)) = (&self.quota_manager, &self.config().quota)
+        else {
+            return;
+        };
+
+        let mut group_config = core_group_quota_config(quota_config);
+        let groups = self.groups.list_groups();
+        for group in &groups {
+            group_config.set_group_quota(&group.name, group.quota);
+            group_config.set_group_ratio(&group.name, &group.ratio);
+            group_config.set_group_total_quota(&group.name, group.total_quota);
+        }
+
+        let users = self.users();
+        match users.usernames().await {
+            Ok(usernames) => {
+                for username in usernames {
+                    let from_userfile = match users.get(&username).await {
+                        Ok(Some(user)) => user.groups.first().map(|g| g.name.clone()),
+                        _ => None,
+                    };
+                    let primary = from_userfile.or_else(|| {
+                        groups
+                            .iter()
+                            .find(|group| group.has_user(&username))
+                            .map(|group| group.name.clone())
+                    });
+                    if let Some(primary) = primary {
+                        group_config.set_primary_group(&username, &primary);
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to list the users for the quotas: {}", e),
+        }
+
+        quota_manager.set_group_config(group_config).await;
+        quota_manager.load_user_configs(&users).await;
+    }
+
+    /// Re-reads and validates the configuration file, then swaps it in.
+    ///
+    /// The running configuration is left untouched if the new one cannot be
+    /// loaded or is invalid.
+    ///
+    /// # Returns
+    ///
+    /// The newly active configuration.
+    pub async fn reload(&self) -> Result<Arc<Config>> {
+        info!("Reloading configuration from {}", self.config_path);
+
+        let new_config = load_config(&self.config_path)?;
+        new_config.validate()?;
+
+        let old_config = self.config();
+        if new_config.server.listen_port != old_config.server.listen_port {
+            warn!("listen_port changed, the new port is only used after a restart");
+        }
+        if new_config.server.ipc_key != old_config.server.ipc_key {
+            warn!("ipc_key changed, the new key is only used after a restart");
+        }
+
+        match (&self.quota_manager, &new_config.quota) {
+            (Some(quota_manager), Some(quota_config)) => {
+                quota_manager.update_config(core_quota_config(quota_config));
+            }
+            (None, Some(_)) => warn!("Quota system can only be enabled with a restart"),
+            (Some(_), None) => warn!("Quota system can only be disabled with a restart"),
+            (None, None) => {}
+        }
+
+        self.download_bucket
+            .set_rate(new_config.global_speed_limit(false));
+        self.upload_bucket
+            .set_rate(new_config.global_speed_limit(true));
+
+        self.auth
+            .store(Arc::new(AuthChain::from_config(&new_config)));
+        if new_config.server.chroot_dir != old_config.server.chroot_dir {
+            self.users
+                .store(Arc::new(UserStore::from_config(&new_config)));
+        }
+
+        if new_config.groups.storage_file() != old_config.groups.storage_file() {
+            warn!("groups.storage_file changed, the new file is only used after a restart");
+        }
+        if let Err(e) = self.groups.load() {
+            warn!("Failed to reload the groups: {}", e);
+        }
+
+        if new_config.dir_quota_file() != old_config.dir_quota_file() {
+            warn!("dir_quota_storage_file changed, the new file is only used after a restart");
+        }
+        self.dir_quotas
+            .load(&new_config.site_root(), new_config.dir_quotas())
+            .await;
+
+        let new_config = Arc::new(new_config);
+        self.config.store(Arc::clone(&new_config));
+        self.refresh_quota_settings().await;
+        info!("Configuration reloaded.");
+
+        Ok(new_config)
+    }
+}